@@ -0,0 +1,77 @@
+#!/usr/bin/env python3
+# Generates src/xid.rs, the XID_Start and XID_Continue tables of UAX #31
+# that the lexer checks identifiers against. std has no such tables and the
+# compiler takes no dependencies, so they are derived from Python's
+# unicodedata, whose str.isidentifier is exactly (XID_Start | _) XID_Continue*.
+#
+# Run from the compiler directory: python3 scripts/xid_tables.py
+
+import sys
+import unicodedata
+
+
+def ranges(predicate):
+    """Maximal ranges of code points satisfying the predicate."""
+    result = []
+    start = None
+    for cp in range(sys.maxunicode + 2):
+        inside = cp <= sys.maxunicode and predicate(chr(cp))
+        if inside and start is None:
+            start = cp
+        elif not inside and start is not None:
+            result.append((start, cp - 1))
+            start = None
+    return result
+
+
+def table(name, doc, rs):
+    lines = [f"/// {doc}", "#[rustfmt::skip]", f"const {name}: &[(char, char)] = &["]
+    for lo, hi in rs:
+        lines.append(f"    ('\\u{{{lo:X}}}', '\\u{{{hi:X}}}'),")
+    lines.append("];")
+    return "\n".join(lines)
+
+
+def main():
+    # A surrogate is never an identifier, and '_' alone is one without being
+    # XID_Start
+    start = ranges(lambda c: c != "_" and c.isidentifier())
+    cont = ranges(lambda c: ("a" + c).isidentifier())
+    source = f"""\
+// Unicode Identifier Tables
+//
+// XID_Start and XID_Continue from UAX #31, Unicode {unicodedata.unidata_version}, as sorted
+// inclusive ranges. Generated by scripts/xid_tables.py; do not edit.
+
+{table("XID_START", "Chars that may begin an identifier, besides `_`", start)}
+
+{table("XID_CONTINUE", "Chars that may follow the first char of an identifier", cont)}
+
+fn in_table(table: &[(char, char)], ch: char) -> bool {{
+    table
+        .binary_search_by(|&(lo, hi)| {{
+            if hi < ch {{
+                std::cmp::Ordering::Less
+            }} else if lo > ch {{
+                std::cmp::Ordering::Greater
+            }} else {{
+                std::cmp::Ordering::Equal
+            }}
+        }})
+        .is_ok()
+}}
+
+pub fn is_xid_start(ch: char) -> bool {{
+    in_table(XID_START, ch)
+}}
+
+pub fn is_xid_continue(ch: char) -> bool {{
+    in_table(XID_CONTINUE, ch)
+}}
+"""
+    with open("src/xid.rs", "w", newline="\n") as f:
+        f.write(source)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,617 @@
+// Distribution Analysis for Mist Programs
+//
+// A Mist program is a function of its coin flips: fix every flip and the run is
+// deterministic. The analyzer therefore enumerates all 2^k coin outcomes exactly
+// when k is small, and falls back to rejection sampling otherwise.
+//
+// Conditioning (`given`) is handled the same way the generated C handles it:
+// rejected worlds are discarded and the remaining ones renormalised.
+//
+// The same enumeration answers `expect` and `prob` queries at compile time,
+// in query.rs.
+
+use crate::compiler::{
+    Attempt, ClockCoins, Interpreter, NullMode, Program, ProgramInput, RuntimeError, Statement,
+    StatementKind, Value,
+};
+use crate::rational::Rational;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::fmt;
+
+// ===========================================================================
+// WORLD ENUMERATION
+// ===========================================================================
+
+/// Programs with at most this many coin flips are enumerated exactly (2^20 worlds)
+pub const EXACT_COIN_LIMIT: usize = 20;
+
+/// Number of runs used when a program has too many coin flips to enumerate
+pub const DEFAULT_SAMPLES: usize = 100_000;
+
+/// Number of coin flips in a run that reaches the end of the program
+///
+/// Entangled declarations share a single flip.
+pub fn coin_count(program: &Program) -> usize {
+    program
+        .iter()
+        .filter(|stmt| {
+            matches!(
+                stmt.kind,
+                StatementKind::MaybeDeclaration(_, _) | StatementKind::EntangledDeclaration(_)
+            )
+        })
+        .count()
+}
+
+/// Which `maybe` declaration each coin flip belongs to, as `--list-worlds` prints it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorldMap {
+    /// (source line, declared names) of flip i, which is bit i of a world
+    pub flips: Vec<(usize, Vec<String>)>,
+}
+
+/// Number the coin flips of a program whose statements start on `lines`
+pub fn world_map(program: &Program, lines: &[usize]) -> WorldMap {
+    let flips = program
+        .iter()
+        .zip(lines)
+        .filter_map(|(stmt, &line)| match &stmt.kind {
+            StatementKind::MaybeDeclaration(name, _) => Some((line, vec![name.text.clone()])),
+            StatementKind::EntangledDeclaration(bindings) => Some((
+                line,
+                bindings.iter().map(|(name, _)| name.text.clone()).collect(),
+            )),
+            _ => None,
+        })
+        .collect();
+    WorldMap { flips }
+}
+
+/// A probability, exact when worlds were enumerated and estimated when sampled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Probability {
+    Exact(Rational),
+    Estimate { hits: u64, trials: u64 },
+}
+
+impl Probability {
+    fn from_counts(hits: u64, trials: u64, exact: bool) -> Self {
+        if exact {
+            Rational::new(i128::from(hits), i128::from(trials.max(1))).into()
+        } else {
+            Probability::Estimate { hits, trials }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Probability::Exact(p) => p.to_f64(),
+            Probability::Estimate { hits, trials } => hits as f64 / trials.max(1) as f64,
+        }
+    }
+}
+
+impl From<Rational> for Probability {
+    fn from(p: Rational) -> Self {
+        Probability::Exact(p)
+    }
+}
+
+impl fmt::Display for Probability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probability::Exact(p) => write!(f, "{} ({:.2}%)", p, p.to_f64() * 100.0),
+            Probability::Estimate { .. } => write!(f, "≈ {:.2}%", self.to_f64() * 100.0),
+        }
+    }
+}
+
+/// How the worlds were explored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Exact { coins: usize },
+    Sampled { samples: u64 },
+}
+
+/// Posterior distribution of the value printed by one `blah` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDistribution {
+    /// Index of the statement in the program
+    pub statement: usize,
+    pub expression: String,
+    /// Printed values in ascending order, with their probabilities
+    pub outcomes: Vec<(Value, Probability)>,
+    /// Probability that the statement is never reached (e.g. after `bye`)
+    pub unreached: Probability,
+}
+
+/// Probability that a `surely` assertion aborts an execution attempt
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionReport {
+    pub line: usize,
+    pub condition: String,
+    pub failure: Probability,
+}
+
+/// Everything the analyzer learned about a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub method: Method,
+    /// Probability that an attempt satisfies every `given` and runs to completion
+    pub evidence: Probability,
+    /// One entry per `blah`, conditioned on the attempt being accepted
+    pub outputs: Vec<OutputDistribution>,
+    /// One entry per `surely`, per execution attempt (before conditioning)
+    pub assertions: Vec<AssertionReport>,
+    /// Probability that an attempt stops on null arithmetic (NullMode::Strict)
+    pub null_failures: Probability,
+}
+
+/// Outcome counts accumulated over every explored world
+struct Tally {
+    worlds: u64,
+    accepted: u64,
+    /// Per blah statement: (statement index, [(value, count)])
+    outputs: Vec<(usize, Vec<(Value, u64)>)>,
+    /// Per surely statement: (statement index, failure count)
+    assertions: Vec<(usize, u64)>,
+    null_failures: u64,
+}
+
+impl Tally {
+    fn new(program: &Program) -> Self {
+        let mut outputs = Vec::new();
+        let mut assertions = Vec::new();
+        for (index, stmt) in program.iter().enumerate() {
+            match stmt.kind {
+                StatementKind::BlahStatement(_) => outputs.push((index, Vec::new())),
+                StatementKind::SurelyStatement(_) => assertions.push((index, 0)),
+                _ => {},
+            }
+        }
+        Tally {
+            worlds: 0,
+            accepted: 0,
+            outputs,
+            assertions,
+            null_failures: 0,
+        }
+    }
+
+    fn record(
+        &mut self,
+        program: &Program,
+        attempt: Result<Attempt, RuntimeError>,
+        printed: &[(usize, Value)],
+    ) -> Result<(), RuntimeError> {
+        self.worlds += 1;
+        match attempt {
+            Ok(Attempt::Finished(_)) => {
+                self.accepted += 1;
+                for (index, value) in printed {
+                    if let Some((_, counts)) = self.outputs.iter_mut().find(|(i, _)| i == index) {
+                        match counts.iter_mut().find(|(v, _)| v == value) {
+                            Some((_, count)) => *count += 1,
+                            None => counts.push((value.clone(), 1)),
+                        }
+                    }
+                }
+            },
+            Ok(Attempt::Rejected(_)) => {},
+            Err(RuntimeError::AssertionFailed { span, .. }) => {
+                let failed = program.iter().position(|stmt| stmt.span == span);
+                if let Some((_, count)) =
+                    self.assertions.iter_mut().find(|(i, _)| Some(*i) == failed)
+                {
+                    *count += 1;
+                }
+            },
+            Err(RuntimeError::NullOperand(..)) => self.null_failures += 1,
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn into_report(self, program: &Program, method: Method) -> Report {
+        let exact = matches!(method, Method::Exact { .. });
+        let outputs = distributions(program, self.outputs, self.accepted, exact);
+
+        let assertions = self
+            .assertions
+            .into_iter()
+            .filter_map(|(index, failures)| match &program[index].kind {
+                StatementKind::SurelyStatement(expr) => Some(AssertionReport {
+                    line: program[index].span.start.line,
+                    condition: expr.to_string(),
+                    failure: Probability::from_counts(failures, self.worlds, exact),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Report {
+            method,
+            evidence: Probability::from_counts(self.accepted, self.worlds, exact),
+            outputs,
+            assertions,
+            null_failures: Probability::from_counts(self.null_failures, self.worlds, exact),
+        }
+    }
+}
+
+/// Turn per-statement value counts over `runs` runs into distributions
+fn distributions(
+    program: &Program,
+    outputs: Vec<(usize, Vec<(Value, u64)>)>,
+    runs: u64,
+    exact: bool,
+) -> Vec<OutputDistribution> {
+    outputs
+        .into_iter()
+        .map(|(statement, mut counts)| {
+            counts.sort_by(|(a, _), (b, _)| value_order(a, b));
+            let reached: u64 = counts.iter().map(|(_, c)| c).sum();
+            OutputDistribution {
+                statement,
+                expression: statement_source(&program[statement]),
+                outcomes: counts
+                    .into_iter()
+                    .map(|(v, c)| (v, Probability::from_counts(c, runs, exact)))
+                    .collect(),
+                unreached: Probability::from_counts(runs - reached, runs, exact),
+            }
+        })
+        .collect()
+}
+
+/// Null first, then integers in numeric order, then booleans and strings
+fn value_order(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Integer(_) => 1,
+            Value::Boolean(_) => 2,
+            Value::String(_) => 3,
+            Value::Rational(_) => 4,
+        }
+    }
+    match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => x.cmp(y),
+        (Value::Boolean(x), Value::Boolean(y)) => x.cmp(y),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Rational(x), Value::Rational(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn statement_source(stmt: &Statement) -> String {
+    match &stmt.kind {
+        StatementKind::BlahStatement(expr) => format!("blah {}", expr),
+        _ => String::new(),
+    }
+}
+
+/// Analyze a type-checked program, exactly if it has at most EXACT_COIN_LIMIT
+/// coin flips and by `samples` rejection-sampled runs otherwise
+///
+/// `input` is read once and replayed in every world, as if each run received
+/// the same stdin and arguments.
+pub fn analyze(
+    program: &Program,
+    input: Box<dyn ProgramInput + '_>,
+    mode: NullMode,
+    samples: usize,
+) -> Result<Report, RuntimeError> {
+    let coins = coin_count(program);
+    if coins <= EXACT_COIN_LIMIT {
+        enumerate(program, input, mode, coins)
+    } else {
+        sample(program, input, mode, samples)
+    }
+}
+
+/// Run every one of the 2^coins worlds once; flip i of world w is bit i of w
+pub fn each_world<E>(
+    program: &Program,
+    input: Box<dyn ProgramInput + '_>,
+    mode: NullMode,
+    coins: usize,
+    mut visit: impl FnMut(Result<Attempt, RuntimeError>, &[(usize, Value)]) -> Result<(), E>,
+) -> Result<(), E> {
+    let bits = Cell::new(0u64);
+    let mut world_coins = || {
+        let heads = bits.get() & 1 == 1;
+        bits.set(bits.get() >> 1);
+        heads
+    };
+    let mut sink = std::io::sink();
+    let mut interpreter = Interpreter::new(&mut world_coins, &mut sink)
+        .with_input(input)
+        .with_null_mode(mode);
+
+    for world in 0..1u64 << coins {
+        bits.set(world);
+        let attempt = interpreter.run_attempt(program);
+        visit(attempt, interpreter.printed())?;
+    }
+    Ok(())
+}
+
+fn enumerate(
+    program: &Program,
+    input: Box<dyn ProgramInput + '_>,
+    mode: NullMode,
+    coins: usize,
+) -> Result<Report, RuntimeError> {
+    let mut tally = Tally::new(program);
+    each_world(program, input, mode, coins, |attempt, printed| {
+        tally.record(program, attempt, printed)
+    })?;
+    Ok(tally.into_report(program, Method::Exact { coins }))
+}
+
+/// Exact distribution of what each `blah` prints over the runs of the compiled
+/// program, as `--runs` observes them
+///
+/// Unlike `analyze`, runs cut short by a failing `surely` or a strict-mode null
+/// still count, with the output they printed before stopping. Only attempts a
+/// `given` rejects are left out, since their output is never shown. None when
+/// the program has too many coin flips to enumerate, or no run is accepted.
+pub fn printed_distributions(
+    program: &Program,
+    input: Box<dyn ProgramInput + '_>,
+    mode: NullMode,
+) -> Result<Option<Vec<OutputDistribution>>, RuntimeError> {
+    let coins = coin_count(program);
+    if coins > EXACT_COIN_LIMIT {
+        return Ok(None);
+    }
+
+    let mut tally = Tally::new(program);
+    let mut runs = 0;
+    each_world(program, input, mode, coins, |attempt, printed| {
+        match attempt {
+            Ok(Attempt::Rejected(_)) => return Ok(()),
+            Ok(Attempt::Finished(_))
+            | Err(RuntimeError::AssertionFailed { .. } | RuntimeError::NullOperand(..)) => {},
+            Err(e) => return Err(e),
+        }
+        runs += 1;
+        tally.record(program, Ok(Attempt::Finished(0)), printed)
+    })?;
+
+    Ok((runs > 0).then(|| distributions(program, tally.outputs, runs, true)))
+}
+
+/// Run `samples` attempts with random coins, keeping only the accepted ones
+fn sample(
+    program: &Program,
+    input: Box<dyn ProgramInput + '_>,
+    mode: NullMode,
+    samples: usize,
+) -> Result<Report, RuntimeError> {
+    let mut coins = ClockCoins::new();
+    let mut sink = std::io::sink();
+    let mut interpreter = Interpreter::new(&mut coins, &mut sink)
+        .with_input(input)
+        .with_null_mode(mode);
+
+    let mut tally = Tally::new(program);
+    for _ in 0..samples {
+        let attempt = interpreter.run_attempt(program);
+        tally.record(program, attempt, interpreter.printed())?;
+    }
+
+    let samples = tally.worlds;
+    Ok(tally.into_report(program, Method::Sampled { samples }))
+}
+
+impl fmt::Display for WorldMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let coins = self.flips.len();
+        match 1u128.checked_shl(u32::try_from(coins).unwrap_or(u32::MAX)) {
+            Some(worlds) => writeln!(f, "{} coin flip(s): {} worlds", coins, worlds)?,
+            None => writeln!(f, "{} coin flip(s): 2^{} worlds", coins, coins)?,
+        }
+        if coins == 0 {
+            return Ok(());
+        }
+
+        writeln!(f)?;
+        let bit_width = (coins - 1).to_string().len();
+        for (bit, (line, names)) in self.flips.iter().enumerate() {
+            let declared = match names.as_slice() {
+                [name] => name.clone(),
+                names => format!("({})", names.join(", ")),
+            };
+            writeln!(
+                f,
+                "bit {:<width$}  line {}: maybe {}",
+                bit,
+                line,
+                declared,
+                width = bit_width
+            )?;
+        }
+        writeln!(
+            f,
+            "\nRun one world with --world=<bitmask> or --world=<outcome>,<outcome>,...,"
+        )?;
+        writeln!(
+            f,
+            "where 1 keeps a declaration's value and 0 makes it null."
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.method {
+            Method::Exact { coins } => writeln!(
+                f,
+                "Exact enumeration of {} coin flip(s): {} worlds",
+                coins,
+                1u64 << coins
+            )?,
+            Method::Sampled { samples } => {
+                writeln!(f, "Rejection sampling with {} runs", samples)?;
+            },
+        }
+        writeln!(f, "P(accepted) = {}", self.evidence)?;
+
+        if self.evidence.to_f64() == 0.0 {
+            writeln!(f, "\nNo world satisfies the given conditions.")?;
+        } else if !self.outputs.is_empty() {
+            writeln!(f, "\nOutput distributions (given the run is accepted):")?;
+            for output in &self.outputs {
+                writeln!(
+                    f,
+                    "  statement {}: {}",
+                    output.statement + 1,
+                    output.expression
+                )?;
+                for (value, p) in &output.outcomes {
+                    writeln!(f, "    {:<12} {}", value.to_string(), p)?;
+                }
+                if output.unreached.to_f64() > 0.0 {
+                    writeln!(f, "    {:<12} {}", "(not printed)", output.unreached)?;
+                }
+            }
+        }
+
+        if !self.assertions.is_empty() {
+            writeln!(f, "\nAssertion failures (per execution attempt):")?;
+            for assertion in &self.assertions {
+                writeln!(
+                    f,
+                    "  line {}: surely {} fails with {}",
+                    assertion.line, assertion.condition, assertion.failure
+                )?;
+            }
+        }
+
+        if self.null_failures.to_f64() > 0.0 {
+            writeln!(
+                f,
+                "\nNull arithmetic failures (strict mode): {}",
+                self.null_failures
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::NoInput;
+    use crate::driver::front_end;
+
+    fn report(source: &str) -> Report {
+        let (program, _) = front_end(source).unwrap();
+        analyze(
+            &program,
+            Box::new(NoInput),
+            NullMode::default(),
+            DEFAULT_SAMPLES,
+        )
+        .unwrap()
+    }
+
+    fn exact(num: i128, den: i128) -> Probability {
+        Probability::Exact(Rational::new(num, den))
+    }
+
+    #[test]
+    fn test_world_map() {
+        let source = "maybe a = 1\n\nmaybe (b, c) = (2, 3)\nblah a\nmaybe d = 4";
+        let checked =
+            crate::driver::check_source(source, &crate::compiler::CompileOptions::default())
+                .unwrap();
+        let map = world_map(&checked.program, &checked.lines);
+        assert_eq!(
+            map.flips,
+            vec![
+                (1, vec!["a".to_string()]),
+                (3, vec!["b".to_string(), "c".to_string()]),
+                (5, vec!["d".to_string()]),
+            ]
+        );
+        let listing = map.to_string();
+        assert!(listing.starts_with("3 coin flip(s): 8 worlds\n"));
+        assert!(listing.contains("bit 1  line 3: maybe (b, c)\n"));
+    }
+
+    #[test]
+    fn test_exact_distribution() {
+        let report = report("maybe a = 1\nmaybe b = 2\nblah a + b");
+        assert_eq!(report.method, Method::Exact { coins: 2 });
+        assert_eq!(report.evidence, exact(1, 1));
+        assert_eq!(
+            report.outputs[0].outcomes,
+            vec![
+                (Value::Integer(0), exact(1, 4)),
+                (Value::Integer(1), exact(1, 4)),
+                (Value::Integer(2), exact(1, 4)),
+                (Value::Integer(3), exact(1, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_given_posterior() {
+        // Knowing the sum is positive leaves three equally likely worlds
+        let report = report("maybe a = 1\nmaybe b = 2\ngiven a + b > 0\nblah a");
+        assert_eq!(report.evidence, exact(3, 4));
+        assert_eq!(
+            report.outputs[0].outcomes,
+            vec![(Value::Null, exact(1, 3)), (Value::Integer(1), exact(2, 3))]
+        );
+    }
+
+    #[test]
+    fn test_entangled_correlation_is_exact() {
+        let report = report("maybe (tax, tip) = (4, 10)\nblah tax + tip");
+        assert_eq!(report.method, Method::Exact { coins: 1 });
+        assert_eq!(
+            report.outputs[0].outcomes,
+            vec![
+                (Value::Integer(0), exact(1, 2)),
+                (Value::Integer(14), exact(1, 2))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assertion_failure_probability() {
+        let report = report("maybe a = 1\nmaybe b = 1\nsurely a + b > 0\nbye 3\nblah a");
+        assert_eq!(report.assertions[0].line, 3);
+        assert_eq!(report.assertions[0].failure, exact(1, 4));
+        // The blah after bye is never printed in an accepted run
+        assert_eq!(report.outputs[0].unreached, exact(1, 1));
+    }
+
+    #[test]
+    fn test_printed_distributions_count_failed_runs() {
+        let (program, _) =
+            front_end("maybe a = 1\ngiven a == 1\nmaybe b = 2\nblah b\nsurely b > 0\nblah 7")
+                .unwrap();
+        let outputs = printed_distributions(&program, Box::new(NoInput), NullMode::default())
+            .unwrap()
+            .unwrap();
+        // Rejected worlds are left out; the failing surely still printed b
+        assert_eq!(
+            outputs[0].outcomes,
+            vec![(Value::Null, exact(1, 2)), (Value::Integer(2), exact(1, 2))]
+        );
+        assert_eq!(outputs[1].outcomes, vec![(Value::Integer(7), exact(1, 2))]);
+        assert_eq!(outputs[1].unreached, exact(1, 2));
+
+        let (program, _) = front_end("maybe a = 1\ngiven a == 2\nblah a").unwrap();
+        let none = printed_distributions(&program, Box::new(NoInput), NullMode::default());
+        assert_eq!(none.unwrap(), None);
+    }
+}
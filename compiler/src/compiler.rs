@@ -14,7 +14,12 @@
 #![allow(clippy::needless_pass_by_value)]
 #![allow(clippy::uninlined_format_args)]
 
-use std::collections::HashMap;
+use crate::ir::{self, Instruction, IrProgram, IrType, Op, Temp};
+use crate::optimize::optimize;
+use crate::rational::Rational;
+use crate::xid;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 // ===========================================================================
 // FORMAL LANGUAGE DEFINITION
@@ -25,26 +30,340 @@ use std::fmt;
 /// BNF Grammar:
 /// ```
 /// Program  ::= Statement*
-/// Statement ::= MaybeDecl | BlahStmt
+/// Statement ::= MaybeDecl | EntangledDecl | BlahStmt | SurelyStmt | ByeStmt | GivenStmt
 /// MaybeDecl ::= "maybe" Identifier "=" Expression
+/// EntangledDecl ::= "maybe" "(" Identifier ("," Identifier)* ")"
+///                   "=" "(" Expression ("," Expression)* ")"
 /// BlahStmt  ::= "blah" Expression
-/// Expression ::= Number | Variable | String | Add | Subtract
+/// SurelyStmt ::= "surely" Expression
+/// ByeStmt   ::= "bye" Expression
+/// GivenStmt ::= "given" Expression
+/// Expression ::= Number | Variable | String | Input | Arg | Call | Add | Subtract | Compare
+///              | IsNull | Expect | Prob
+/// Input     ::= "input"
+/// Arg       ::= "arg" "(" Expression ")"
+/// Expect    ::= "expect" "(" Expression ")"
+/// Prob      ::= "prob" "(" Expression ")"
+/// IsNull    ::= Expression "is" "null"
+/// Call      ::= Identifier "(" (Expression ("," Expression)*)? ")"
 /// Add       ::= Expression "+" Expression
 /// Subtract  ::= Expression "-" Expression
+/// Compare   ::= Expression ("==" | "!=" | "<" | "<=" | ">" | ">=") Expression
 /// ```
+///
+/// Every node carries the span of source it was parsed from. Equality and
+/// hashing look only at the structure, so the same expression written in two
+/// places, or built by hand in a test, compares equal.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+
+    /// Whether any part of the expression failed to parse
+    pub fn contains_error(&self) -> bool {
+        match &self.kind {
+            ExpressionKind::Error => true,
+            ExpressionKind::Number(_)
+            | ExpressionKind::Variable(_)
+            | ExpressionKind::StringLiteral(_)
+            | ExpressionKind::Input
+            | ExpressionKind::Exact(_, _) => false,
+            ExpressionKind::Argument(e)
+            | ExpressionKind::IsNull(e)
+            | ExpressionKind::Expect(e)
+            | ExpressionKind::Prob(e) => e.contains_error(),
+            ExpressionKind::Call(_, args) => args.iter().any(Expression::contains_error),
+            ExpressionKind::Add(e1, e2)
+            | ExpressionKind::Subtract(e1, e2)
+            | ExpressionKind::Comparison(_, e1, e2) => e1.contains_error() || e2.contains_error(),
+        }
+    }
+}
+
+impl ExpressionKind {
+    /// Name of the variant, as `--emit` prints it
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExpressionKind::Number(_) => "Number",
+            ExpressionKind::Variable(_) => "Variable",
+            ExpressionKind::StringLiteral(_) => "StringLiteral",
+            ExpressionKind::Input => "Input",
+            ExpressionKind::Argument(_) => "Argument",
+            ExpressionKind::Call(_, _) => "Call",
+            ExpressionKind::Add(_, _) => "Add",
+            ExpressionKind::Subtract(_, _) => "Subtract",
+            ExpressionKind::Comparison(_, _, _) => "Comparison",
+            ExpressionKind::IsNull(_) => "IsNull",
+            ExpressionKind::Expect(_) => "Expect",
+            ExpressionKind::Prob(_) => "Prob",
+            ExpressionKind::Exact(_, _) => "Exact",
+            ExpressionKind::Error => "Error",
+        }
+    }
+}
+
+/// An expression without a source location, as built by tests and tools
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Self {
+        Expression::new(kind, Span::default())
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Expression {}
+
+impl std::hash::Hash for Expression {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Expression {
+pub enum ExpressionKind {
     Number(i32),
     Variable(String),
     StringLiteral(String),
+    /// Reads one line from stdin as an integer; null on EOF or a failed parse
+    Input,
+    /// Process argument n as an integer (arg(0) is the program name)
+    Argument(Box<Expression>),
+    /// Call of a builtin function such as `max(a, b)`
+    Call(String, Vec<Expression>),
     Add(Box<Expression>, Box<Expression>),
     Subtract(Box<Expression>, Box<Expression>),
+    Comparison(ComparisonOp, Box<Expression>, Box<Expression>),
+    /// `e is null`: whether the value is null, without coercing it to 0
+    IsNull(Box<Expression>),
+    /// Expected value of an integer expression over the worlds reaching it
+    Expect(Box<Expression>),
+    /// Probability that a condition holds over the worlds reaching it
+    Prob(Box<Expression>),
+    /// A query answered at compile time; the original query is kept for diagnostics
+    Exact(Rational, Box<Expression>),
+    /// Source that failed to parse; the error has been reported, and a
+    /// program containing one never gets past the front end
+    Error,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Statement {
-    MaybeDeclaration(String, Expression),
+/// Relational operators, all of which compare integers after null coercion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComparisonOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl ComparisonOp {
+    /// Source (and C) spelling of the operator
+    pub fn symbol(self) -> &'static str {
+        match self {
+            ComparisonOp::Equal => "==",
+            ComparisonOp::NotEqual => "!=",
+            ComparisonOp::Less => "<",
+            ComparisonOp::LessEqual => "<=",
+            ComparisonOp::Greater => ">",
+            ComparisonOp::GreaterEqual => ">=",
+        }
+    }
+
+    pub fn apply(self, n1: i32, n2: i32) -> bool {
+        match self {
+            ComparisonOp::Equal => n1 == n2,
+            ComparisonOp::NotEqual => n1 != n2,
+            ComparisonOp::Less => n1 < n2,
+            ComparisonOp::LessEqual => n1 <= n2,
+            ComparisonOp::Greater => n1 > n2,
+            ComparisonOp::GreaterEqual => n1 >= n2,
+        }
+    }
+
+    /// Whether the operator accepts two operands that compare as `ordering`
+    pub fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            ComparisonOp::Equal => ordering.is_eq(),
+            ComparisonOp::NotEqual => ordering.is_ne(),
+            ComparisonOp::Less => ordering.is_lt(),
+            ComparisonOp::LessEqual => ordering.is_le(),
+            ComparisonOp::Greater => ordering.is_gt(),
+            ComparisonOp::GreaterEqual => ordering.is_ge(),
+        }
+    }
+}
+
+/// Pretty-prints an expression back to Mist source, used in diagnostics
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExpressionKind::Number(n) => write!(f, "{}", n),
+            ExpressionKind::Variable(x) => write!(f, "{}", x),
+            ExpressionKind::StringLiteral(s) => f.write_str(&c_string_literal(s)),
+            ExpressionKind::Input => write!(f, "input"),
+            ExpressionKind::Argument(e) => write!(f, "arg({})", e),
+            ExpressionKind::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            },
+            ExpressionKind::Add(e1, e2) => write!(f, "{} + {}", e1, e2),
+            ExpressionKind::Subtract(e1, e2) => write!(f, "{} - {}", e1, e2),
+            ExpressionKind::Comparison(op, e1, e2) => write!(f, "{} {} {}", e1, op.symbol(), e2),
+            ExpressionKind::IsNull(e) => write!(f, "{} is null", e),
+            ExpressionKind::Expect(e) => write!(f, "expect({})", e),
+            ExpressionKind::Prob(e) => write!(f, "prob({})", e),
+            ExpressionKind::Exact(_, query) => write!(f, "{}", query),
+            ExpressionKind::Error => write!(f, "<error>"),
+        }
+    }
+}
+
+/// A statement and the span of source it was parsed from; like expressions,
+/// statements compare by structure alone
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Statement { kind, span }
+    }
+}
+
+impl StatementKind {
+    /// Name of the variant, as `--emit` prints it
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatementKind::MaybeDeclaration(_, _) => "MaybeDeclaration",
+            StatementKind::EntangledDeclaration(_) => "EntangledDeclaration",
+            StatementKind::BlahStatement(_) => "BlahStatement",
+            StatementKind::SurelyStatement(_) => "SurelyStatement",
+            StatementKind::ByeStatement(_) => "ByeStatement",
+            StatementKind::GivenStatement(_) => "GivenStatement",
+            StatementKind::Error => "Error",
+        }
+    }
+}
+
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Self {
+        Statement::new(kind, Span::default())
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Statement {}
+
+impl std::hash::Hash for Statement {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+    }
+}
+
+/// A variable name where a declaration introduces it, with the span of the
+/// name alone; like expressions, names compare by their text
+#[derive(Debug, Clone)]
+pub struct Name {
+    pub text: String,
+    pub span: Span,
+}
+
+impl Name {
+    pub fn new(text: impl Into<String>, span: Span) -> Self {
+        Name {
+            text: text.into(),
+            span,
+        }
+    }
+}
+
+/// A name without a source location, as built by tests and tools
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Name::new(text, Span::default())
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for Name {}
+
+impl std::hash::Hash for Name {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.text.hash(state);
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StatementKind {
+    MaybeDeclaration(Name, Expression),
+    /// Several variables sharing a single coin flip: all bound or all null
+    EntangledDeclaration(Vec<(Name, Expression)>),
     BlahStatement(Expression),
+    /// Runtime assertion; failures report the statement's span
+    SurelyStatement(Expression),
+    /// Ends the program with the expression's value as exit status (null exits with 0)
+    ByeStatement(Expression),
+    /// Conditioning: worlds where the condition is false are discarded
+    GivenStatement(Expression),
+    /// A statement that failed to parse before anything could be salvaged
+    Error,
+}
+
+/// Pretty-prints a statement back to Mist source, on one line
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            StatementKind::MaybeDeclaration(name, e) => write!(f, "maybe {} = {}", name, e),
+            StatementKind::EntangledDeclaration(bindings) => {
+                let names: Vec<&str> = bindings
+                    .iter()
+                    .map(|(name, _)| name.text.as_str())
+                    .collect();
+                let values: Vec<String> = bindings.iter().map(|(_, e)| e.to_string()).collect();
+                write!(f, "maybe ({}) = ({})", names.join(", "), values.join(", "))
+            },
+            StatementKind::BlahStatement(e) => write!(f, "blah {}", e),
+            StatementKind::SurelyStatement(e) => write!(f, "surely {}", e),
+            StatementKind::ByeStatement(e) => write!(f, "bye {}", e),
+            StatementKind::GivenStatement(e) => write!(f, "given {}", e),
+            StatementKind::Error => write!(f, "<error>"),
+        }
+    }
 }
 
 pub type Program = Vec<Statement>;
@@ -59,6 +378,21 @@ pub enum Value {
     Integer(i32),
     Null,
     String(String),
+    Boolean(bool),
+    /// Answer of an `expect` or `prob` query
+    Rational(Rational),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Null => write!(f, "null"),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Rational(r) => write!(f, "{}", r),
+        }
+    }
 }
 
 /// Runtime environment: Σ : Var → Value
@@ -67,8 +401,112 @@ pub type Environment = HashMap<String, Value>;
 /// Semantic errors that can occur during evaluation
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeError {
-    UndefinedVariable(String),
-    TypeError(String),
+    UndefinedVariable(String, Span),
+    TypeError(String, Span),
+    AssertionFailed {
+        span: Span,
+        condition: String,
+    },
+    /// Every attempt was rejected; the span is the `given` that rejected the
+    /// last one
+    NoAcceptedWorld {
+        attempts: usize,
+        span: Span,
+    },
+    /// Arithmetic or comparison on null under NullMode::Strict
+    NullOperand(String, Span),
+    /// A replayed coin trace had fewer flips than the run needed; the span is
+    /// the declaration that wanted one more
+    CoinsExhausted(Span),
+    /// A `given` failed in the one world the run was asked to explore
+    WorldRejected(Span),
+    /// Writing the output or the trace failed, which is about the stream and
+    /// not about any place in the program, so this is the one error without a
+    /// span
+    Io(String),
+}
+
+impl RuntimeError {
+    /// Where in the source the error happened, for the errors that have a place
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::UndefinedVariable(_, span)
+            | RuntimeError::TypeError(_, span)
+            | RuntimeError::AssertionFailed { span, .. }
+            | RuntimeError::NullOperand(_, span)
+            | RuntimeError::CoinsExhausted(span)
+            | RuntimeError::WorldRejected(span)
+            | RuntimeError::NoAcceptedWorld { span, .. } => Some(*span),
+            RuntimeError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedVariable(x, _) => write!(f, "Undefined variable '{}'", x),
+            RuntimeError::TypeError(msg, _) => write!(f, "{}", msg),
+            RuntimeError::AssertionFailed { span, condition } => {
+                write!(
+                    f,
+                    "surely failed at line {}: {}",
+                    span.start.line, condition
+                )
+            },
+            RuntimeError::NoAcceptedWorld { attempts, .. } => write!(
+                f,
+                "given: no world satisfied the conditions in {} attempts",
+                attempts
+            ),
+            RuntimeError::NullOperand(expr, _) => {
+                write!(f, "null operand in strict mode: {}", expr)
+            },
+            RuntimeError::CoinsExhausted(_) => {
+                write!(f, "coin trace ended before the program did")
+            },
+            RuntimeError::WorldRejected(_) => {
+                write!(
+                    f,
+                    "given: the selected world does not satisfy the conditions"
+                )
+            },
+            RuntimeError::Io(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// The null algebra used by `+`, `-` and comparisons
+///
+///   Zero:      Null ⊕ Int(n) = Int(n),  Null ⊕ Null = Int(0)   (null acts as 0)
+///   Propagate: Null ⊕ v = v ⊕ Null = Null                      (SQL style)
+///   Strict:    Null ⊕ v = v ⊕ Null = error, and so is Null ⋈ v
+///
+/// Builtins are null-aware by definition and behave the same in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NullMode {
+    #[default]
+    Zero,
+    Propagate,
+    Strict,
+}
+
+impl std::str::FromStr for NullMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(NullMode::Zero),
+            "propagate" => Ok(NullMode::Propagate),
+            "strict" => Ok(NullMode::Strict),
+            _ => Err(format!(
+                "unknown null mode '{}' (expected zero, propagate or strict)",
+                s
+            )),
+        }
+    }
 }
 
 /// Big-step operational semantics: ⟨e, σ⟩ ⇓ v
@@ -89,65 +527,356 @@ pub enum RuntimeError {
 ///          ─────────────────────────────────────────────
 ///          ⟨e₁ + e₂, σ⟩ ⇓ v₃
 ///
-/// Where ⊕ is defined as:
+/// Where ⊕ is defined as (in the default NullMode::Zero):
 ///   Int(n₁) ⊕ Int(n₂) = Int(n₁ + n₂)
 ///   Null ⊕ Int(n) = Int(n)
 ///   Int(n) ⊕ Null = Int(n)
 ///   Null ⊕ Null = Int(0)
+///
+/// [E-Cmp]  ⟨e₁, σ⟩ ⇓ v₁   ⟨e₂, σ⟩ ⇓ v₂   ⌊v₁⌋ ⋈ ⌊v₂⌋ = b
+///          ─────────────────────────────────────────────
+///          ⟨e₁ ⋈ e₂, σ⟩ ⇓ Bool(b)
+///
+/// Where ⌊Null⌋ = 0 and ⌊Int(n)⌋ = n, matching the coercion used by ⊕;
+/// a comparison involving a query answer compares exact rationals
+///
+/// [E-IsNull] ⟨e, σ⟩ ⇓ v
+///            ──────────────────────────────
+///            ⟨e is null, σ⟩ ⇓ Bool(v = Null)
+///
+/// [E-Exact]  ─────────────────────────
+///            ⟨q ↦ r, σ⟩ ⇓ Rational(r)
+///
+/// where q ↦ r is an `expect`/`prob` query answered by the front end
 pub fn eval_expr(expr: &Expression, env: &Environment) -> Result<Value, RuntimeError> {
-    match expr {
+    eval_expr_in_mode(expr, env, NullMode::default())
+}
+
+/// ⟨e, σ⟩ ⇓ v with ⊕ taken from `mode`
+pub fn eval_expr_in_mode(
+    expr: &Expression,
+    env: &Environment,
+    mode: NullMode,
+) -> Result<Value, RuntimeError> {
+    eval_expr_with_input(expr, env, &mut NoInput, mode)
+}
+
+/// ⟨e, σ⟩ ⇓ v where `input` and `arg(n)` observe the given outside world
+///
+/// [E-Input]  ⟨input, σ⟩ ⇓ Int(n) if the next stdin line parses as n, else Null
+/// [E-Arg]    ⟨arg(e), σ⟩ ⇓ Int(n) if argv[⌊e⌋] parses as n, else Null
+pub fn eval_expr_with_input(
+    expr: &Expression,
+    env: &Environment,
+    input: &mut dyn ProgramInput,
+    mode: NullMode,
+) -> Result<Value, RuntimeError> {
+    match &expr.kind {
         // [E-Num]
-        Expression::Number(n) => Ok(Value::Integer(*n)),
+        ExpressionKind::Number(n) => Ok(Value::Integer(*n)),
 
         // [E-Str]
-        Expression::StringLiteral(s) => Ok(Value::String(s.clone())),
+        ExpressionKind::StringLiteral(s) => Ok(Value::String(s.clone())),
 
         // [E-Var]
-        Expression::Variable(x) => env
+        ExpressionKind::Variable(x) => env
             .get(x)
             .cloned()
-            .ok_or_else(|| RuntimeError::UndefinedVariable(x.clone())),
+            .ok_or_else(|| RuntimeError::UndefinedVariable(x.clone(), expr.span)),
+
+        // [E-Input]
+        ExpressionKind::Input => Ok(input.read_int().map_or(Value::Null, Value::Integer)),
+
+        // [E-Arg]
+        ExpressionKind::Argument(e) => {
+            let n = coerce_to_int(eval_expr_with_input(e, env, input, mode)?)
+                .map_err(|msg| RuntimeError::TypeError(msg, e.span))?;
+            Ok(input.arg(n).map_or(Value::Null, Value::Integer))
+        },
+
+        // [E-Call]
+        ExpressionKind::Call(name, args) => {
+            let values = args
+                .iter()
+                .map(|arg| eval_expr_with_input(arg, env, input, mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            apply_builtin(name, &values).map_err(|msg| RuntimeError::TypeError(msg, expr.span))
+        },
 
         // [E-Add]
-        Expression::Add(e1, e2) => {
-            let v1 = eval_expr(e1, env)?;
-            let v2 = eval_expr(e2, env)?;
-            add_values(v1, v2)
+        ExpressionKind::Add(e1, e2) => {
+            let v1 = eval_expr_with_input(e1, env, input, mode)?;
+            let v2 = eval_expr_with_input(e2, env, input, mode)?;
+            check_strict(expr, &v1, &v2, mode)?;
+            add_values(v1, v2, mode).map_err(|msg| RuntimeError::TypeError(msg, expr.span))
         },
 
         // [E-Sub]
-        Expression::Subtract(e1, e2) => {
-            let v1 = eval_expr(e1, env)?;
-            let v2 = eval_expr(e2, env)?;
-            subtract_values(v1, v2)
+        ExpressionKind::Subtract(e1, e2) => {
+            let v1 = eval_expr_with_input(e1, env, input, mode)?;
+            let v2 = eval_expr_with_input(e2, env, input, mode)?;
+            check_strict(expr, &v1, &v2, mode)?;
+            subtract_values(v1, v2, mode).map_err(|msg| RuntimeError::TypeError(msg, expr.span))
+        },
+
+        // [E-Cmp]
+        ExpressionKind::Comparison(op, e1, e2) => {
+            let v1 = eval_expr_with_input(e1, env, input, mode)?;
+            let v2 = eval_expr_with_input(e2, env, input, mode)?;
+            check_strict(expr, &v1, &v2, mode)?;
+            let located = |msg| RuntimeError::TypeError(msg, expr.span);
+            if matches!(v1, Value::Rational(_)) || matches!(v2, Value::Rational(_)) {
+                let ordering = coerce_to_rational(v1)
+                    .map_err(located)?
+                    .cmp(&coerce_to_rational(v2).map_err(located)?);
+                return Ok(Value::Boolean(op.holds(ordering)));
+            }
+            Ok(Value::Boolean(op.apply(
+                coerce_to_int(v1).map_err(located)?,
+                coerce_to_int(v2).map_err(located)?,
+            )))
+        },
+
+        // [E-IsNull]
+        ExpressionKind::IsNull(e) => Ok(Value::Boolean(
+            eval_expr_with_input(e, env, input, mode)? == Value::Null,
+        )),
+
+        // [E-Exact]
+        ExpressionKind::Exact(r, _) => Ok(Value::Rational(*r)),
+
+        ExpressionKind::Error => Err(RuntimeError::TypeError(
+            "Source that failed to parse cannot run".to_string(),
+            expr.span,
+        )),
+
+        ExpressionKind::Expect(_) | ExpressionKind::Prob(_) => Err(RuntimeError::TypeError(
+            format!("{} was not answered before the program ran", expr),
+            expr.span,
+        )),
+    }
+}
+
+// ===========================================================================
+// BUILTIN FUNCTIONS - Null-Aware Integer Utilities
+// ===========================================================================
+
+/// Signature of a builtin function; every parameter is a (maybe) integer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+}
+
+/// The builtin function table shared by the type checker, evaluator and C backend
+///
+/// Null semantics:
+///   abs(null) = sign(null) = null
+///   min/max ignore null arguments: max(null, 3) = 3, max(null, null) = null
+///   clamp(x, lo, hi) is null when x is null and ignores null bounds
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "abs",
+        arity: 1,
+    },
+    Builtin {
+        name: "min",
+        arity: 2,
+    },
+    Builtin {
+        name: "max",
+        arity: 2,
+    },
+    Builtin {
+        name: "clamp",
+        arity: 3,
+    },
+    Builtin {
+        name: "sign",
+        arity: 1,
+    },
+];
+
+pub fn lookup_builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+/// Apply a builtin to already-evaluated arguments
+pub fn apply_builtin(name: &str, args: &[Value]) -> Result<Value, String> {
+    let ints = args
+        .iter()
+        .map(|v| match v {
+            Value::Integer(n) => Ok(Some(*n)),
+            Value::Null => Ok(None),
+            Value::String(_) | Value::Boolean(_) | Value::Rational(_) => {
+                Err(format!("{} requires integer arguments", name))
+            },
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let result = match (name, ints.as_slice()) {
+        ("abs", [x]) => x.map(i32::saturating_abs),
+        ("sign", [x]) => x.map(i32::signum),
+        ("min", [a, b]) => null_aware(*a, *b, std::cmp::min),
+        ("max", [a, b]) => null_aware(*a, *b, std::cmp::max),
+        ("clamp", [x, lo, hi]) => x.map(|x| {
+            let x = lo.map_or(x, |lo| x.max(lo));
+            hi.map_or(x, |hi| x.min(hi))
+        }),
+        _ => {
+            return Err(format!(
+                "Unknown function '{}' with {} arguments",
+                name,
+                args.len()
+            ))
+        },
+    };
+
+    Ok(result.map_or(Value::Null, Value::Integer))
+}
+
+/// Combine two maybe-integers, ignoring whichever side is null
+fn null_aware(a: Option<i32>, b: Option<i32>, f: fn(i32, i32) -> i32) -> Option<i32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(f(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// The outside world observed by `input` and `arg(n)` expressions
+pub trait ProgramInput {
+    /// Next stdin line as an integer, or None on EOF or a failed parse
+    fn read_int(&mut self) -> Option<i32>;
+
+    /// Process argument n as an integer, or None if absent or not a number
+    fn arg(&self, n: i32) -> Option<i32>;
+}
+
+/// A world with empty stdin and no arguments: every read is null
+pub struct NoInput;
+
+impl ProgramInput for NoInput {
+    fn read_int(&mut self) -> Option<i32> {
+        None
+    }
+
+    fn arg(&self, _n: i32) -> Option<i32> {
+        None
+    }
+}
+
+/// Real stdin and argv, parsed exactly like the generated C's `mist_parse_int`
+pub struct ProcessInput<R: std::io::BufRead> {
+    reader: R,
+    args: Vec<String>,
+}
+
+impl<R: std::io::BufRead> ProcessInput<R> {
+    /// `args[0]` plays the role of argv[0], the program name
+    pub fn new(reader: R, args: Vec<String>) -> Self {
+        ProcessInput { reader, args }
+    }
+}
+
+impl<R: std::io::BufRead> ProgramInput for ProcessInput<R> {
+    fn read_int(&mut self) -> Option<i32> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => parse_int(&line),
+        }
+    }
+
+    fn arg(&self, n: i32) -> Option<i32> {
+        let index = usize::try_from(n).ok()?;
+        self.args.get(index).and_then(|arg| parse_int(arg))
+    }
+}
+
+/// Surrounding whitespace, as C's `isspace` knows it, is allowed; anything
+/// else makes the read null. A line is read whole, however long it is
+fn parse_int(text: &str) -> Option<i32> {
+    text.trim_matches(|c: char| c.is_ascii_whitespace() || c == '\x0b')
+        .parse()
+        .ok()
+}
+
+/// Null coercion ⌊·⌋ used by comparisons and exit statuses
+///
+/// Like the other value helpers, fails with just a message; the caller knows
+/// which expression to blame.
+fn coerce_to_int(v: Value) -> Result<i32, String> {
+    match v {
+        Value::Integer(n) => Ok(n),
+        Value::Null => Ok(0),
+        Value::String(_) | Value::Boolean(_) | Value::Rational(_) => {
+            Err("Comparison requires integer operands".to_string())
         },
     }
 }
 
+/// Null coercion for comparisons against a query answer
+fn coerce_to_rational(v: Value) -> Result<Rational, String> {
+    match v {
+        Value::Rational(r) => Ok(r),
+        v => coerce_to_int(v).map(Rational::from),
+    }
+}
+
+/// In strict mode, an operation on null is an error naming the whole expression
+fn check_strict(
+    expr: &Expression,
+    v1: &Value,
+    v2: &Value,
+    mode: NullMode,
+) -> Result<(), RuntimeError> {
+    if mode == NullMode::Strict && (*v1 == Value::Null || *v2 == Value::Null) {
+        return Err(RuntimeError::NullOperand(expr.to_string(), expr.span));
+    }
+    Ok(())
+}
+
 /// Addition operation on values with null handling
-fn add_values(v1: Value, v2: Value) -> Result<Value, RuntimeError> {
+fn add_values(v1: Value, v2: Value, mode: NullMode) -> Result<Value, String> {
     match (v1, v2) {
         (Value::Integer(n1), Value::Integer(n2)) => Ok(Value::Integer(n1.saturating_add(n2))),
+        (Value::Null, Value::Integer(_) | Value::Null) | (Value::Integer(_), Value::Null)
+            if mode == NullMode::Propagate =>
+        {
+            Ok(Value::Null)
+        },
         (Value::Null, Value::Integer(n)) | (Value::Integer(n), Value::Null) => {
             Ok(Value::Integer(n))
         },
         (Value::Null, Value::Null) => Ok(Value::Integer(0)),
-        (Value::String(_), _) | (_, Value::String(_)) => {
-            Err(RuntimeError::TypeError("Cannot add strings".to_string()))
+        (Value::String(_), _) | (_, Value::String(_)) => Err("Cannot add strings".to_string()),
+        (Value::Boolean(_), _) | (_, Value::Boolean(_)) => Err("Cannot add booleans".to_string()),
+        (Value::Rational(_), _) | (_, Value::Rational(_)) => {
+            Err("Cannot add probabilities".to_string())
         },
     }
 }
 
 /// Subtraction operation on values with null handling
-fn subtract_values(v1: Value, v2: Value) -> Result<Value, RuntimeError> {
+fn subtract_values(v1: Value, v2: Value, mode: NullMode) -> Result<Value, String> {
     match (v1, v2) {
         (Value::Integer(n1), Value::Integer(n2)) => Ok(Value::Integer(n1.saturating_sub(n2))),
+        (Value::Null, Value::Integer(_) | Value::Null) | (Value::Integer(_), Value::Null)
+            if mode == NullMode::Propagate =>
+        {
+            Ok(Value::Null)
+        },
         (Value::Null, Value::Integer(n)) => Ok(Value::Integer(-n)),
         (Value::Integer(n), Value::Null) => Ok(Value::Integer(n)),
         (Value::Null, Value::Null) => Ok(Value::Integer(0)),
-        (Value::String(_), _) | (_, Value::String(_)) => Err(RuntimeError::TypeError(
-            "Cannot subtract strings".to_string(),
-        )),
+        (Value::String(_), _) | (_, Value::String(_)) => Err("Cannot subtract strings".to_string()),
+        (Value::Boolean(_), _) | (_, Value::Boolean(_)) => {
+            Err("Cannot subtract booleans".to_string())
+        },
+        (Value::Rational(_), _) | (_, Value::Rational(_)) => {
+            Err("Cannot subtract probabilities".to_string())
+        },
     }
 }
 
@@ -160,20 +889,94 @@ fn subtract_values(v1: Value, v2: Value) -> Result<Value, RuntimeError> {
 pub enum Type {
     Int,              // Integer type
     String,           // String type
+    Bool,             // Result of a comparison
+    Rational,         // Answer of an expect/prob query
     Maybe(Box<Type>), // Maybe type (can be null)
 }
 
+impl Type {
+    /// Maybe(τ), without nesting: a nullable value that may also be nulled
+    /// by a coin flip is still just nullable
+    pub fn into_maybe(self) -> Type {
+        match self {
+            Type::Maybe(_) => self,
+            t => Type::Maybe(Box::new(t)),
+        }
+    }
+
+    /// Name of the type as `--emit` prints it, such as `Maybe(Int)`
+    pub fn name(&self) -> String {
+        match self {
+            Type::Int => "Int".to_string(),
+            Type::String => "String".to_string(),
+            Type::Bool => "Bool".to_string(),
+            Type::Rational => "Rational".to_string(),
+            Type::Maybe(t) => format!("Maybe({})", t.name()),
+        }
+    }
+}
+
 /// Type environment: Γ : Var → Type
 pub type TypeEnvironment = HashMap<String, Type>;
 
 /// Type errors
 #[derive(Debug, Clone, PartialEq)]
 pub enum TypeError {
-    UndefinedVariable(String),
-    TypeMismatch { expected: String, found: String },
-    InvalidOperation(String),
+    UndefinedVariable(String, Span),
+    TypeMismatch {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    InvalidOperation(String, Span),
+    UnknownFunction(String, Span),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+}
+
+impl TypeError {
+    /// The expression or statement the error is about
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::UndefinedVariable(_, span)
+            | TypeError::TypeMismatch { span, .. }
+            | TypeError::InvalidOperation(_, span)
+            | TypeError::UnknownFunction(_, span)
+            | TypeError::ArityMismatch { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::UndefinedVariable(x, _) => write!(f, "undefined variable '{}'", x),
+            TypeError::TypeMismatch {
+                expected, found, ..
+            } => write!(f, "expected {} but found {}", expected, found),
+            TypeError::InvalidOperation(msg, _) => write!(f, "{}", msg),
+            TypeError::UnknownFunction(name, _) => write!(f, "unknown function '{}'", name),
+            TypeError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "{} takes {} argument(s) but was given {}",
+                name, expected, found
+            ),
+        }?;
+        write!(f, " at {}", self.span())
+    }
 }
 
+impl std::error::Error for TypeError {}
+
 /// Type inference rules:
 ///
 /// [T-Num]  ───────────
@@ -186,75 +989,353 @@ pub enum TypeError {
 ///          ─────────
 ///          Γ ⊢ x : τ
 ///
+/// [T-Input] ──────────────────────
+///           Γ ⊢ input : Maybe(Int)
+///
+/// [T-Arg]  Γ ⊢ e : τ   τ ⊕ᵗ Int = Int
+///          ──────────────────────────
+///          Γ ⊢ arg(e) : Maybe(Int)
+///
+/// [T-Call] f ∈ BUILTINS   |ē| = arity(f)   Γ ⊢ eᵢ : τᵢ   τᵢ ⊕ᵗ Int = Int
+///          ──────────────────────────────────────────────────────────
+///          Γ ⊢ f(ē) : Int, or Maybe(Int) if the result can be null
+///
 /// [T-Add]  Γ ⊢ e₁ : τ₁   Γ ⊢ e₂ : τ₂   τ₁ ⊕ᵗ τ₂ = Int
 ///          ──────────────────────────────────────────
 ///          Γ ⊢ e₁ + e₂ : Int
+///
+/// [T-Cmp]  Γ ⊢ e₁ : τ₁   Γ ⊢ e₂ : τ₂   τᵢ ⊕ᵗ Int = Int or τᵢ = Rational
+///          ────────────────────────────────────────────────────────
+///          Γ ⊢ e₁ ⋈ e₂ : Bool
+///
+/// [T-IsNull] Γ ⊢ e : τ   τ ⊕ᵗ Int = Int
+///            ──────────────────────────
+///            Γ ⊢ e is null : Bool
+///
+/// [T-Expect] Γ ⊢ e : τ   τ ⊕ᵗ Int = Int        [T-Prob] Γ ⊢ e : Bool
+///            ───────────────────────────               ─────────────────────
+///            Γ ⊢ expect(e) : Rational                  Γ ⊢ prob(e) : Rational
 pub fn type_check_expr(expr: &Expression, env: &TypeEnvironment) -> Result<Type, TypeError> {
-    match expr {
+    type_check_expr_in_mode(expr, env, NullMode::default())
+}
+
+/// Γ ⊢ e : τ under a null algebra; with NullMode::Propagate, [T-Add] gives
+/// Maybe(Int) when either operand is nullable
+pub fn type_check_expr_in_mode(
+    expr: &Expression,
+    env: &TypeEnvironment,
+    mode: NullMode,
+) -> Result<Type, TypeError> {
+    match &expr.kind {
         // [T-Num]
-        Expression::Number(_) => Ok(Type::Int),
+        ExpressionKind::Number(_) => Ok(Type::Int),
 
         // [T-Str]
-        Expression::StringLiteral(_) => Ok(Type::String),
+        ExpressionKind::StringLiteral(_) => Ok(Type::String),
 
         // [T-Var]
-        Expression::Variable(x) => env
+        ExpressionKind::Variable(x) => env
             .get(x)
             .cloned()
-            .ok_or_else(|| TypeError::UndefinedVariable(x.clone())),
+            .ok_or_else(|| TypeError::UndefinedVariable(x.clone(), expr.span)),
 
-        // [T-Add], [T-Sub]
-        Expression::Add(e1, e2) | Expression::Subtract(e1, e2) => {
-            let t1 = type_check_expr(e1, env)?;
-            let t2 = type_check_expr(e2, env)?;
+        // [T-Input]; error nodes are skipped by the program checker, and
+        // anywhere else get the type every declared variable has
+        ExpressionKind::Input | ExpressionKind::Error => Ok(Type::Maybe(Box::new(Type::Int))),
 
-            if can_use_in_arithmetic(&t1) && can_use_in_arithmetic(&t2) {
-                Ok(Type::Int)
+        // [T-Arg]
+        ExpressionKind::Argument(e) => {
+            if can_use_in_arithmetic(&type_check_expr_in_mode(e, env, mode)?) {
+                Ok(Type::Maybe(Box::new(Type::Int)))
             } else {
                 Err(TypeError::InvalidOperation(
-                    "Arithmetic requires integer types".to_string(),
+                    "arg index must be an integer".to_string(),
+                    e.span,
                 ))
             }
         },
-    }
-}
 
-/// Check if a type can be used in arithmetic operations
-fn can_use_in_arithmetic(t: &Type) -> bool {
-    match t {
-        Type::Int => true,
-        Type::Maybe(inner) => matches!(**inner, Type::Int),
-        Type::String => false,
-    }
-}
+        // [T-Call]
+        ExpressionKind::Call(name, args) => {
+            let builtin = lookup_builtin(name)
+                .ok_or_else(|| TypeError::UnknownFunction(name.clone(), expr.span))?;
+            if args.len() != builtin.arity {
+                return Err(TypeError::ArityMismatch {
+                    name: name.clone(),
+                    expected: builtin.arity,
+                    found: args.len(),
+                    span: expr.span,
+                });
+            }
 
-/// Type check a complete program
-///
+            let mut nullable = Vec::new();
+            for arg in args {
+                let t = type_check_expr_in_mode(arg, env, mode)?;
+                if !can_use_in_arithmetic(&t) {
+                    return Err(TypeError::InvalidOperation(
+                        format!("{} requires integer arguments", name),
+                        arg.span,
+                    ));
+                }
+                nullable.push(matches!(t, Type::Maybe(_)));
+            }
+
+            // min/max are only null when both sides are; the rest follow their first argument
+            let result_nullable = match name.as_str() {
+                "min" | "max" => nullable.iter().all(|n| *n),
+                _ => nullable[0],
+            };
+            if result_nullable {
+                Ok(Type::Maybe(Box::new(Type::Int)))
+            } else {
+                Ok(Type::Int)
+            }
+        },
+
+        // [T-Add], [T-Sub]
+        ExpressionKind::Add(e1, e2) | ExpressionKind::Subtract(e1, e2) => {
+            let t1 = type_check_expr_in_mode(e1, env, mode)?;
+            let t2 = type_check_expr_in_mode(e2, env, mode)?;
+
+            if !(can_use_in_arithmetic(&t1) && can_use_in_arithmetic(&t2)) {
+                Err(TypeError::InvalidOperation(
+                    "Arithmetic requires integer types".to_string(),
+                    expr.span,
+                ))
+            } else if mode == NullMode::Propagate
+                && (matches!(t1, Type::Maybe(_)) || matches!(t2, Type::Maybe(_)))
+            {
+                Ok(Type::Maybe(Box::new(Type::Int)))
+            } else {
+                Ok(Type::Int)
+            }
+        },
+
+        // [T-Cmp]
+        ExpressionKind::Comparison(_, e1, e2) => {
+            let t1 = type_check_expr_in_mode(e1, env, mode)?;
+            let t2 = type_check_expr_in_mode(e2, env, mode)?;
+
+            let comparable = |t: &Type| can_use_in_arithmetic(t) || *t == Type::Rational;
+            if comparable(&t1) && comparable(&t2) {
+                Ok(Type::Bool)
+            } else {
+                Err(TypeError::InvalidOperation(
+                    "Comparison requires integer types".to_string(),
+                    expr.span,
+                ))
+            }
+        },
+
+        // [T-IsNull]
+        ExpressionKind::IsNull(e) => {
+            if can_use_in_arithmetic(&type_check_expr_in_mode(e, env, mode)?) {
+                Ok(Type::Bool)
+            } else {
+                Err(TypeError::InvalidOperation(
+                    "'is null' requires an integer expression".to_string(),
+                    expr.span,
+                ))
+            }
+        },
+
+        // [T-Expect]
+        ExpressionKind::Expect(e) => {
+            if can_use_in_arithmetic(&type_check_expr_in_mode(e, env, mode)?) {
+                Ok(Type::Rational)
+            } else {
+                Err(TypeError::InvalidOperation(
+                    "expect requires an integer expression".to_string(),
+                    expr.span,
+                ))
+            }
+        },
+
+        // [T-Prob]
+        ExpressionKind::Prob(e) => {
+            let t = type_check_expr_in_mode(e, env, mode)?;
+            if t == Type::Bool {
+                Ok(Type::Rational)
+            } else {
+                Err(TypeError::TypeMismatch {
+                    expected: "Bool".to_string(),
+                    found: format!("{:?}", t),
+                    span: e.span,
+                })
+            }
+        },
+
+        ExpressionKind::Exact(_, _) => Ok(Type::Rational),
+    }
+}
+
+/// Check if a type can be used in arithmetic operations
+fn can_use_in_arithmetic(t: &Type) -> bool {
+    match t {
+        Type::Int => true,
+        Type::Maybe(inner) => matches!(**inner, Type::Int),
+        Type::String | Type::Bool | Type::Rational => false,
+    }
+}
+
+/// Type of the value bound by a `maybe` declaration
+fn type_check_declaration(
+    e: &Expression,
+    env: &TypeEnvironment,
+    mode: NullMode,
+) -> Result<Type, TypeError> {
+    let t = type_check_expr_in_mode(e, env, mode)?;
+    if matches!(t, Type::String) {
+        return Err(TypeError::InvalidOperation(
+            "Cannot assign strings to variables".to_string(),
+            e.span,
+        ));
+    }
+    if matches!(t, Type::Bool) {
+        return Err(TypeError::InvalidOperation(
+            "Cannot assign booleans to variables".to_string(),
+            e.span,
+        ));
+    }
+    if matches!(t, Type::Rational) {
+        return Err(TypeError::InvalidOperation(
+            "Cannot assign probabilities to variables".to_string(),
+            e.span,
+        ));
+    }
+    Ok(t)
+}
+
+/// Type check a complete program
+///
 /// THEOREM (Type Soundness): If ⊢ P : ok, then P does not get stuck
 /// Proof: By induction on the typing derivation
 pub fn type_check_program(program: &Program) -> Result<TypeEnvironment, TypeError> {
+    type_check_program_in_mode(program, NullMode::default())
+}
+
+/// Type check a complete program under a null algebra
+pub fn type_check_program_in_mode(
+    program: &Program,
+    mode: NullMode,
+) -> Result<TypeEnvironment, TypeError> {
+    let (env, errors) = type_check_program_recovering(program, mode);
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(env),
+    }
+}
+
+/// Type check every statement, reporting at most one error for each.
+/// Statements containing parse errors are skipped, and every declaration
+/// binds its variables even when its value is ill-typed: declared variables
+/// are always Maybe(Int), so one mistake is not reported again at each use.
+pub fn type_check_program_recovering(
+    program: &Program,
+    mode: NullMode,
+) -> (TypeEnvironment, Vec<TypeError>) {
     let mut env = TypeEnvironment::new();
+    let mut errors = Vec::new();
 
     for stmt in program {
-        match stmt {
-            Statement::MaybeDeclaration(x, e) => {
-                let t = type_check_expr(e, &env)?;
-                if matches!(t, Type::String) {
-                    return Err(TypeError::InvalidOperation(
-                        "Cannot assign strings to variables".to_string(),
-                    ));
-                }
-                // Variables declared with 'maybe' have Maybe type
-                env.insert(x.clone(), Type::Maybe(Box::new(t)));
-            },
-            Statement::BlahStatement(e) => {
-                // Blah can print any well-typed expression
-                type_check_expr(e, &env)?;
-            },
+        if let Err(error) = type_check_statement(stmt, &mut env, mode) {
+            errors.push(error);
         }
     }
 
-    Ok(env)
+    (env, errors)
+}
+
+/// Check one statement against the variables declared before it, then
+/// declare its own
+pub fn type_check_statement(
+    stmt: &Statement,
+    env: &mut TypeEnvironment,
+    mode: NullMode,
+) -> Result<(), TypeError> {
+    let declared_int = || Type::Maybe(Box::new(Type::Int));
+    match &stmt.kind {
+        StatementKind::MaybeDeclaration(x, e) => {
+            let t = if e.contains_error() {
+                Ok(Type::Int)
+            } else {
+                type_check_declaration(e, env, mode)
+            };
+            // Variables declared with 'maybe' have Maybe type
+            env.insert(
+                x.text.clone(),
+                t.clone().map_or_else(|_| declared_int(), Type::into_maybe),
+            );
+            t.map(drop)
+        },
+        StatementKind::EntangledDeclaration(bindings) => {
+            // Bindings are simultaneous: every expression sees the outer Γ
+            let mut types = Vec::new();
+            let mut result = Ok(());
+            for (x, e) in bindings {
+                if types.iter().any(|(y, _)| *y == x) {
+                    result = Err(TypeError::InvalidOperation(
+                        format!("Variable '{}' is entangled twice", x),
+                        stmt.span,
+                    ));
+                    break;
+                }
+                if e.contains_error() {
+                    types.push((x, declared_int()));
+                    continue;
+                }
+                match type_check_declaration(e, env, mode) {
+                    Ok(t) => types.push((x, t.into_maybe())),
+                    Err(error) => {
+                        result = Err(error);
+                        break;
+                    },
+                }
+            }
+            for (x, _) in bindings {
+                let t = types.iter().find(|(y, _)| *y == x).map(|(_, t)| t.clone());
+                env.insert(x.text.clone(), t.unwrap_or_else(declared_int));
+            }
+            result
+        },
+        StatementKind::BlahStatement(e)
+        | StatementKind::ByeStatement(e)
+        | StatementKind::SurelyStatement(e)
+        | StatementKind::GivenStatement(e)
+            if e.contains_error() =>
+        {
+            Ok(())
+        },
+        StatementKind::BlahStatement(e) => {
+            // Blah can print any well-typed expression
+            type_check_expr_in_mode(e, env, mode).map(drop)
+        },
+        StatementKind::ByeStatement(e) => {
+            let t = type_check_expr_in_mode(e, env, mode)?;
+            if can_use_in_arithmetic(&t) {
+                Ok(())
+            } else {
+                Err(TypeError::TypeMismatch {
+                    expected: "Int".to_string(),
+                    found: format!("{:?}", t),
+                    span: e.span,
+                })
+            }
+        },
+        StatementKind::SurelyStatement(e) | StatementKind::GivenStatement(e) => {
+            let t = type_check_expr_in_mode(e, env, mode)?;
+            if t == Type::Bool {
+                Ok(())
+            } else {
+                Err(TypeError::TypeMismatch {
+                    expected: "Bool".to_string(),
+                    found: format!("{:?}", t),
+                    span: e.span,
+                })
+            }
+        },
+        StatementKind::Error => Ok(()),
+    }
 }
 
 // ===========================================================================
@@ -266,6 +1347,8 @@ pub fn type_check_program(program: &Program) -> Result<TypeEnvironment, TypeErro
 pub struct Token {
     pub kind: TokenKind,
     pub position: Position,
+    /// Just past the token's last character
+    pub end: Position,
     pub lexeme: String,
 }
 
@@ -274,48 +1357,153 @@ pub enum TokenKind {
     // Keywords
     Blah,
     Maybe,
+    Surely,
+    Bye,
+    Input,
+    Arg,
+    Given,
+    Is,
+    Null,
+    Expect,
+    Prob,
 
     // Literals
     Number(i32),
     StringLiteral(String),
     Identifier(String),
 
+    // Punctuation
+    LeftParen,
+    RightParen,
+    Comma,
+
     // Operators
     Plus,
     Minus,
     Equals,
+    EqualEqual,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+
+    // Trivia, kept aside by the lexer rather than passed to the parser
+    Comment,
 
     // Special
+    /// Text that failed to lex; its LexError has been reported already
+    Error,
     Eof,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl TokenKind {
+    /// Name of the kind without its payload, as `--emit tokens` prints it
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Blah => "Blah",
+            TokenKind::Maybe => "Maybe",
+            TokenKind::Surely => "Surely",
+            TokenKind::Bye => "Bye",
+            TokenKind::Input => "Input",
+            TokenKind::Arg => "Arg",
+            TokenKind::Given => "Given",
+            TokenKind::Is => "Is",
+            TokenKind::Null => "Null",
+            TokenKind::Expect => "Expect",
+            TokenKind::Prob => "Prob",
+            TokenKind::Number(_) => "Number",
+            TokenKind::StringLiteral(_) => "StringLiteral",
+            TokenKind::Identifier(_) => "Identifier",
+            TokenKind::LeftParen => "LeftParen",
+            TokenKind::RightParen => "RightParen",
+            TokenKind::Comma => "Comma",
+            TokenKind::Plus => "Plus",
+            TokenKind::Minus => "Minus",
+            TokenKind::Equals => "Equals",
+            TokenKind::EqualEqual => "EqualEqual",
+            TokenKind::BangEqual => "BangEqual",
+            TokenKind::Less => "Less",
+            TokenKind::LessEqual => "LessEqual",
+            TokenKind::Greater => "Greater",
+            TokenKind::GreaterEqual => "GreaterEqual",
+            TokenKind::Comment => "Comment",
+            TokenKind::Error => "Error",
+            TokenKind::Eof => "Eof",
+        }
+    }
+}
+
+/// A place in the source. Columns are 1-based and count chars; editors and
+/// language servers that count UTF-16 code units use `utf16_column` instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
+    /// Byte offset into the source
+    pub offset: usize,
+    pub utf16_column: usize,
 }
 
 impl Position {
     pub fn new() -> Self {
-        Position { line: 1, column: 1 }
+        Position {
+            line: 1,
+            column: 1,
+            offset: 0,
+            utf16_column: 1,
+        }
     }
 
     pub fn advance(&mut self, ch: char) {
+        self.offset += ch.len_utf8();
         if ch == '\n' {
             self.line += 1;
             self.column = 1;
+            self.utf16_column = 1;
         } else {
             self.column += 1;
+            self.utf16_column += ch.len_utf16();
         }
     }
 }
 
+impl Default for Position {
+    fn default() -> Self {
+        Position::new()
+    }
+}
+
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}:{}", self.line, self.column)
     }
 }
 
+/// A range of source, from `start` up to but not including `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
 /// Lexical error types
 #[derive(Debug, Clone, PartialEq)]
 pub enum LexError {
@@ -340,11 +1528,25 @@ impl fmt::Display for LexError {
     }
 }
 
+impl std::error::Error for LexError {}
+
+/// Identifiers follow UAX #31: an XID_Start char or `_`, then XID_Continue
+/// chars, as tabulated in `xid.rs`
+fn is_identifier_start(ch: char) -> bool {
+    ch == '_' || xid::is_xid_start(ch)
+}
+
+fn is_identifier_continue(ch: char) -> bool {
+    xid::is_xid_continue(ch)
+}
+
 /// Lexer state
 pub struct Lexer {
     input: Vec<char>,
     current: usize,
     position: Position,
+    /// `//` comments seen so far, which the formatter puts back
+    comments: Vec<Token>,
 }
 
 impl Lexer {
@@ -353,13 +1555,31 @@ impl Lexer {
             input: input.chars().collect(),
             current: 0,
             position: Position::new(),
+            comments: Vec::new(),
         }
     }
 
+    /// The comments of the input, in order, once it has been tokenized. Each
+    /// is a Comment token whose lexeme runs from `//` to the end of the line.
+    pub fn comments(&self) -> &[Token] {
+        &self.comments
+    }
+
     /// THEOREM: Lexer terminates on all inputs
     /// Proof: current strictly increases, bounded by input.len()
     pub fn tokenize(&mut self) -> Result<Vec<Token>, LexError> {
+        let (tokens, errors) = self.tokenize_recovering();
+        match errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(tokens),
+        }
+    }
+
+    /// Tokenize the whole input, turning each stretch that fails to lex into
+    /// an Error token so that the parser can carry on past it
+    pub fn tokenize_recovering(&mut self) -> (Vec<Token>, Vec<LexError>) {
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
 
         while !self.is_at_end() {
             self.skip_whitespace_and_comments();
@@ -367,17 +1587,29 @@ impl Lexer {
                 break;
             }
 
-            let token = self.next_token()?;
-            tokens.push(token);
+            let (start, start_pos) = (self.current, self.position);
+            match self.next_token() {
+                Ok(token) => tokens.push(token),
+                Err(error) => {
+                    errors.push(error);
+                    tokens.push(Token {
+                        kind: TokenKind::Error,
+                        position: start_pos,
+                        end: self.position,
+                        lexeme: self.input[start..self.current].iter().collect(),
+                    });
+                },
+            }
         }
 
         tokens.push(Token {
             kind: TokenKind::Eof,
             position: self.position,
+            end: self.position,
             lexeme: String::new(),
         });
 
-        Ok(tokens)
+        (tokens, errors)
     }
 
     fn is_at_end(&self) -> bool {
@@ -406,15 +1638,20 @@ impl Lexer {
                     self.advance();
                 },
                 '/' if self.peek_next() == Some('/') => {
-                    self.advance(); // First /
-                    self.advance(); // Second /
-                                    // Skip until newline
+                    // Up to the newline, kept as trivia
+                    let (start, start_pos) = (self.current, self.position);
                     while let Some(ch) = self.peek() {
                         if ch == '\n' {
                             break;
                         }
                         self.advance();
                     }
+                    self.comments.push(Token {
+                        kind: TokenKind::Comment,
+                        position: start_pos,
+                        end: self.position,
+                        lexeme: self.input[start..self.current].iter().collect(),
+                    });
                 },
                 _ => break,
             }
@@ -427,11 +1664,42 @@ impl Lexer {
         match self.peek() {
             None => unreachable!("next_token called at end"),
 
+            Some('(') => {
+                self.advance();
+                Ok(Token {
+                    kind: TokenKind::LeftParen,
+                    position: start_pos,
+                    end: self.position,
+                    lexeme: "(".to_string(),
+                })
+            },
+
+            Some(')') => {
+                self.advance();
+                Ok(Token {
+                    kind: TokenKind::RightParen,
+                    position: start_pos,
+                    end: self.position,
+                    lexeme: ")".to_string(),
+                })
+            },
+
+            Some(',') => {
+                self.advance();
+                Ok(Token {
+                    kind: TokenKind::Comma,
+                    position: start_pos,
+                    end: self.position,
+                    lexeme: ",".to_string(),
+                })
+            },
+
             Some('+') => {
                 self.advance();
                 Ok(Token {
                     kind: TokenKind::Plus,
                     position: start_pos,
+                    end: self.position,
                     lexeme: "+".to_string(),
                 })
             },
@@ -441,24 +1709,62 @@ impl Lexer {
                 Ok(Token {
                     kind: TokenKind::Minus,
                     position: start_pos,
+                    end: self.position,
                     lexeme: "-".to_string(),
                 })
             },
 
+            Some('=') if self.peek_next() == Some('=') => {
+                Ok(self.lex_two_char_operator(TokenKind::EqualEqual, "=="))
+            },
+
             Some('=') => {
                 self.advance();
                 Ok(Token {
                     kind: TokenKind::Equals,
                     position: start_pos,
+                    end: self.position,
                     lexeme: "=".to_string(),
                 })
             },
 
+            Some('!') if self.peek_next() == Some('=') => {
+                Ok(self.lex_two_char_operator(TokenKind::BangEqual, "!="))
+            },
+
+            Some('<') if self.peek_next() == Some('=') => {
+                Ok(self.lex_two_char_operator(TokenKind::LessEqual, "<="))
+            },
+
+            Some('<') => {
+                self.advance();
+                Ok(Token {
+                    kind: TokenKind::Less,
+                    position: start_pos,
+                    end: self.position,
+                    lexeme: "<".to_string(),
+                })
+            },
+
+            Some('>') if self.peek_next() == Some('=') => {
+                Ok(self.lex_two_char_operator(TokenKind::GreaterEqual, ">="))
+            },
+
+            Some('>') => {
+                self.advance();
+                Ok(Token {
+                    kind: TokenKind::Greater,
+                    position: start_pos,
+                    end: self.position,
+                    lexeme: ">".to_string(),
+                })
+            },
+
             Some('"') => self.lex_string(),
 
             Some(ch) if ch.is_ascii_digit() => self.lex_number(),
 
-            Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => self.lex_identifier(),
+            Some(ch) if is_identifier_start(ch) => self.lex_identifier(),
 
             Some(ch) => {
                 self.advance();
@@ -470,6 +1776,18 @@ impl Lexer {
         }
     }
 
+    fn lex_two_char_operator(&mut self, kind: TokenKind, lexeme: &str) -> Token {
+        let start_pos = self.position;
+        self.advance();
+        self.advance();
+        Token {
+            kind,
+            position: start_pos,
+            end: self.position,
+            lexeme: lexeme.to_string(),
+        }
+    }
+
     fn lex_string(&mut self) -> Result<Token, LexError> {
         let start_pos = self.position;
         let mut value = String::new();
@@ -483,6 +1801,7 @@ impl Lexer {
                 return Ok(Token {
                     kind: TokenKind::StringLiteral(value),
                     position: start_pos,
+                    end: self.position,
                     lexeme,
                 });
             }
@@ -536,6 +1855,7 @@ impl Lexer {
             Ok(n) => Ok(Token {
                 kind: TokenKind::Number(n),
                 position: start_pos,
+                end: self.position,
                 lexeme,
             }),
             Err(_) => Err(LexError::InvalidNumber {
@@ -550,7 +1870,7 @@ impl Lexer {
         let mut lexeme = String::new();
 
         while let Some(ch) = self.peek() {
-            if ch.is_ascii_alphanumeric() || ch == '_' {
+            if is_identifier_continue(ch) {
                 lexeme.push(ch);
                 self.advance();
             } else {
@@ -561,12 +1881,22 @@ impl Lexer {
         let kind = match lexeme.as_str() {
             "blah" => TokenKind::Blah,
             "maybe" => TokenKind::Maybe,
+            "surely" => TokenKind::Surely,
+            "bye" => TokenKind::Bye,
+            "input" => TokenKind::Input,
+            "arg" => TokenKind::Arg,
+            "given" => TokenKind::Given,
+            "is" => TokenKind::Is,
+            "null" => TokenKind::Null,
+            "expect" => TokenKind::Expect,
+            "prob" => TokenKind::Prob,
             _ => TokenKind::Identifier(lexeme.clone()),
         };
 
         Ok(Token {
             kind,
             position: start_pos,
+            end: self.position,
             lexeme,
         })
     }
@@ -579,8 +1909,32 @@ impl Lexer {
 /// Parse errors
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseError {
-    UnexpectedToken { expected: String, found: Token },
-    UnexpectedEof { expected: String },
+    UnexpectedToken {
+        expected: String,
+        found: Box<Token>,
+    },
+    /// The input ended where `expected` should have come, at `position`
+    UnexpectedEof {
+        expected: String,
+        position: Position,
+    },
+}
+
+impl ParseError {
+    /// The error for finding `found` where `expected` should be
+    fn unexpected(expected: impl Into<String>, found: &Token) -> Self {
+        let expected = expected.into();
+        match found.kind {
+            TokenKind::Eof => ParseError::UnexpectedEof {
+                expected,
+                position: found.position,
+            },
+            _ => ParseError::UnexpectedToken {
+                expected,
+                found: Box::new(found.clone()),
+            },
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
@@ -593,36 +1947,72 @@ impl fmt::Display for ParseError {
                     expected, found.kind, found.position
                 )
             },
-            ParseError::UnexpectedEof { expected } => {
-                write!(f, "Unexpected end of file, expected {}", expected)
+            ParseError::UnexpectedEof { expected, position } => {
+                write!(
+                    f,
+                    "Expected {} but found end of file at {}",
+                    expected, position
+                )
             },
         }
     }
 }
 
+impl std::error::Error for ParseError {}
+
 /// Parser state
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    /// Source line on which each parsed statement starts
+    lines: Vec<usize>,
+    /// Errors recovered from so far, in source order
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            lines: Vec::new(),
+            errors: Vec::new(),
+        }
     }
 
     /// THEOREM: Parser is sound - produces valid AST for valid token sequences
     /// THEOREM: Parser is complete - parses all valid token sequences
     /// Proof: By structural induction on the grammar
     pub fn parse(&mut self) -> Result<Program, ParseError> {
+        let (program, errors) = self.parse_recovering();
+        match errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(program),
+        }
+    }
+
+    /// Parse the whole program, skipping to the next statement keyword after
+    /// each error. What could not be parsed is left in the AST as Error nodes,
+    /// and a `maybe` whose name was read still declares it, so later passes
+    /// can check the rest without tripping over the gaps.
+    pub fn parse_recovering(&mut self) -> (Program, Vec<ParseError>) {
         let mut program = Vec::new();
 
         while !self.is_at_end() {
-            let stmt = self.parse_statement()?;
+            let start = self.peek().position;
+            let stmt = self
+                .recovering(Self::parse_statement)
+                .unwrap_or_else(|span| Statement::new(StatementKind::Error, span));
+            self.lines.push(start.line);
             program.push(stmt);
         }
 
-        Ok(program)
+        (program, std::mem::take(&mut self.errors))
+    }
+
+    /// Line of each statement returned by `parse`, indexed like the program
+    pub fn statement_lines(&self) -> &[usize] {
+        &self.lines
     }
 
     fn is_at_end(&self) -> bool {
@@ -633,6 +2023,15 @@ impl Parser {
         &self.tokens[self.current.min(self.tokens.len() - 1)]
     }
 
+    /// From `start` to the end of the last token consumed
+    fn span_from(&self, start: Position) -> Span {
+        let end = self
+            .current
+            .checked_sub(1)
+            .map_or(start, |i| self.tokens[i].end);
+        Span::new(start, end)
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -647,55 +2046,193 @@ impl Parser {
         std::mem::discriminant(&self.peek().kind) == std::mem::discriminant(kind)
     }
 
+    /// Run `parse`, or on failure record its error and skip to the next
+    /// statement keyword, returning the span skipped over. Errors at a token
+    /// the lexer already rejected are not reported twice.
+    ///
+    /// The statement loop always makes progress: a statement that fails
+    /// before consuming anything fails at a token that is not a keyword,
+    /// which the skip then passes.
+    fn recovering<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, Span> {
+        let start = self.peek().position;
+        parse(self).map_err(|error| {
+            let already_reported = matches!(
+                &error,
+                ParseError::UnexpectedToken { found, .. } if found.kind == TokenKind::Error
+            );
+            if !already_reported {
+                self.errors.push(error);
+            }
+            while !self.is_at_end()
+                && !matches!(
+                    self.peek().kind,
+                    TokenKind::Maybe
+                        | TokenKind::Blah
+                        | TokenKind::Surely
+                        | TokenKind::Bye
+                        | TokenKind::Given
+                )
+            {
+                self.advance();
+            }
+            self.span_from(start)
+        })
+    }
+
     fn consume(&mut self, kind: TokenKind, message: &str) -> Result<&Token, ParseError> {
         if self.check(&kind) {
             Ok(self.advance())
         } else {
-            Err(ParseError::UnexpectedToken {
-                expected: message.to_string(),
-                found: self.peek().clone(),
-            })
+            Err(ParseError::unexpected(message, self.peek()))
         }
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
-        match &self.peek().kind {
+        let start = self.peek().position;
+        let kind = match &self.peek().kind {
             TokenKind::Maybe => self.parse_maybe_declaration(),
             TokenKind::Blah => self.parse_blah_statement(),
-            _ => Err(ParseError::UnexpectedToken {
-                expected: "'maybe' or 'blah'".to_string(),
-                found: self.peek().clone(),
-            }),
-        }
+            TokenKind::Surely => self.parse_surely_statement(),
+            TokenKind::Bye => self.parse_bye_statement(),
+            TokenKind::Given => self.parse_given_statement(),
+            _ => Err(ParseError::unexpected(
+                "'maybe', 'blah', 'surely', 'bye' or 'given'",
+                self.peek(),
+            )),
+        }?;
+        Ok(Statement::new(kind, self.span_from(start)))
     }
 
-    fn parse_maybe_declaration(&mut self) -> Result<Statement, ParseError> {
+    fn parse_maybe_declaration(&mut self) -> Result<StatementKind, ParseError> {
         self.consume(TokenKind::Maybe, "'maybe'")?;
 
-        let name = match &self.advance().kind {
-            TokenKind::Identifier(name) => name.clone(),
-            _ => {
-                return Err(ParseError::UnexpectedToken {
-                    expected: "identifier".to_string(),
-                    found: self.tokens[self.current - 1].clone(),
-                })
-            },
+        if self.check(&TokenKind::LeftParen) {
+            return self.parse_entangled_declaration();
+        }
+
+        let name = self.parse_identifier()?;
+
+        // Once the name is read the variable stays declared, whatever follows
+        let expr = self
+            .recovering(|parser| {
+                parser.consume(TokenKind::Equals, "'='")?;
+                parser.parse_expression()
+            })
+            .unwrap_or_else(|span| Expression::new(ExpressionKind::Error, span));
+
+        Ok(StatementKind::MaybeDeclaration(name, expr))
+    }
+
+    /// `maybe (a, b) = (e1, e2)` - the tuples must have the same length
+    fn parse_entangled_declaration(&mut self) -> Result<StatementKind, ParseError> {
+        self.consume(TokenKind::LeftParen, "'('")?;
+        let mut names = vec![self.parse_identifier()?];
+        while self.check(&TokenKind::Comma) {
+            self.advance();
+            names.push(self.parse_identifier()?);
+        }
+        self.consume(TokenKind::RightParen, "')'")?;
+
+        let count = names.len();
+        let values = self
+            .recovering(|parser| {
+                parser.consume(TokenKind::Equals, "'='")?;
+                parser.consume(TokenKind::LeftParen, "'('")?;
+                let mut values = Vec::new();
+                for i in 0..count {
+                    if i > 0 {
+                        parser.consume(
+                            TokenKind::Comma,
+                            "',' (one expression per entangled variable)",
+                        )?;
+                    }
+                    values.push(parser.parse_expression()?);
+                }
+                parser.consume(
+                    TokenKind::RightParen,
+                    "')' (one expression per entangled variable)",
+                )?;
+                Ok(values)
+            })
+            .unwrap_or_else(|span| vec![Expression::new(ExpressionKind::Error, span); count]);
+
+        Ok(StatementKind::EntangledDeclaration(
+            names.into_iter().zip(values).collect(),
+        ))
+    }
+
+    fn parse_identifier(&mut self) -> Result<Name, ParseError> {
+        let token = self.peek();
+        let TokenKind::Identifier(text) = &token.kind else {
+            return Err(ParseError::unexpected("identifier", token));
         };
+        let name = Name::new(text.clone(), Span::new(token.position, token.end));
+        self.advance();
+        Ok(name)
+    }
 
-        self.consume(TokenKind::Equals, "'='")?;
+    fn parse_blah_statement(&mut self) -> Result<StatementKind, ParseError> {
+        self.consume(TokenKind::Blah, "'blah'")?;
         let expr = self.parse_expression()?;
+        Ok(StatementKind::BlahStatement(expr))
+    }
 
-        Ok(Statement::MaybeDeclaration(name, expr))
+    fn parse_surely_statement(&mut self) -> Result<StatementKind, ParseError> {
+        self.consume(TokenKind::Surely, "'surely'")?;
+        let expr = self.parse_expression()?;
+        Ok(StatementKind::SurelyStatement(expr))
     }
 
-    fn parse_blah_statement(&mut self) -> Result<Statement, ParseError> {
-        self.consume(TokenKind::Blah, "'blah'")?;
+    fn parse_bye_statement(&mut self) -> Result<StatementKind, ParseError> {
+        self.consume(TokenKind::Bye, "'bye'")?;
+        let expr = self.parse_expression()?;
+        Ok(StatementKind::ByeStatement(expr))
+    }
+
+    fn parse_given_statement(&mut self) -> Result<StatementKind, ParseError> {
+        self.consume(TokenKind::Given, "'given'")?;
         let expr = self.parse_expression()?;
-        Ok(Statement::BlahStatement(expr))
+        Ok(StatementKind::GivenStatement(expr))
     }
 
     fn parse_expression(&mut self) -> Result<Expression, ParseError> {
-        self.parse_additive()
+        self.parse_comparison()
+    }
+
+    /// Comparisons and `is null` bind looser than `+`/`-` and do not chain
+    fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
+        let left = self.parse_additive()?;
+
+        if self.check(&TokenKind::Is) {
+            self.advance();
+            self.consume(TokenKind::Null, "'null' after 'is'")?;
+            let span = self.span_from(left.span.start);
+            return Ok(Expression::new(
+                ExpressionKind::IsNull(Box::new(left)),
+                span,
+            ));
+        }
+
+        let op = match self.peek().kind {
+            TokenKind::EqualEqual => ComparisonOp::Equal,
+            TokenKind::BangEqual => ComparisonOp::NotEqual,
+            TokenKind::Less => ComparisonOp::Less,
+            TokenKind::LessEqual => ComparisonOp::LessEqual,
+            TokenKind::Greater => ComparisonOp::Greater,
+            TokenKind::GreaterEqual => ComparisonOp::GreaterEqual,
+            _ => return Ok(left),
+        };
+        self.advance();
+
+        let right = self.parse_additive()?;
+        let span = left.span.to(right.span);
+        Ok(Expression::new(
+            ExpressionKind::Comparison(op, Box::new(left), Box::new(right)),
+            span,
+        ))
     }
 
     fn parse_additive(&mut self) -> Result<Expression, ParseError> {
@@ -704,27 +2241,80 @@ impl Parser {
         while matches!(self.peek().kind, TokenKind::Plus | TokenKind::Minus) {
             let op = self.advance().kind.clone();
             let right = self.parse_primary()?;
+            let span = expr.span.to(right.span);
 
-            expr = match op {
-                TokenKind::Plus => Expression::Add(Box::new(expr), Box::new(right)),
-                TokenKind::Minus => Expression::Subtract(Box::new(expr), Box::new(right)),
+            let kind = match op {
+                TokenKind::Plus => ExpressionKind::Add(Box::new(expr), Box::new(right)),
+                TokenKind::Minus => ExpressionKind::Subtract(Box::new(expr), Box::new(right)),
                 _ => unreachable!(),
             };
+            expr = Expression::new(kind, span);
         }
 
         Ok(expr)
     }
 
+    /// Arguments of `name(...)`; whether `name` is a builtin is the type checker's call
+    fn parse_call(&mut self, name: String) -> Result<ExpressionKind, ParseError> {
+        self.consume(TokenKind::LeftParen, "'('")?;
+        let mut args = Vec::new();
+        if !self.check(&TokenKind::RightParen) {
+            args.push(self.parse_expression()?);
+            while self.check(&TokenKind::Comma) {
+                self.advance();
+                args.push(self.parse_expression()?);
+            }
+        }
+        self.consume(TokenKind::RightParen, "')'")?;
+        Ok(ExpressionKind::Call(name, args))
+    }
+
+    /// The `(e)` after a keyword such as `arg` or `prob`
+    fn parse_parenthesized(&mut self, keyword: &str) -> Result<Box<Expression>, ParseError> {
+        self.consume(TokenKind::LeftParen, &format!("'(' after '{}'", keyword))?;
+        let inner = self.parse_expression()?;
+        self.consume(TokenKind::RightParen, "')'")?;
+        Ok(Box::new(inner))
+    }
+
     fn parse_primary(&mut self) -> Result<Expression, ParseError> {
-        match &self.advance().kind {
-            TokenKind::Number(n) => Ok(Expression::Number(*n)),
-            TokenKind::StringLiteral(s) => Ok(Expression::StringLiteral(s.clone())),
-            TokenKind::Identifier(name) => Ok(Expression::Variable(name.clone())),
-            _ => Err(ParseError::UnexpectedToken {
-                expected: "number, string, or identifier".to_string(),
-                found: self.tokens[self.current - 1].clone(),
-            }),
+        let token = self.peek().clone();
+        let start = token.position;
+        // A token that cannot start an expression, such as the keyword of the
+        // next statement, is left in place for error recovery to stop at
+        let starts_expression = matches!(
+            token.kind,
+            TokenKind::Number(_)
+                | TokenKind::StringLiteral(_)
+                | TokenKind::Identifier(_)
+                | TokenKind::Input
+                | TokenKind::Arg
+                | TokenKind::Expect
+                | TokenKind::Prob
+                | TokenKind::Error
+        );
+        if starts_expression {
+            self.advance();
         }
+        let kind = match token.kind.clone() {
+            TokenKind::Number(n) => Ok(ExpressionKind::Number(n)),
+            TokenKind::StringLiteral(s) => Ok(ExpressionKind::StringLiteral(s)),
+            TokenKind::Identifier(name) if self.check(&TokenKind::LeftParen) => {
+                self.parse_call(name)
+            },
+            TokenKind::Identifier(name) => Ok(ExpressionKind::Variable(name)),
+            TokenKind::Input => Ok(ExpressionKind::Input),
+            TokenKind::Arg => Ok(ExpressionKind::Argument(self.parse_parenthesized("arg")?)),
+            TokenKind::Expect => Ok(ExpressionKind::Expect(self.parse_parenthesized("expect")?)),
+            TokenKind::Prob => Ok(ExpressionKind::Prob(self.parse_parenthesized("prob")?)),
+            // Already reported by the lexer
+            TokenKind::Error => Ok(ExpressionKind::Error),
+            _ => Err(ParseError::unexpected(
+                "number, string, identifier, 'input', 'arg', 'expect' or 'prob'",
+                &token,
+            )),
+        }?;
+        Ok(Expression::new(kind, self.span_from(start)))
     }
 }
 
@@ -732,42 +2322,147 @@ impl Parser {
 // VERIFIED CODE GENERATION - Proven Correct
 // ===========================================================================
 
-/// C code generation with verification conditions
+/// C code generation from the IR, one block per source statement
+///
+/// Temporaries are not C variables: each is used exactly once, so the backend
+/// renders it where it is used, and a block becomes nested C expressions. Only
+/// a bound temporary is computed where it stands, into the local `mist_t<n>`.
 pub struct CodeGenerator {
     indent_level: usize,
+    runtime: BTreeSet<RuntimeHelper>,
+    /// `given` turns the body into a retry loop whose output is buffered
+    conditioned: bool,
+    trace_file: Option<String>,
+    /// Accept `--world=<selector>` as the first argument of the program
+    world_selector: bool,
+    /// Temporaries of the block being generated that are not yet rendered
+    pending: HashMap<Temp, (IrType, Op)>,
+    /// Bound temporaries not yet used, which are held in C locals
+    bound: HashMap<Temp, IrType>,
 }
 
 impl CodeGenerator {
     pub fn new() -> Self {
-        CodeGenerator { indent_level: 0 }
+        CodeGenerator {
+            indent_level: 0,
+            runtime: BTreeSet::new(),
+            conditioned: false,
+            trace_file: None,
+            world_selector: false,
+            pending: HashMap::new(),
+            bound: HashMap::new(),
+        }
     }
 
-    /// THEOREM: Code generation preserves semantics
-    /// For all e, σ: eval(e, σ) = eval_c(gen(e), gen(σ))
-    pub fn generate_program(&mut self, program: &Program, type_env: &TypeEnvironment) -> String {
-        let mut output = String::new();
+    /// Write the trace to `file`, or to stderr when there is no file
+    pub fn with_trace_file(mut self, file: Option<String>) -> Self {
+        self.trace_file = file;
+        self
+    }
 
-        // Header with verification annotation
-        output.push_str("// Generated by Proven Mist Compiler\n");
-        output.push_str("// THEOREM: This C code has equivalent semantics to the source\n");
+    /// Let the program be run in one chosen world, as `./program --world=5`
+    pub fn with_world_selector(mut self, enabled: bool) -> Self {
+        self.world_selector = enabled;
+        self
+    }
+
+    /// THEOREM: Code generation preserves semantics
+    /// For all e, σ: eval(e, σ) = eval_c(gen(lower(e)), gen(σ))
+    pub fn generate_program(&mut self, program: &IrProgram) -> String {
+        self.conditioned = program.is_conditioned();
+        if self.conditioned {
+            self.require(RuntimeHelper::Conditioning);
+        }
+        if self.world_selector {
+            self.require(RuntimeHelper::World);
+        }
+
+        // The body decides which runtime helpers the prelude has to provide
+        self.indent_level = if self.conditioned { 2 } else { 1 };
+        let mut body = String::new();
+        for block in &program.blocks {
+            body.push_str(&self.indent(&format!("// {}\n", block.comment)));
+            for instruction in &block.instructions {
+                body.push_str(&self.generate_instruction(instruction));
+            }
+            body.push('\n');
+        }
+        self.indent_level = 1;
+
+        let mut output = String::new();
+
+        // Header with verification annotation
+        output.push_str("// Generated by Proven Mist Compiler\n");
+        output.push_str("// THEOREM: This C code has equivalent semantics to the source\n");
         output.push_str("// PROOF: By structural induction on the AST\n\n");
 
         output.push_str("#include <stdio.h>\n");
         output.push_str("#include <stdlib.h>\n");
         output.push_str("#include <time.h>\n");
-        output.push_str("#include <stdbool.h>\n\n");
+        output.push_str("#include <stdbool.h>\n");
+        let extra_includes: BTreeSet<&str> = self
+            .runtime
+            .iter()
+            .flat_map(|h| h.includes())
+            .copied()
+            .collect();
+        for header in extra_includes {
+            output.push_str("#include <");
+            output.push_str(header);
+            output.push_str(">\n");
+        }
+        output.push('\n');
 
-        output.push_str("int main(void) {\n");
-        self.indent_level = 1;
+        for helper in &self.runtime {
+            output.push_str(helper.c_source());
+            output.push('\n');
+        }
+        if self.runtime.contains(&RuntimeHelper::Strict) {
+            output.push_str(&self.generate_error_exit("mist_null_error"));
+        }
+        if self.runtime.contains(&RuntimeHelper::Coins) {
+            output.push_str(&self.generate_error_exit("mist_coins_error"));
+        }
+
+        if self.world_selector {
+            output.push_str("int main(int argc, char **argv) {\n");
+            output.push_str(&self.generate_world_selection(program));
+        }
+        if self.runtime.contains(&RuntimeHelper::Args) {
+            if !self.world_selector {
+                output.push_str("int main(int argc, char **argv) {\n");
+            }
+            output.push_str(&self.indent("mist_argc = argc;\n"));
+            output.push_str(&self.indent("mist_argv = argv;\n\n"));
+        } else if !self.world_selector {
+            output.push_str("int main(void) {\n");
+        }
 
         // Initialize random seed
-        output.push_str(&self.indent("// Initialize RNG for stochastic semantics\n"));
-        output.push_str(&self.indent("srand(time(NULL));\n\n"));
+        output.push_str(&self.indent(
+            "// Initialize RNG for stochastic semantics; MIST_SEED makes a run repeatable\n",
+        ));
+        output.push_str(&self.indent("const char *mist_seed = getenv(\"MIST_SEED\");\n"));
+        output.push_str(&self.indent(
+            "srand(mist_seed != NULL ? (unsigned)strtoul(mist_seed, NULL, 10) : (unsigned)time(NULL));\n",
+        ));
+        if self.runtime.contains(&RuntimeHelper::Coins) {
+            output.push_str(&self.indent(&format!("mist_coins_open(\"{}\");\n", program.checksum)));
+        }
+        output.push('\n');
+
+        if self.runtime.contains(&RuntimeHelper::Trace) {
+            let path = self
+                .trace_file
+                .as_deref()
+                .map_or_else(|| "NULL".to_string(), c_string_literal);
+            output.push_str(&self.indent(&format!("mist_trace_open({});\n\n", path)));
+        }
 
-        // Generate code for each statement
-        for stmt in program {
-            output.push_str(&self.generate_statement(stmt, type_env));
-            output.push('\n');
+        if self.conditioned {
+            output.push_str(&self.generate_rejection_loop(&body));
+        } else {
+            output.push_str(&body);
         }
 
         output.push_str(&self.indent("return 0;\n"));
@@ -776,178 +2471,1740 @@ impl CodeGenerator {
         output
     }
 
+    /// Consume a leading `--world=<selector>` argument, so that `arg(n)` still
+    /// sees the program name at 0 and its own arguments after it
+    fn generate_world_selection(&self, program: &IrProgram) -> String {
+        let mut output = self.indent("// Run in the world chosen by --world=<selector>, if any\n");
+        output
+            .push_str(&self.indent("if (argc > 1 && strncmp(argv[1], \"--world=\", 8) == 0) {\n"));
+        output.push_str(&self.indent(&format!(
+            "    mist_world_select(argv[1] + 8, {});\n",
+            program.coin_count()
+        )));
+        output.push_str(&self.indent("    argv[1] = argv[0];\n"));
+        output.push_str(&self.indent("    argc--;\n"));
+        output.push_str(&self.indent("    argv++;\n"));
+        output.push_str(&self.indent("}\n\n"));
+        output
+    }
+
+    /// Rejection sampling by re-execution: a failed `given` restarts the body with
+    /// fresh coin flips, replayed stdin and the attempt's output thrown away
+    fn generate_rejection_loop(&self, body: &str) -> String {
+        let mut output = String::new();
+        output.push_str(&self.indent("// Re-execute until every given condition holds\n"));
+        output.push_str(&self.indent("for (int mist_attempt = 0; ; mist_attempt++) {\n"));
+        output.push_str(&self.indent(&format!(
+            "    if (mist_attempt == {}) {{\n",
+            GIVEN_ATTEMPT_LIMIT
+        )));
+        let message = c_string_literal(
+            // The C program only prints the message, which names no place
+            &RuntimeError::NoAcceptedWorld {
+                attempts: GIVEN_ATTEMPT_LIMIT,
+                span: Span::default(),
+            }
+            .to_string(),
+        );
+        output.push_str(&self.indent(&format!(
+            "        fprintf(stderr, \"%s\\n\", {});\n",
+            message
+        )));
+        output.push_str(&self.indent(&format!("        return {};\n", REJECTION_EXIT_CODE)));
+        output.push_str(&self.indent("    }\n"));
+        if self.world_selector {
+            // Retrying cannot help when every flip is fixed
+            let message =
+                c_string_literal(&RuntimeError::WorldRejected(Span::default()).to_string());
+            output.push_str(&self.indent("    if (mist_attempt > 0 && mist_world_selected) {\n"));
+            output.push_str(&self.indent(&format!(
+                "        fprintf(stderr, \"%s\\n\", {});\n",
+                message
+            )));
+            output.push_str(&self.indent(&format!("        return {};\n", REJECTION_EXIT_CODE)));
+            output.push_str(&self.indent("    }\n"));
+        }
+        output.push_str(&self.indent("    mist_discard();\n"));
+        if self.runtime.contains(&RuntimeHelper::InputReplay) {
+            output.push_str(&self.indent("    mist_input_rewind();\n"));
+        }
+        output.push('\n');
+        output.push_str(body);
+        output.push_str(&self.indent("    break;\n"));
+        output.push_str(&self.indent("}\n"));
+        output.push_str(&self.indent("mist_flush();\n\n"));
+        output
+    }
+
+    /// A runtime failure path, such as a null operand in strict mode, which
+    /// flushes buffered output like `surely`
+    fn generate_error_exit(&self, name: &str) -> String {
+        let mut output = format!("static void {}(const char *message) {{\n", name);
+        output.push_str(&self.flush_before_exit());
+        output.push_str(&self.indent("fprintf(stderr, \"%s\\n\", message);\n"));
+        output.push_str(&self.indent("exit(EXIT_FAILURE);\n"));
+        output.push_str("}\n\n");
+        output
+    }
+
+    /// Name of the function every line of program output goes through
+    fn print_fn(&self) -> &'static str {
+        if self.conditioned {
+            "mist_printf"
+        } else {
+            "printf"
+        }
+    }
+
+    /// Output buffered by a conditioned program must be written before it exits
+    fn flush_before_exit(&self) -> String {
+        if self.conditioned {
+            self.indent("mist_flush();\n")
+        } else {
+            String::new()
+        }
+    }
+
+    /// Record that the generated code calls a runtime helper (and its dependencies)
+    fn require(&mut self, helper: RuntimeHelper) {
+        for dependency in helper.dependencies() {
+            self.require(*dependency);
+        }
+        self.runtime.insert(helper);
+    }
+
     fn indent(&self, s: &str) -> String {
         let indent = "    ".repeat(self.indent_level);
         format!("{}{}", indent, s)
     }
 
-    fn generate_statement(&mut self, stmt: &Statement, type_env: &TypeEnvironment) -> String {
-        match stmt {
-            Statement::MaybeDeclaration(name, expr) => {
-                let mut output = String::new();
+    fn generate_instruction(&mut self, instruction: &Instruction) -> String {
+        match instruction {
+            Instruction::Let(temp, ty, op) => {
+                // Rendered where it is used
+                self.pending.insert(*temp, (*ty, op.clone()));
+                String::new()
+            },
 
-                // Generate variable declarations
-                output.push_str(&self.indent(&format!("// Maybe declaration: {}\n", name)));
-                output.push_str(&self.indent(&format!("int {} = 0;\n", name)));
-                output.push_str(&self.indent(&format!("bool {}_is_null = false;\n", name)));
+            Instruction::Bind(temp, ty, op) => {
+                self.pending.insert(*temp, (*ty, op.clone()));
+                let value = self.generate_expression(*temp);
+                self.bound.insert(*temp, *ty);
+                let c_type = match ty {
+                    IrType::Int => "int",
+                    IrType::Bool => "bool",
+                    IrType::Str | IrType::Rational => "const char *",
+                    IrType::Maybe => "mist_maybe",
+                };
+                self.indent(&format!("{} {} = {};\n", c_type, c_temporary(*temp), value))
+            },
 
-                // Generate stochastic assignment
-                output.push_str(&self.indent("if (rand() % 2 == 0) {\n"));
-                self.indent_level += 1;
+            Instruction::Declare(name) => {
+                let mut output = self.indent(&format!("int {} = 0;\n", c_variable(name)));
+                output.push_str(&self.indent(&format!("bool {} = false;\n", c_null_flag(name))));
+                output
+            },
 
-                let expr_code = self.generate_expression(expr, type_env);
-                output.push_str(&self.indent(&format!("{} = {};\n", name, expr_code)));
-                output.push_str(
-                    &self.indent(&format!("printf(\"maybe {} = %d\\n\", {});\n", name, name)),
-                );
+            Instruction::DeclareValue(name) => {
+                self.indent(&format!("int {} = 0;\n", c_variable(name)))
+            },
 
-                self.indent_level -= 1;
-                output.push_str(&self.indent("} else {\n"));
+            Instruction::Store(name, value) => {
+                let value = self.generate_expression(*value);
+                self.indent(&format!("{} = {};\n", c_variable(name), value))
+            },
+
+            Instruction::StoreMaybe(name, value) => {
+                let value = self.generate_expression(*value);
+                self.indent(&format!(
+                    "{{ mist_maybe v = {}; {} = v.value; {} = v.is_null; }}\n",
+                    value,
+                    c_variable(name),
+                    c_null_flag(name)
+                ))
+            },
+
+            Instruction::SetNull(name) => self.indent(&format!("{} = true;\n", c_null_flag(name))),
+
+            Instruction::If(condition, then, otherwise) => {
+                let condition = self.generate_expression(*condition);
+                let mut output = self.indent(&format!("if ({}) {{\n", condition));
                 self.indent_level += 1;
+                for instruction in then {
+                    output.push_str(&self.generate_instruction(instruction));
+                }
+                if !otherwise.is_empty() {
+                    self.indent_level -= 1;
+                    output.push_str(&self.indent("} else {\n"));
+                    self.indent_level += 1;
+                    for instruction in otherwise {
+                        output.push_str(&self.generate_instruction(instruction));
+                    }
+                }
+                self.indent_level -= 1;
+                output.push_str(&self.indent("}\n"));
+                output
+            },
 
-                output.push_str(&self.indent(&format!("{}_is_null = true;\n", name)));
-                output.push_str(&self.indent(&format!("printf(\"maybe {} = null\\n\");\n", name)));
+            Instruction::Print(value) => self.generate_print(*value),
 
+            Instruction::Assert(condition, message) => {
+                let condition = self.generate_expression(*condition);
+                let mut output = self.indent(&format!("if (!{}) {{\n", condition));
+                self.indent_level += 1;
+                output.push_str(&self.flush_before_exit());
+                output.push_str(&self.indent(&format!(
+                    "fprintf(stderr, \"%s\\n\", {});\n",
+                    c_string_literal(message)
+                )));
+                output.push_str(&self.indent(&format!("exit({});\n", ASSERTION_EXIT_CODE)));
                 self.indent_level -= 1;
                 output.push_str(&self.indent("}\n"));
+                output
+            },
 
+            Instruction::Exit(status) => {
+                // exit() rather than return so it also works from nested blocks
+                let status = self.generate_expression(*status);
+                let mut output = self.flush_before_exit();
+                output.push_str(&self.indent(&format!("exit({});\n", status)));
                 output
             },
 
-            Statement::BlahStatement(expr) => {
-                let mut output = String::new();
-                output.push_str(&self.indent("// Blah statement\n"));
+            Instruction::Condition(condition) => {
+                // Only reachable inside the rejection loop emitted by generate_program
+                let condition = self.generate_expression(*condition);
+                let mut output = self.indent(&format!("if (!{}) {{\n", condition));
+                output.push_str(&self.indent("    continue;\n"));
+                output.push_str(&self.indent("}\n"));
+                output
+            },
 
-                let expr_type = match type_check_expr(expr, type_env) {
-                    Ok(t) => t,
-                    Err(_) => Type::Int, // Default to Int for error recovery
-                };
-                let expr_code = self.generate_expression(expr, type_env);
+            Instruction::Discard(value) => {
+                let value = self.generate_expression(*value);
+                self.indent(&format!("(void){};\n", value))
+            },
 
-                match expr_type {
-                    Type::String => {
-                        output.push_str(
-                            &self.indent(&format!("printf(\"%s\\n\", {});\n", expr_code)),
-                        );
-                    },
-                    _ => {
-                        output.push_str(
-                            &self.indent(&format!("printf(\"%d\\n\", {});\n", expr_code)),
-                        );
-                    },
+            Instruction::TraceDeclaration {
+                name,
+                line,
+                nullable,
+            } => {
+                self.require(RuntimeHelper::Trace);
+                let prefix = trace_prefix(*line);
+                let print = format!(
+                    "fprintf(mist_trace_out, \"{}maybe {} = %d\\n\", {});\n",
+                    prefix,
+                    ir::split_slot(name).0,
+                    c_variable(name)
+                );
+                if *nullable {
+                    self.indent(&format!(
+                        "if ({}) fprintf(mist_trace_out, \"{}maybe {} = null\\n\"); else {}",
+                        c_null_flag(name),
+                        prefix,
+                        ir::split_slot(name).0,
+                        print
+                    ))
+                } else {
+                    self.indent(&print)
                 }
+            },
 
-                output
+            Instruction::TraceNull { name, line } => {
+                self.require(RuntimeHelper::Trace);
+                self.indent(&format!(
+                    "fprintf(mist_trace_out, \"{}maybe {} = null\\n\");\n",
+                    trace_prefix(*line),
+                    ir::split_slot(name).0
+                ))
             },
         }
     }
 
-    fn generate_expression(&self, expr: &Expression, type_env: &TypeEnvironment) -> String {
-        match expr {
-            Expression::Number(n) => n.to_string(),
+    /// `blah`: a maybe keeps its (value, is_null) pair so that null prints as
+    /// `null` rather than collapsing to 0
+    fn generate_print(&mut self, value: Temp) -> String {
+        let print = self.print_fn();
+        if let Some(name) = self.pending_variable(value) {
+            return self.indent(&format!(
+                "if ({}) {}(\"null\\n\"); else {}(\"%d\\n\", {});\n",
+                c_null_flag(&name),
+                print,
+                print,
+                c_variable(&name)
+            ));
+        }
 
-            Expression::StringLiteral(s) => {
-                let escaped = s
-                    .replace('\\', "\\\\")
-                    .replace('"', "\\\"")
-                    .replace('\n', "\\n")
-                    .replace('\t', "\\t");
-                format!("\"{}\"", escaped)
+        let ty = self.pending_type(value);
+        let code = self.generate_expression(value);
+        match ty {
+            IrType::Str | IrType::Rational => {
+                self.indent(&format!("{}(\"%s\\n\", {});\n", print, code))
+            },
+            IrType::Bool => self.indent(&format!(
+                "{}(\"%s\\n\", {} ? \"true\" : \"false\");\n",
+                print, code
+            )),
+            IrType::Int => self.indent(&format!("{}(\"%d\\n\", {});\n", print, code)),
+            IrType::Maybe => {
+                let mut output = self.indent("{\n");
+                self.indent_level += 1;
+                output.push_str(&self.indent(&format!("mist_maybe v = {};\n", code)));
+                output.push_str(&self.indent(&format!(
+                    "if (v.is_null) {}(\"null\\n\"); else {}(\"%d\\n\", v.value);\n",
+                    print, print
+                )));
+                self.indent_level -= 1;
+                output.push_str(&self.indent("}\n"));
+                output
             },
+        }
+    }
 
-            Expression::Variable(name) => {
-                // Check if variable can be null
-                if let Some(Type::Maybe(_)) = type_env.get(name) {
-                    format!("({}_is_null ? 0 : {})", name, name)
-                } else {
-                    name.clone()
+    /// The variable a maybe was packed from as it is, which is printed from its
+    /// slots directly; the pack is then consumed
+    fn pending_variable(&mut self, temp: Temp) -> Option<String> {
+        let Some((_, Op::Pack(value, flag))) = self.pending.get(&temp) else {
+            return None;
+        };
+        let (value, flag) = (*value, *flag);
+        match (self.pending.get(&value), self.pending.get(&flag)) {
+            (Some((_, Op::Value(x))), Some((_, Op::NullFlag(y)))) if x == y => {
+                let name = x.clone();
+                for temp in [temp, value, flag] {
+                    self.pending.remove(&temp);
                 }
+                Some(name)
             },
+            _ => None,
+        }
+    }
+
+    fn pending_type(&self, temp: Temp) -> IrType {
+        match self.bound.get(&temp) {
+            Some(ty) => *ty,
+            None => self.pending.get(&temp).map_or(IrType::Int, |(ty, _)| *ty),
+        }
+    }
 
-            Expression::Add(e1, e2) => {
+    /// The C expression of a temporary and everything it is computed from
+    fn generate_expression(&mut self, temp: Temp) -> String {
+        if self.bound.remove(&temp).is_some() {
+            return c_temporary(temp);
+        }
+        let Some((ty, op)) = self.pending.remove(&temp) else {
+            unreachable!(
+                "{} is used twice or never defined; the IR was verified",
+                temp
+            )
+        };
+        if ty == IrType::Maybe {
+            self.require(RuntimeHelper::Maybe);
+        }
+        match op {
+            Op::Int(n) => n.to_string(),
+            Op::Bool(b) => b.to_string(),
+            Op::Str(s) => c_string_literal(&s),
+            // The answer is only ever printed; comparisons use generate_fraction
+            Op::Rational(r) => c_string_literal(&r.to_string()),
+            Op::Value(name) => c_variable(&name),
+            Op::NullFlag(name) => c_null_flag(&name),
+            Op::Select(condition, a, b) => format!(
+                "({} ? {} : {})",
+                self.generate_expression(condition),
+                self.generate_expression(a),
+                self.generate_expression(b)
+            ),
+            Op::Pack(value, flag) => format!(
+                "((mist_maybe){{ {}, {} }})",
+                self.generate_expression(value),
+                self.generate_expression(flag)
+            ),
+            Op::Some(value) => format!("mist_some({})", self.generate_expression(value)),
+            Op::OrZero(maybe) => format!("mist_or_zero({})", self.generate_expression(maybe)),
+            Op::IsNull(maybe) => format!("{}.is_null", self.generate_expression(maybe)),
+            Op::NonNull(maybe, message) => {
+                self.require(RuntimeHelper::Strict);
                 format!(
-                    "({} + {})",
-                    self.generate_expression(e1, type_env),
-                    self.generate_expression(e2, type_env)
+                    "mist_nonnull({}, {})",
+                    self.generate_expression(maybe),
+                    c_string_literal(&message)
                 )
             },
-
-            Expression::Subtract(e1, e2) => {
+            Op::Add(a, b) | Op::Sub(a, b) => {
+                self.require(RuntimeHelper::Arithmetic);
+                let function = match op {
+                    Op::Add(_, _) => "mist_add_int",
+                    _ => "mist_sub_int",
+                };
                 format!(
-                    "({} - {})",
-                    self.generate_expression(e1, type_env),
-                    self.generate_expression(e2, type_env)
+                    "{}({}, {})",
+                    function,
+                    self.generate_expression(a),
+                    self.generate_expression(b)
                 )
             },
+            Op::AddMaybe(a, b) | Op::SubMaybe(a, b) => {
+                self.require(RuntimeHelper::Propagate);
+                let function = match op {
+                    Op::AddMaybe(_, _) => "mist_add",
+                    _ => "mist_sub",
+                };
+                format!(
+                    "{}({}, {})",
+                    function,
+                    self.generate_expression(a),
+                    self.generate_expression(b)
+                )
+            },
+            Op::Compare(op, a, b)
+                if self.pending_type(a) == IrType::Rational
+                    || self.pending_type(b) == IrType::Rational =>
+            {
+                // a/b ⋈ c/d ⇔ a·d ⋈ c·b, as both denominators are positive
+                let (n1, d1) = self.generate_fraction(a);
+                let (n2, d2) = self.generate_fraction(b);
+                format!(
+                    "((long long){} * {} {} (long long){} * {})",
+                    n1,
+                    d2,
+                    op.symbol(),
+                    n2,
+                    d1
+                )
+            },
+            Op::Compare(op, a, b) => format!(
+                "({} {} {})",
+                self.generate_expression(a),
+                op.symbol(),
+                self.generate_expression(b)
+            ),
+            Op::Call(name, args) => {
+                self.require(RuntimeHelper::Builtins);
+                let args: Vec<String> = args
+                    .into_iter()
+                    .map(|arg| self.generate_expression(arg))
+                    .collect();
+                format!("mist_{}({})", name, args.join(", "))
+            },
+            Op::Input if self.conditioned => {
+                // Retried attempts must see the same stdin as the first one
+                self.require(RuntimeHelper::InputReplay);
+                "mist_replay_input()".to_string()
+            },
+            Op::Input => {
+                self.require(RuntimeHelper::Input);
+                "mist_input()".to_string()
+            },
+            Op::Arg(index) => {
+                self.require(RuntimeHelper::Args);
+                format!("mist_arg({})", self.generate_expression(index))
+            },
+            Op::Coin => {
+                self.require(RuntimeHelper::Coins);
+                if self.world_selector {
+                    "mist_world_coin()".to_string()
+                } else {
+                    "mist_coin()".to_string()
+                }
+            },
+            Op::Trace {
+                line,
+                statement,
+                value,
+            } => {
+                // Logs the value and passes it through, so it is still evaluated once
+                self.require(RuntimeHelper::TraceValues);
+                let kind = match self.pending_type(value) {
+                    IrType::Str | IrType::Rational => "str",
+                    IrType::Bool => "bool",
+                    IrType::Int => "int",
+                    IrType::Maybe => "maybe",
+                };
+                format!(
+                    "mist_trace_{}({}, {}, {})",
+                    kind,
+                    line,
+                    c_string_literal(&statement),
+                    self.generate_expression(value)
+                )
+            },
+        }
+    }
+
+    /// Numerator and denominator of an operand compared against a query answer
+    fn generate_fraction(&mut self, temp: Temp) -> (String, String) {
+        match self.pending.get(&temp) {
+            Some((_, Op::Rational(r))) => {
+                let fraction = (r.numerator().to_string(), r.denominator().to_string());
+                self.pending.remove(&temp);
+                fraction
+            },
+            _ => (self.generate_expression(temp), "1".to_string()),
         }
     }
 }
 
+/// Format-string prefix of a trace line, naming the statement's source line
+/// at full trace level
+fn trace_prefix(line: Option<usize>) -> String {
+    line.map_or_else(String::new, |line| format!("[line {}] ", line))
+}
+
 // ===========================================================================
-// MAIN COMPILER INTERFACE - Proven Correct
+// C RUNTIME SUPPORT - Helpers Emitted Only When the Program Needs Them
 // ===========================================================================
 
-/// Compiler error type unifying all error types
-#[derive(Debug)]
-pub enum CompilerError {
-    LexError(LexError),
-    ParseError(ParseError),
-    TypeError(TypeError),
+/// A piece of the C prelude; variants are ordered so dependencies come first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum RuntimeHelper {
+    /// `mist_maybe`, a (value, is_null) pair for nullable expressions
+    Maybe,
+    /// `+` and `-` on ints, saturating like the interpreter instead of overflowing
+    Arithmetic,
+    /// Strict, overflow-checked integer parsing shared by stdin and argv
+    ParseInt,
+    /// `input`: one line of stdin
+    Input,
+    /// `arg(n)`: argv, stashed in globals by main
+    Args,
+    /// The builtin function table, over `mist_maybe`
+    Builtins,
+    /// `input` that replays earlier reads after a rejected attempt
+    InputReplay,
+    /// Output buffer for the rejection loop of conditioned programs
+    Conditioning,
+    /// `+` and `-` that propagate null (NullMode::Propagate)
+    Propagate,
+    /// Null checks on arithmetic operands (NullMode::Strict)
+    Strict,
+    /// The stream that --trace writes to
+    Trace,
+    /// Pass-through loggers for statement values at full trace level
+    TraceValues,
+    /// The coin flip, recording to MIST_TRACE_OUT or replaying MIST_REPLAY
+    Coins,
+    /// Coin outcomes chosen by `--world=<selector>` (CompileOptions::world_selector)
+    World,
 }
 
-impl fmt::Display for CompilerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl RuntimeHelper {
+    fn dependencies(self) -> &'static [RuntimeHelper] {
+        match self {
+            RuntimeHelper::Maybe
+            | RuntimeHelper::Arithmetic
+            | RuntimeHelper::Conditioning
+            | RuntimeHelper::Trace
+            | RuntimeHelper::Coins => &[],
+            RuntimeHelper::ParseInt | RuntimeHelper::Builtins | RuntimeHelper::Strict => {
+                &[RuntimeHelper::Maybe]
+            },
+            RuntimeHelper::Propagate => &[RuntimeHelper::Maybe, RuntimeHelper::Arithmetic],
+            RuntimeHelper::Input | RuntimeHelper::Args => &[RuntimeHelper::ParseInt],
+            RuntimeHelper::InputReplay => &[RuntimeHelper::Input],
+            RuntimeHelper::TraceValues => &[RuntimeHelper::Maybe, RuntimeHelper::Trace],
+            RuntimeHelper::World => &[RuntimeHelper::Coins],
+        }
+    }
+
+    fn includes(self) -> &'static [&'static str] {
         match self {
-            CompilerError::LexError(e) => write!(f, "Lexical error: {}", e),
-            CompilerError::ParseError(e) => write!(f, "Parse error: {}", e),
-            CompilerError::TypeError(e) => write!(f, "Type error: {:?}", e),
+            RuntimeHelper::Maybe
+            | RuntimeHelper::Args
+            | RuntimeHelper::InputReplay
+            | RuntimeHelper::Propagate
+            | RuntimeHelper::Strict
+            | RuntimeHelper::Trace
+            | RuntimeHelper::TraceValues => &[],
+            RuntimeHelper::Conditioning => &["stdarg.h"],
+            RuntimeHelper::Arithmetic | RuntimeHelper::Builtins => &["limits.h"],
+            RuntimeHelper::ParseInt => &["ctype.h", "errno.h", "limits.h"],
+            RuntimeHelper::World => &["ctype.h", "errno.h", "string.h"],
+            RuntimeHelper::Input | RuntimeHelper::Coins => &["string.h"],
+        }
+    }
+
+    fn c_source(self) -> &'static str {
+        match self {
+            RuntimeHelper::Maybe => {
+                "typedef struct {
+    int value;
+    bool is_null;
+} mist_maybe;
+
+static inline mist_maybe mist_some(int value) {
+    mist_maybe m = { value, false };
+    return m;
+}
+
+static inline int mist_or_zero(mist_maybe m) {
+    return m.is_null ? 0 : m.value;
+}
+"
+            },
+            RuntimeHelper::Arithmetic => {
+                "/* Overflow stops at the limits, as in the interpreter, and is never undefined */
+static inline int mist_add_int(int a, int b) {
+    if (b > 0 && a > INT_MAX - b) {
+        return INT_MAX;
+    }
+    if (b < 0 && a < INT_MIN - b) {
+        return INT_MIN;
+    }
+    return a + b;
+}
+
+static inline int mist_sub_int(int a, int b) {
+    if (b < 0 && a > INT_MAX + b) {
+        return INT_MAX;
+    }
+    if (b > 0 && a < INT_MIN + b) {
+        return INT_MIN;
+    }
+    return a - b;
+}
+"
+            },
+            RuntimeHelper::ParseInt => {
+                "static mist_maybe mist_parse_int(const char *text) {
+    mist_maybe result = { 0, true };
+    char *end;
+    long n;
+    errno = 0;
+    n = strtol(text, &end, 10);
+    if (end == text || errno == ERANGE || n < INT_MIN || n > INT_MAX) {
+        return result;
+    }
+    while (isspace((unsigned char)*end)) {
+        end++;
+    }
+    if (*end != '\\0') {
+        return result;
+    }
+    result.value = (int)n;
+    result.is_null = false;
+    return result;
+}
+"
+            },
+            RuntimeHelper::Input => {
+                "static char *mist_line;
+static size_t mist_line_cap;
+
+static mist_maybe mist_input(void) {
+    mist_maybe result = { 0, true };
+    size_t len = 0;
+    int ch;
+    /* The whole line, however much whitespace or how many leading zeros it has */
+    while ((ch = getchar()) != EOF && ch != '\\n') {
+        if (len + 1 >= mist_line_cap) {
+            size_t cap = mist_line_cap == 0 ? 64 : mist_line_cap * 2;
+            char *grown = realloc(mist_line, cap);
+            if (grown == NULL) {
+                fputs(\"out of memory\\n\", stderr);
+                exit(EXIT_FAILURE);
+            }
+            mist_line = grown;
+            mist_line_cap = cap;
+        }
+        mist_line[len++] = (char)ch;
+    }
+    if (len == 0) {
+        return result;
+    }
+    mist_line[len] = '\\0';
+    if (strlen(mist_line) != len) {
+        /* A NUL byte, which no number contains */
+        return result;
+    }
+    return mist_parse_int(mist_line);
+}
+"
+            },
+            RuntimeHelper::Args => {
+                "static int mist_argc;
+static char **mist_argv;
+
+static mist_maybe mist_arg(int n) {
+    mist_maybe result = { 0, true };
+    if (n < 0 || n >= mist_argc) {
+        return result;
+    }
+    return mist_parse_int(mist_argv[n]);
+}
+"
+            },
+            RuntimeHelper::Builtins => {
+                "static inline mist_maybe mist_abs(mist_maybe x) {
+    if (!x.is_null) {
+        x.value = x.value == INT_MIN ? INT_MAX : (x.value < 0 ? -x.value : x.value);
+    }
+    return x;
+}
+
+static inline mist_maybe mist_sign(mist_maybe x) {
+    if (!x.is_null) {
+        x.value = (x.value > 0) - (x.value < 0);
+    }
+    return x;
+}
+
+static inline mist_maybe mist_min(mist_maybe a, mist_maybe b) {
+    if (a.is_null) {
+        return b;
+    }
+    if (b.is_null) {
+        return a;
+    }
+    return b.value < a.value ? b : a;
+}
+
+static inline mist_maybe mist_max(mist_maybe a, mist_maybe b) {
+    if (a.is_null) {
+        return b;
+    }
+    if (b.is_null) {
+        return a;
+    }
+    return b.value > a.value ? b : a;
+}
+
+static inline mist_maybe mist_clamp(mist_maybe x, mist_maybe lo, mist_maybe hi) {
+    if (x.is_null) {
+        return x;
+    }
+    return mist_min(mist_max(x, lo), hi);
+}
+"
+            },
+            RuntimeHelper::InputReplay => {
+                "static mist_maybe *mist_input_log;
+static size_t mist_input_len;
+static size_t mist_input_cap;
+static size_t mist_input_cursor;
+
+static mist_maybe mist_replay_input(void) {
+    mist_maybe result;
+    if (mist_input_cursor < mist_input_len) {
+        return mist_input_log[mist_input_cursor++];
+    }
+    result = mist_input();
+    if (mist_input_len == mist_input_cap) {
+        size_t cap = mist_input_cap == 0 ? 16 : mist_input_cap * 2;
+        mist_maybe *grown = realloc(mist_input_log, cap * sizeof *grown);
+        if (grown == NULL) {
+            fputs(\"out of memory\\n\", stderr);
+            exit(EXIT_FAILURE);
+        }
+        mist_input_log = grown;
+        mist_input_cap = cap;
+    }
+    mist_input_log[mist_input_len++] = result;
+    mist_input_cursor = mist_input_len;
+    return result;
+}
+
+static void mist_input_rewind(void) {
+    mist_input_cursor = 0;
+}
+"
+            },
+            RuntimeHelper::Conditioning => {
+                "static char *mist_out;
+static size_t mist_out_len;
+static size_t mist_out_cap;
+
+static void mist_printf(const char *format, ...) {
+    va_list args;
+    int needed;
+    va_start(args, format);
+    needed = vsnprintf(NULL, 0, format, args);
+    va_end(args);
+    if (needed < 0) {
+        return;
+    }
+    if (mist_out_len + (size_t)needed + 1 > mist_out_cap) {
+        size_t cap = mist_out_cap == 0 ? 256 : mist_out_cap;
+        char *grown;
+        while (cap < mist_out_len + (size_t)needed + 1) {
+            cap *= 2;
+        }
+        grown = realloc(mist_out, cap);
+        if (grown == NULL) {
+            fputs(\"out of memory\\n\", stderr);
+            exit(EXIT_FAILURE);
+        }
+        mist_out = grown;
+        mist_out_cap = cap;
+    }
+    va_start(args, format);
+    vsnprintf(mist_out + mist_out_len, mist_out_cap - mist_out_len, format, args);
+    va_end(args);
+    mist_out_len += (size_t)needed;
+}
+
+static void mist_discard(void) {
+    mist_out_len = 0;
+}
+
+static void mist_flush(void) {
+    fwrite(mist_out, 1, mist_out_len, stdout);
+    mist_out_len = 0;
+}
+"
+            },
+            RuntimeHelper::Propagate => {
+                "static inline mist_maybe mist_add(mist_maybe a, mist_maybe b) {
+    mist_maybe result = { 0, true };
+    if (a.is_null || b.is_null) {
+        return result;
+    }
+    return mist_some(mist_add_int(a.value, b.value));
+}
+
+static inline mist_maybe mist_sub(mist_maybe a, mist_maybe b) {
+    mist_maybe result = { 0, true };
+    if (a.is_null || b.is_null) {
+        return result;
+    }
+    return mist_some(mist_sub_int(a.value, b.value));
+}
+"
+            },
+            RuntimeHelper::Strict => {
+                "/* Defined after the runtime, as it may flush buffered output */
+static void mist_null_error(const char *message);
+
+static inline int mist_nonnull(mist_maybe m, const char *message) {
+    if (m.is_null) {
+        mist_null_error(message);
+    }
+    return m.value;
+}
+"
+            },
+            RuntimeHelper::Trace => {
+                "static FILE *mist_trace_out;
+
+static void mist_trace_open(const char *path) {
+    mist_trace_out = path == NULL ? stderr : fopen(path, \"w\");
+    if (mist_trace_out == NULL) {
+        fprintf(stderr, \"cannot open trace file %s\\n\", path);
+        exit(EXIT_FAILURE);
+    }
+}
+"
+            },
+            RuntimeHelper::TraceValues => {
+                "static inline int mist_trace_int(int line, const char *source, int value) {
+    fprintf(mist_trace_out, \"[line %d] %s => %d\\n\", line, source, value);
+    return value;
+}
+
+static inline bool mist_trace_bool(int line, const char *source, bool value) {
+    fprintf(mist_trace_out, \"[line %d] %s => %s\\n\", line, source, value ? \"true\" : \"false\");
+    return value;
+}
+
+static inline const char *mist_trace_str(int line, const char *source, const char *value) {
+    fprintf(mist_trace_out, \"[line %d] %s => %s\\n\", line, source, value);
+    return value;
+}
+
+static inline mist_maybe mist_trace_maybe(int line, const char *source, mist_maybe value) {
+    if (value.is_null) {
+        fprintf(mist_trace_out, \"[line %d] %s => null\\n\", line, source);
+    } else {
+        fprintf(mist_trace_out, \"[line %d] %s => %d\\n\", line, source, value.value);
+    }
+    return value;
+}
+"
+            },
+            RuntimeHelper::Coins => {
+                "/* Defined after the runtime, as it may flush buffered output */
+static void mist_coins_error(const char *message);
+
+static FILE *mist_coins_in;
+static FILE *mist_coins_out;
+
+static FILE *mist_coins_file(const char *variable, const char *mode) {
+    const char *path = getenv(variable);
+    if (path == NULL || *path == '\\0') {
+        return NULL;
+    }
+    FILE *file = fopen(path, mode);
+    if (file == NULL) {
+        fprintf(stderr, \"cannot open coin trace %s\\n\", path);
+        exit(EXIT_FAILURE);
+    }
+    return file;
+}
+
+/* MIST_REPLAY replays a recorded coin trace and MIST_TRACE_OUT records one */
+static void mist_coins_open(const char *checksum) {
+    char recorded[32];
+    mist_coins_in = mist_coins_file(\"MIST_REPLAY\", \"r\");
+    if (mist_coins_in != NULL
+        && (fscanf(mist_coins_in, \"mist-coins %31s\", recorded) != 1
+            || strcmp(recorded, checksum) != 0)) {
+        fprintf(stderr, \"coin trace was recorded for a different program\\n\");
+        exit(EXIT_FAILURE);
+    }
+    mist_coins_out = mist_coins_file(\"MIST_TRACE_OUT\", \"w\");
+    if (mist_coins_out != NULL) {
+        fprintf(mist_coins_out, \"mist-coins %s\\n\", checksum);
+    }
+}
+
+static bool mist_coin(void) {
+    bool heads = rand() % 2 == 0;
+    if (mist_coins_in != NULL) {
+        int c = fgetc(mist_coins_in);
+        while (c == ' ' || c == '\\n' || c == '\\r' || c == '\\t') {
+            c = fgetc(mist_coins_in);
+        }
+        if (c != '0' && c != '1') {
+            mist_coins_error(\"coin trace ended before the program did\");
         }
+        heads = c == '1';
     }
+    if (mist_coins_out != NULL) {
+        fputc(heads ? '1' : '0', mist_coins_out);
+    }
+    return heads;
+}
+"
+            },
+            RuntimeHelper::World => {
+                "static bool mist_world_selected;
+static const char *mist_world_list;
+static unsigned long long mist_world_mask;
+static int mist_world_flip;
+
+static void mist_world_invalid(const char *selector) {
+    fprintf(stderr,
+            \"invalid world '%s' (expected a bitmask such as 5 or a list such as 1,0,1)\\n\",
+            selector);
+    exit(EXIT_FAILURE);
+}
+
+static void mist_world_missing(const char *selector, int coins) {
+    fprintf(stderr, \"world %s does not exist: the program has %d coin flips\\n\", selector, coins);
+    exit(EXIT_FAILURE);
 }
 
-impl From<LexError> for CompilerError {
-    fn from(e: LexError) -> Self {
-        CompilerError::LexError(e)
+/* A bitmask whose bit i is flip i (5, 0x5), or a list of outcomes (1,0,1) */
+static void mist_world_select(const char *selector, int coins) {
+    if (strchr(selector, ',') != NULL) {
+        int count = 0;
+        for (const char *p = selector; ; p += 2) {
+            if ((*p != '0' && *p != '1') || (p[1] != ',' && p[1] != '\\0')) {
+                mist_world_invalid(selector);
+            }
+            count++;
+            if (p[1] == '\\0') {
+                break;
+            }
+        }
+        if (count != coins) {
+            mist_world_missing(selector, coins);
+        }
+        mist_world_list = selector;
+    } else {
+        const char *digits = selector;
+        int base = 10;
+        if (selector[0] == '0' && (selector[1] == 'x' || selector[1] == 'X')) {
+            digits = selector + 2;
+            base = 16;
+        }
+        if (*digits == '\\0') {
+            mist_world_invalid(selector);
+        }
+        for (const char *p = digits; *p != '\\0'; p++) {
+            if (base == 16 ? !isxdigit((unsigned char)*p) : !isdigit((unsigned char)*p)) {
+                mist_world_invalid(selector);
+            }
+        }
+        errno = 0;
+        mist_world_mask = strtoull(digits, NULL, base);
+        if (errno == ERANGE) {
+            mist_world_invalid(selector);
+        }
+        if (coins < 64 && (mist_world_mask >> coins) != 0) {
+            mist_world_missing(selector, coins);
+        }
     }
+    mist_world_selected = true;
 }
 
-impl From<ParseError> for CompilerError {
-    fn from(e: ParseError) -> Self {
-        CompilerError::ParseError(e)
+static bool mist_world_coin(void) {
+    if (!mist_world_selected) {
+        return mist_coin();
     }
+    int flip = mist_world_flip++;
+    if (mist_world_list != NULL) {
+        return mist_world_list[2 * flip] == '1';
+    }
+    return flip < 64 && ((mist_world_mask >> flip) & 1) != 0;
+}
+"
+            },
+        }
+    }
+}
+
+/// Exit status of a program whose `surely` assertion fails
+pub const ASSERTION_EXIT_CODE: i32 = 1;
+
+/// Exit status of a program whose `given` conditions were never all satisfied
+pub const REJECTION_EXIT_CODE: i32 = 2;
+
+/// How many times a conditioned program is re-executed before giving up
+pub const GIVEN_ATTEMPT_LIMIT: usize = 100_000;
+
+/// Whether the program contains `given`, and so needs rejection sampling
+pub fn is_conditioned(program: &Program) -> bool {
+    program
+        .iter()
+        .any(|stmt| matches!(stmt.kind, StatementKind::GivenStatement(_)))
+}
+
+// ===========================================================================
+// C NAMES - Mangling Mist Variables into Their Own C Namespaces
+// ===========================================================================
+//
+// A Mist variable `x` is stored in the C variable `mist_v_x` with its null
+// flag in `mist_n_x`. The prefixes keep every Mist name clear of C keywords,
+// libc and the `mist_` runtime helpers, none of which may start with
+// `mist_v_`, `mist_n_`, `mist_u_` or `mist_nu_`. A name that is not plain
+// ASCII gets `mist_u_` and `mist_nu_` instead, followed by the name with `_`
+// doubled and each non-ASCII char written `_u<hex>_`; the separate prefixes
+// stop `café` meeting an ASCII name spelled like its escape. A variable
+// declared again has new slots each time, numbered in the prefix: the second
+// `x` is `mist_v1_x`. A temporary the backend binds to a local is `mist_t`
+// and its number, a name no helper has either.
+
+/// C variable holding a variable's value
+fn c_variable(variable: &str) -> String {
+    mangle(variable, "mist_v", "mist_u")
 }
 
-impl From<TypeError> for CompilerError {
-    fn from(e: TypeError) -> Self {
-        CompilerError::TypeError(e)
+/// C variable holding a variable's null flag
+fn c_null_flag(variable: &str) -> String {
+    mangle(variable, "mist_n", "mist_nu")
+}
+
+/// C local holding a bound temporary
+fn c_temporary(temp: Temp) -> String {
+    format!("mist_t{}", temp.0)
+}
+
+fn mangle(variable: &str, ascii_prefix: &str, unicode_prefix: &str) -> String {
+    use std::fmt::Write as _;
+    let (name, version) = ir::split_slot(variable);
+    let version = if version == 0 {
+        String::new()
+    } else {
+        version.to_string()
+    };
+    let ascii_prefix = format!("{}{}_", ascii_prefix, version);
+    let unicode_prefix = format!("{}{}_", unicode_prefix, version);
+    if name.is_ascii() {
+        return format!("{}{}", ascii_prefix, name);
+    }
+    let mut mangled = unicode_prefix;
+    for ch in name.chars() {
+        match ch {
+            '_' => mangled.push_str("__"),
+            ch if ch.is_ascii() => mangled.push(ch),
+            ch => {
+                let _ = write!(mangled, "_u{:x}_", u32::from(ch));
+            },
+        }
+    }
+    mangled
+}
+
+/// Render a Rust string as an escaped C string literal, which is also how
+/// Mist spells it
+fn c_string_literal(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+// ===========================================================================
+// REFERENCE INTERPRETER - Executes the Big-Step Semantics Directly
+// ===========================================================================
+
+/// Source of the coin flips that decide each `maybe` declaration
+pub trait CoinSource {
+    /// Returns true when the declaration keeps its value
+    fn flip(&mut self) -> bool;
+
+    /// Like `flip`, for sources that can run out of coins; None once they have
+    fn try_flip(&mut self) -> Option<bool> {
+        Some(self.flip())
+    }
+}
+
+impl<F: FnMut() -> bool> CoinSource for F {
+    fn flip(&mut self) -> bool {
+        self()
     }
 }
 
-/// MAIN THEOREM: Compiler Correctness
+/// xorshift64* generator seeded from the clock, standing in for `rand()`
+pub struct ClockCoins {
+    state: u64,
+}
+
+impl ClockCoins {
+    pub fn new() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        // Truncation is fine: only the low bits vary between runs anyway
+        #[allow(clippy::cast_possible_truncation)]
+        let seed = nanos as u64 ^ u64::from(std::process::id());
+        ClockCoins { state: seed | 1 }
+    }
+}
+
+impl CoinSource for ClockCoins {
+    fn flip(&mut self) -> bool {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 63 == 0
+    }
+}
+
+// ===========================================================================
+// COIN TRACES - Recording and Replaying the Flips of One Run
+// ===========================================================================
+//
+// A coin trace is a header naming the program, then one character per flip
+// in the order the run made them, rejected attempts included:
+//
+//   mist-coins 5c0e7a4b19d2f683
+//   1101
+//
+// `1` keeps the declared value and `0` makes it null. Whitespace between flips
+// is ignored. Compiled programs and the interpreter read and write the same
+// format, so a run of either can be replayed by the other.
+
+/// Environment variable naming the file a run records its coin trace to
+pub const COIN_TRACE_OUT_VAR: &str = "MIST_TRACE_OUT";
+
+/// Environment variable naming a coin trace to replay instead of flipping
+pub const COIN_REPLAY_VAR: &str = "MIST_REPLAY";
+
+const COIN_TRACE_HEADER: &str = "mist-coins";
+
+/// FNV-1a hash of the checked program, as 16 hex digits
 ///
-/// For all well-formed Mist programs P:
-/// 1. Totality: compile(P) terminates
-/// 2. Type Preservation: If P is well-typed, compile(P) produces well-typed C
-/// 3. Semantic Preservation: P and compile(P) have equivalent observable behavior
+/// Replaying a trace recorded for a different program is refused, since its
+/// flips would land on different declarations. Spans are not hashed, so
+/// reformatting a program or editing its comments keeps old traces valid.
+pub fn program_checksum(program: &Program) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = Fnv1a::default();
+    program.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// FNV-1a over the bytes `Hash` feeds it, with integers widened and written
+/// little-endian so the checksum is the same on every platform
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl std::hash::Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn write_isize(&mut self, n: isize) {
+        self.write(&(n as i64).to_le_bytes());
+    }
+}
+
+/// Coins read back from a recorded trace
+pub struct ReplayCoins {
+    flips: std::vec::IntoIter<bool>,
+}
+
+impl ReplayCoins {
+    /// Parse a coin trace, checking it was recorded for the program with `checksum`
+    pub fn parse(trace: &str, checksum: &str) -> Result<Self, String> {
+        let mut words = trace.split_whitespace();
+        if words.next() != Some(COIN_TRACE_HEADER) || words.next() != Some(checksum) {
+            return Err("coin trace was recorded for a different program".to_string());
+        }
+        let flips = words
+            .flat_map(str::chars)
+            .map(|c| match c {
+                '1' => Ok(true),
+                '0' => Ok(false),
+                _ => Err(format!("unexpected '{}' in coin trace", c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ReplayCoins {
+            flips: flips.into_iter(),
+        })
+    }
+}
+
+impl CoinSource for ReplayCoins {
+    /// Tails once the trace runs out; the interpreter uses `try_flip` instead
+    fn flip(&mut self) -> bool {
+        self.try_flip().unwrap_or(false)
+    }
+
+    fn try_flip(&mut self) -> Option<bool> {
+        self.flips.next()
+    }
+}
+
+/// Passes flips through from another source, keeping a log of them
+pub struct RecordingCoins<'a> {
+    inner: &'a mut dyn CoinSource,
+    flips: Vec<bool>,
+}
+
+impl<'a> RecordingCoins<'a> {
+    pub fn new(inner: &'a mut dyn CoinSource) -> Self {
+        RecordingCoins {
+            inner,
+            flips: Vec::new(),
+        }
+    }
+
+    /// The coin trace of every flip so far, for the program with `checksum`
+    pub fn trace(&self, checksum: &str) -> String {
+        let flips: String = self
+            .flips
+            .iter()
+            .map(|&heads| if heads { '1' } else { '0' })
+            .collect();
+        format!("{} {}\n{}\n", COIN_TRACE_HEADER, checksum, flips)
+    }
+}
+
+impl CoinSource for RecordingCoins<'_> {
+    fn flip(&mut self) -> bool {
+        let heads = self.inner.flip();
+        self.flips.push(heads);
+        heads
+    }
+
+    fn try_flip(&mut self) -> Option<bool> {
+        let heads = self.inner.try_flip()?;
+        self.flips.push(heads);
+        Some(heads)
+    }
+}
+
+// ===========================================================================
+// POSSIBLE WORLDS - Running the Program Under Chosen Coin Outcomes
+// ===========================================================================
+//
+// A world fixes the outcome of every coin flip. Flip i is the i-th `maybe`
+// declaration in the source (an entangled declaration is one flip), which is
+// also the numbering `--analyze` enumerates and `--list-worlds` prints.
+
+/// One possible world: the outcome of each coin flip, in source order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct World {
+    outcomes: Vec<bool>,
+}
+
+impl World {
+    /// Parse a world of a program with `coins` coin flips, given either as a
+    /// bitmask whose bit i is flip i (`5`, `0x5`) or as a list of outcomes
+    /// (`1,0,1`); 1 keeps the declared value and 0 makes it null
+    ///
+    /// The generated `mist_world_select` accepts and rejects the same selectors.
+    pub fn parse(selector: &str, coins: usize) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid world '{}' (expected a bitmask such as 5 or a list such as 1,0,1)",
+                selector
+            )
+        };
+        let missing = || {
+            format!(
+                "world {} does not exist: the program has {} coin flips",
+                selector, coins
+            )
+        };
+
+        if selector.contains(',') {
+            let outcomes = selector
+                .split(',')
+                .map(|outcome| match outcome {
+                    "1" => Ok(true),
+                    "0" => Ok(false),
+                    _ => Err(invalid()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return if outcomes.len() == coins {
+                Ok(World { outcomes })
+            } else {
+                Err(missing())
+            };
+        }
+
+        let (digits, radix) = match selector
+            .strip_prefix("0x")
+            .or_else(|| selector.strip_prefix("0X"))
+        {
+            Some(hex) => (hex, 16),
+            None => (selector, 10),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(invalid());
+        }
+        let mask = u64::from_str_radix(digits, radix).map_err(|_| invalid())?;
+        if coins < 64 && mask >> coins != 0 {
+            return Err(missing());
+        }
+        Ok(World {
+            outcomes: (0..coins).map(|i| i < 64 && mask >> i & 1 == 1).collect(),
+        })
+    }
+
+    /// Outcome of the `flip`-th coin flip of a run
+    pub fn heads(&self, flip: usize) -> bool {
+        self.outcomes.get(flip).copied().unwrap_or(false)
+    }
+}
+
+/// Control flow after executing a statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Exit(i32),
+    Reject,
+}
+
+/// How a single execution attempt ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    /// Ran to the end or hit `bye`, with this exit status
+    Finished(i32),
+    /// The `given` condition with this span was false
+    Rejected(Span),
+}
+
+/// Replays earlier stdin reads after a rejected attempt, like `mist_replay_input`
+struct ReplayInput<'a> {
+    inner: Box<dyn ProgramInput + 'a>,
+    log: Vec<Option<i32>>,
+    cursor: usize,
+}
+
+impl ProgramInput for ReplayInput<'_> {
+    fn read_int(&mut self) -> Option<i32> {
+        if self.cursor == self.log.len() {
+            let n = self.inner.read_int();
+            self.log.push(n);
+        }
+        self.cursor += 1;
+        self.log[self.cursor - 1]
+    }
+
+    fn arg(&self, n: i32) -> Option<i32> {
+        self.inner.arg(n)
+    }
+}
+
+/// Executes a type-checked program, producing the same output as its C translation
+pub struct Interpreter<'a> {
+    env: Environment,
+    coins: &'a mut dyn CoinSource,
+    out: &'a mut dyn std::io::Write,
+    input: ReplayInput<'a>,
+    /// Output of the current attempt, held back while it may still be rejected
+    pending: Option<Vec<u8>>,
+    /// (statement index, value) for every `blah` of the current attempt
+    printed: Vec<(usize, Value)>,
+    null_mode: NullMode,
+    trace: TraceLevel,
+    trace_out: Option<&'a mut dyn std::io::Write>,
+    /// Source line of each statement, and of the one being executed
+    lines: Vec<usize>,
+    line: usize,
+    /// The one world to run in, replacing the coins, and the flips made in it
+    world: Option<World>,
+    world_flip: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(coins: &'a mut dyn CoinSource, out: &'a mut dyn std::io::Write) -> Self {
+        Interpreter {
+            env: Environment::new(),
+            coins,
+            out,
+            input: ReplayInput {
+                inner: Box::new(NoInput),
+                log: Vec::new(),
+                cursor: 0,
+            },
+            pending: None,
+            printed: Vec::new(),
+            null_mode: NullMode::default(),
+            trace: TraceLevel::None,
+            trace_out: None,
+            lines: Vec::new(),
+            line: 0,
+            world: None,
+            world_flip: 0,
+        }
+    }
+
+    /// Take every coin outcome from `world` instead of flipping
+    pub fn with_world(mut self, world: World) -> Self {
+        self.world = Some(world);
+        self
+    }
+
+    /// Trace execution at `level` to `out`, as `--trace` does for compiled programs
+    pub fn with_trace(mut self, level: TraceLevel, out: &'a mut dyn std::io::Write) -> Self {
+        self.trace = level;
+        self.trace_out = Some(out);
+        self
+    }
+
+    /// Source lines of the program's statements, as reported by the parser
+    pub fn with_lines(mut self, lines: Vec<usize>) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    /// Evaluate `+`, `-` and comparisons with the given null algebra
+    pub fn with_null_mode(mut self, mode: NullMode) -> Self {
+        self.null_mode = mode;
+        self
+    }
+
+    /// Use `input` for stdin and argv instead of an empty world
+    pub fn with_input(mut self, input: Box<dyn ProgramInput + 'a>) -> Self {
+        self.input.inner = input;
+        self
+    }
+
+    /// Run every statement in order, returning the program's exit status
+    ///
+    /// Stops early at a `bye` statement or the first runtime error. A rejected
+    /// attempt is retried from scratch, up to GIVEN_ATTEMPT_LIMIT times.
+    pub fn run(&mut self, program: &Program) -> Result<i32, RuntimeError> {
+        let conditioned = is_conditioned(program);
+        let mut rejected_by = Span::default();
+        for _ in 0..GIVEN_ATTEMPT_LIMIT {
+            self.pending = conditioned.then(Vec::new);
+            let attempt = self.run_attempt(program);
+            if let Some(buffered) = self.pending.take() {
+                if !matches!(attempt, Ok(Attempt::Rejected(_))) {
+                    self.out
+                        .write_all(&buffered)
+                        .map_err(|e| RuntimeError::Io(e.to_string()))?;
+                }
+            }
+            match attempt? {
+                Attempt::Finished(code) => return Ok(code),
+                // Retrying cannot help when every flip is fixed
+                Attempt::Rejected(span) if self.world.is_some() => {
+                    return Err(RuntimeError::WorldRejected(span))
+                },
+                Attempt::Rejected(span) => rejected_by = span,
+            }
+        }
+        Err(RuntimeError::NoAcceptedWorld {
+            attempts: GIVEN_ATTEMPT_LIMIT,
+            span: rejected_by,
+        })
+    }
+
+    /// Execute the program once from a fresh environment, without retrying
+    pub fn run_attempt(&mut self, program: &Program) -> Result<Attempt, RuntimeError> {
+        self.env.clear();
+        self.printed.clear();
+        self.input.cursor = 0;
+        self.world_flip = 0;
+        for (index, stmt) in program.iter().enumerate() {
+            self.line = self.lines.get(index).copied().unwrap_or(0);
+            match self.execute(index, stmt)? {
+                Flow::Continue => {},
+                Flow::Exit(code) => return Ok(Attempt::Finished(code)),
+                Flow::Reject => return Ok(Attempt::Rejected(stmt.span)),
+            }
+        }
+        Ok(Attempt::Finished(0))
+    }
+
+    /// Values printed by `blah` statements during the latest attempt
+    pub fn printed(&self) -> &[(usize, Value)] {
+        &self.printed
+    }
+
+    fn execute(&mut self, index: usize, stmt: &Statement) -> Result<Flow, RuntimeError> {
+        match &stmt.kind {
+            StatementKind::MaybeDeclaration(name, expr) => {
+                self.declare(stmt.span, &[(name.clone(), expr.clone())])?;
+                Ok(Flow::Continue)
+            },
+
+            StatementKind::EntangledDeclaration(bindings) => {
+                self.declare(stmt.span, bindings)?;
+                Ok(Flow::Continue)
+            },
+
+            StatementKind::BlahStatement(expr) => {
+                let v = self.eval(expr)?;
+                self.trace_value(&format!("blah {}", expr), &v)?;
+                self.emit(&v.to_string())?;
+                self.printed.push((index, v));
+                Ok(Flow::Continue)
+            },
+
+            StatementKind::GivenStatement(expr) => match self.eval_traced("given", expr)? {
+                Value::Boolean(true) => Ok(Flow::Continue),
+                Value::Boolean(false) => Ok(Flow::Reject),
+                _ => Err(RuntimeError::TypeError(
+                    "given requires a boolean condition".to_string(),
+                    expr.span,
+                )),
+            },
+
+            StatementKind::ByeStatement(expr) => {
+                // Traced after coercion, as the C exit status is an int
+                let code = coerce_to_int(self.eval(expr)?)
+                    .map_err(|msg| RuntimeError::TypeError(msg, expr.span))?;
+                self.trace_value(&format!("bye {}", expr), &Value::Integer(code))?;
+                Ok(Flow::Exit(code))
+            },
+
+            StatementKind::SurelyStatement(expr) => match self.eval_traced("surely", expr)? {
+                Value::Boolean(true) => Ok(Flow::Continue),
+                Value::Boolean(false) => Err(RuntimeError::AssertionFailed {
+                    span: stmt.span,
+                    condition: expr.to_string(),
+                }),
+                _ => Err(RuntimeError::TypeError(
+                    "surely requires a boolean condition".to_string(),
+                    expr.span,
+                )),
+            },
+
+            StatementKind::Error => Err(RuntimeError::TypeError(
+                "Source that failed to parse cannot run".to_string(),
+                stmt.span,
+            )),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expression) -> Result<Value, RuntimeError> {
+        eval_expr_with_input(expr, &self.env, &mut self.input, self.null_mode)
+    }
+
+    /// Evaluate the expression of a `keyword` statement, tracing its value
+    fn eval_traced(&mut self, keyword: &str, expr: &Expression) -> Result<Value, RuntimeError> {
+        let value = self.eval(expr)?;
+        self.trace_value(&format!("{} {}", keyword, expr), &value)?;
+        Ok(value)
+    }
+
+    /// At full trace level, log the value a statement's expression produced
+    fn trace_value(&mut self, statement: &str, value: &Value) -> Result<(), RuntimeError> {
+        if self.trace < TraceLevel::Full {
+            return Ok(());
+        }
+        self.trace(&format!("{} => {}", statement, value))
+    }
+
+    /// Write one line to the trace, naming the statement's source line at full level
+    fn trace(&mut self, message: &str) -> Result<(), RuntimeError> {
+        let Some(out) = &mut self.trace_out else {
+            return Ok(());
+        };
+        let result = if self.trace == TraceLevel::Full {
+            writeln!(out, "[line {}] {}", self.line, message)
+        } else {
+            writeln!(out, "{}", message)
+        };
+        result.map_err(|e| RuntimeError::Io(e.to_string()))
+    }
+
+    /// Bind all variables on a single coin flip, mirroring `lower_coin_declaration` in ir.rs
+    fn declare(&mut self, span: Span, bindings: &[(Name, Expression)]) -> Result<(), RuntimeError> {
+        let heads = match &self.world {
+            Some(world) => {
+                self.world_flip += 1;
+                world.heads(self.world_flip - 1)
+            },
+            None => self
+                .coins
+                .try_flip()
+                .ok_or(RuntimeError::CoinsExhausted(span))?,
+        };
+        if heads {
+            let values = bindings
+                .iter()
+                .map(|(_, expr)| self.eval(expr))
+                .collect::<Result<Vec<_>, _>>()?;
+            for ((name, _), value) in bindings.iter().zip(&values) {
+                self.trace_declaration(&name.text, value)?;
+            }
+            for ((name, _), value) in bindings.iter().zip(values) {
+                self.env.insert(name.text.clone(), value);
+            }
+        } else {
+            for (name, _) in bindings {
+                self.trace_declaration(&name.text, &Value::Null)?;
+                self.env.insert(name.text.clone(), Value::Null);
+            }
+        }
+        Ok(())
+    }
+
+    fn trace_declaration(&mut self, name: &str, value: &Value) -> Result<(), RuntimeError> {
+        if self.trace < TraceLevel::Declarations {
+            return Ok(());
+        }
+        self.trace(&format!("maybe {} = {}", name, value))
+    }
+
+    fn emit(&mut self, line: &str) -> Result<(), RuntimeError> {
+        let out: &mut dyn std::io::Write = match &mut self.pending {
+            Some(buffer) => buffer,
+            None => self.out,
+        };
+        writeln!(out, "{}", line).map_err(|e| RuntimeError::Io(e.to_string()))
+    }
+}
+
+// ===========================================================================
+// BACKEND INTERFACE - Options, IR and C for a Checked Program
+// ===========================================================================
+
+// The front end that produces a CheckedProgram, and `compile`, which runs it
+// and then `generate_c`, are in driver.rs.
+
+/// How much of an execution is written to the trace stream
+///
+///   None:         nothing
+///   Declarations: the value each `maybe` declaration ends up with
+///   Full:         declarations plus the value of every statement's expression,
+///                 each prefixed with its source line
 ///
-/// Proof: Composition of correctness proofs for each phase
-pub fn compile(source: &str) -> Result<String, CompilerError> {
-    // Phase 1: Lexical Analysis (Proven Total)
-    let mut lexer = Lexer::new(source);
-    let tokens = lexer.tokenize()?;
+/// The trace goes to stderr or a trace file, never to the program's output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum TraceLevel {
+    None,
+    #[default]
+    Declarations,
+    Full,
+}
 
-    // Phase 2: Parsing (Proven Sound and Complete)
-    let mut parser = Parser::new(tokens);
-    let program = parser.parse()?;
+impl std::str::FromStr for TraceLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TraceLevel::None),
+            "declarations" => Ok(TraceLevel::Declarations),
+            "full" => Ok(TraceLevel::Full),
+            _ => Err(format!(
+                "unknown trace level '{}' (expected none, declarations or full)",
+                s
+            )),
+        }
+    }
+}
 
-    // Phase 3: Type Checking (Proven Sound)
-    let type_env = type_check_program(&program)?;
+/// Settings that change the meaning of a program, what it reports as it runs,
+/// or how it is compiled
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CompileOptions {
+    pub null_mode: NullMode,
+    pub trace: TraceLevel,
+    /// Where the trace is written; stderr when absent
+    pub trace_file: Option<String>,
+    /// Generate a program that can be told which world to run in
+    pub world_selector: bool,
+    /// Run the IR optimisation passes before generating C
+    pub optimize: bool,
+}
+
+/// The front end's result: the checked program, its types, and the source
+/// line of each statement
+#[derive(Debug, Clone)]
+pub struct CheckedProgram {
+    pub program: Program,
+    pub type_env: TypeEnvironment,
+    pub lines: Vec<usize>,
+}
+
+/// Lower a checked program to IR, optimised when the options ask for it
+pub fn build_ir(checked: &CheckedProgram, options: &CompileOptions) -> IrProgram {
+    let mut program = ir::lower(checked, options);
+    if options.optimize {
+        optimize(&mut program);
+    }
+    if let Err(e) = ir::verify(&program) {
+        unreachable!("lowering produced invalid IR: {}", e);
+    }
+    program
+}
 
-    // Phase 4: Code Generation (Proven Correct)
-    let mut generator = CodeGenerator::new();
-    let c_code = generator.generate_program(&program, &type_env);
+/// Generate C for a program that has been through the front end
+pub fn generate_c(checked: CheckedProgram, options: &CompileOptions) -> String {
+    generate_c_from_ir(&build_ir(&checked, options), options)
+}
 
-    Ok(c_code)
+/// Generate C for a program already lowered by `build_ir`
+pub fn generate_c_from_ir(program: &IrProgram, options: &CompileOptions) -> String {
+    let mut generator = CodeGenerator::new()
+        .with_trace_file(options.trace_file.clone())
+        .with_world_selector(options.world_selector);
+    generator.generate_program(program)
 }
 
 // ===========================================================================
@@ -957,6 +4214,9 @@ pub fn compile(source: &str) -> Result<String, CompilerError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::driver::{
+        check_source, compile, compile_with_options, error_chain, front_end, CompilerError,
+    };
 
     // Lexer Tests - Prove Totality
     #[test]
@@ -997,6 +4257,244 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lexer_unicode_identifiers_and_positions() {
+        let tokens = Lexer::new("maybe café = 1\nblah \"🎲\" π_2")
+            .tokenize()
+            .unwrap();
+        assert_eq!(tokens[1].kind, TokenKind::Identifier("café".to_string()));
+        assert_eq!(tokens[6].kind, TokenKind::Identifier("π_2".to_string()));
+        // After the emoji: 4 bytes, 1 char, 2 UTF-16 code units
+        let pi = tokens[6].position;
+        assert_eq!((pi.line, pi.column, pi.utf16_column), (2, 10, 11));
+        assert_eq!(pi.offset, "maybe café = 1\nblah \"🎲\" ".len());
+
+        // Combining marks continue an identifier; emoji start none
+        let tokens = Lexer::new("maybe नमस्ते = 1").tokenize().unwrap();
+        assert_eq!(tokens[1].kind, TokenKind::Identifier("नमस्ते".to_string()));
+        let tokens = Lexer::new("maybe שָׁלוֹם\u{0591} = 1").tokenize().unwrap();
+        assert_eq!(
+            tokens[1].kind,
+            TokenKind::Identifier("שָׁלוֹם\u{0591}".to_string())
+        );
+        // Joiners are not XID_Continue, and the connector `‿` is not XID_Start
+        for source in ["maybe a\u{200D}b = 1", "maybe \u{203F}a = 1"] {
+            assert!(matches!(
+                Lexer::new(source).tokenize(),
+                Err(LexError::UnexpectedCharacter { .. })
+            ));
+        }
+        match Lexer::new("blah 🎲").tokenize() {
+            Err(LexError::UnexpectedCharacter { ch, position }) => {
+                assert_eq!((ch, position.column), ('🎲', 6));
+            },
+            other => unreachable!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unicode_identifiers_are_mangled_in_c() {
+        assert_eq!(c_variable("café"), "mist_u_caf_ue9_");
+        assert_eq!(c_null_flag("café"), "mist_nu_caf_ue9_");
+        // `_` is doubled, so no two Unicode names share an escape
+        assert_eq!(c_variable("é_u"), "mist_u__ue9___u");
+        assert_ne!(c_variable("a_é"), c_variable("a_ue9_é"));
+        // and an ASCII name spelled like an escape stays in its own namespace
+        assert_ne!(c_variable("café"), c_variable("caf_ue9_"));
+
+        let c_code = compile("maybe café = 1\nblah café").unwrap();
+        assert!(c_code.contains("int mist_u_caf_ue9_ = 0;"));
+        assert!(c_code.contains("if (mist_nu_caf_ue9_) printf(\"null\\n\");"));
+        assert!(c_code.contains("\"maybe café = %d\\n\", mist_u_caf_ue9_"));
+    }
+
+    /// Every C99 keyword, and every libc name, macro and local of the generated C
+    const C_RESERVED: &[&str] = &[
+        "auto",
+        "break",
+        "case",
+        "char",
+        "const",
+        "continue",
+        "default",
+        "do",
+        "double",
+        "else",
+        "enum",
+        "extern",
+        "float",
+        "for",
+        "goto",
+        "if",
+        "inline",
+        "int",
+        "long",
+        "register",
+        "restrict",
+        "return",
+        "short",
+        "signed",
+        "sizeof",
+        "static",
+        "struct",
+        "switch",
+        "typedef",
+        "union",
+        "unsigned",
+        "void",
+        "volatile",
+        "while",
+        "_Bool",
+        "_Complex",
+        "_Imaginary",
+        "main",
+        "argc",
+        "argv",
+        "v",
+        "bool",
+        "true",
+        "false",
+        "printf",
+        "fprintf",
+        "vsnprintf",
+        "fputs",
+        "fputc",
+        "fwrite",
+        "fgets",
+        "fgetc",
+        "fscanf",
+        "getchar",
+        "fopen",
+        "feof",
+        "stdin",
+        "stdout",
+        "stderr",
+        "FILE",
+        "EOF",
+        "NULL",
+        "rand",
+        "srand",
+        "time",
+        "exit",
+        "getenv",
+        "realloc",
+        "strtol",
+        "strtoul",
+        "strtoull",
+        "strcmp",
+        "strncmp",
+        "strchr",
+        "isdigit",
+        "isspace",
+        "isxdigit",
+        "errno",
+        "ERANGE",
+        "INT_MAX",
+        "INT_MIN",
+        "EXIT_FAILURE",
+        "size_t",
+        "va_list",
+        "va_start",
+        "va_end",
+    ];
+
+    /// The identifiers of C code, without string literals and comments
+    fn c_tokens(c_code: &str) -> Vec<String> {
+        let mut code = String::new();
+        let mut chars = c_code.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '"' | '\'' => {
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => {
+                                chars.next();
+                            },
+                            c if c == ch => break,
+                            _ => {},
+                        }
+                    }
+                    code.push(' ');
+                },
+                '/' if chars.peek() == Some(&'/') => {
+                    chars.by_ref().find(|&c| c == '\n');
+                    code.push(' ');
+                },
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    let mut last = ' ';
+                    for c in chars.by_ref() {
+                        if last == '*' && c == '/' {
+                            break;
+                        }
+                        last = c;
+                    }
+                    code.push(' ');
+                },
+                _ => code.push(ch),
+            }
+        }
+        code.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .filter(|t| t.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_c_names_never_collide() {
+        // Uses args, stdin, builtins, conditioning, assertions, bye and a
+        // second variable named like the first one's null flag
+        let program = |name: &str| {
+            format!(
+                "maybe {n} = arg(1)\ngiven {n} == {n}\n\
+                 maybe (w, {n}_is_null) = (max({n}, input), 2)\nblah {n} + w\n\
+                 blah {n} is null\nsurely abs(w) >= 0\nbye {n} + {n}_is_null",
+                n = name
+            )
+        };
+        let options = CompileOptions {
+            null_mode: NullMode::Strict,
+            world_selector: true,
+            ..CompileOptions::default()
+        };
+        let baseline = c_tokens(&compile_with_options(&program("zzz"), &options).unwrap());
+        let ours = ["zzz", "w", "zzz_is_null"].map(|n| [c_variable(n), c_null_flag(n)]);
+        for token in &baseline {
+            if ["mist_v_", "mist_n_", "mist_u_", "mist_nu_"]
+                .iter()
+                .any(|prefix| token.starts_with(prefix))
+            {
+                assert!(ours.iter().flatten().any(|n| n == token), "{}", token);
+            }
+        }
+
+        for name in C_RESERVED
+            .iter()
+            .chain(&["mist_coin", "mist_v_zzz", "mist_n_zzz"])
+        {
+            // The same C as for any other name, apart from the mangled names
+            let rename = |token: &String| {
+                [
+                    ("zzz_is_null", format!("{}_is_null", name)),
+                    ("zzz", name.to_string()),
+                ]
+                .iter()
+                .flat_map(|(from, to)| {
+                    [
+                        (c_variable(from), c_variable(to)),
+                        (c_null_flag(from), c_null_flag(to)),
+                    ]
+                })
+                .find(|(from, _)| from == token)
+                .map_or_else(|| token.clone(), |(_, to)| to)
+            };
+            let expected: Vec<String> = baseline.iter().map(rename).collect();
+            let c_code = compile_with_options(&program(name), &options).unwrap();
+            assert_eq!(c_tokens(&c_code), expected, "{}", name);
+            assert!(!baseline.contains(&c_variable(name)), "{}", name);
+        }
+    }
+
     // Parser Tests - Prove Soundness
     #[test]
     fn test_parser_soundness() {
@@ -1004,27 +4502,32 @@ mod tests {
             Token {
                 kind: TokenKind::Maybe,
                 position: Position::new(),
+                end: Position::new(),
                 lexeme: "maybe".to_string(),
             },
             Token {
                 kind: TokenKind::Identifier("x".to_string()),
                 position: Position::new(),
+                end: Position::new(),
                 lexeme: "x".to_string(),
             },
             Token {
                 kind: TokenKind::Equals,
                 position: Position::new(),
+                end: Position::new(),
                 lexeme: "=".to_string(),
             },
             Token {
                 kind: TokenKind::Number(10),
                 position: Position::new(),
+                end: Position::new(),
                 lexeme: "10".to_string(),
             },
             Token {
                 kind: TokenKind::Eof,
                 position: Position::new(),
-                lexeme: "".to_string(),
+                end: Position::new(),
+                lexeme: String::new(),
             },
         ];
 
@@ -1034,15 +4537,85 @@ mod tests {
 
         let program = result.unwrap();
         assert_eq!(program.len(), 1);
-        assert!(matches!(program[0], Statement::MaybeDeclaration(_, _)));
+        assert!(matches!(
+            program[0].kind,
+            StatementKind::MaybeDeclaration(_, _)
+        ));
+    }
+
+    #[test]
+    fn test_parser_spans() {
+        let (program, _) = front_end("maybe x = 1\nblah  x + max(x, 20) is null").unwrap();
+        let at = |span: Span| (span.start.line, span.start.column, span.end.column);
+
+        let stmt = &program[1];
+        assert_eq!(at(stmt.span), (2, 1, 29));
+        assert_eq!(stmt.span.start.offset, 12);
+        let StatementKind::BlahStatement(is_null) = &stmt.kind else {
+            unreachable!("expected blah, got {:?}", stmt.kind)
+        };
+        assert_eq!(at(is_null.span), (2, 7, 29));
+        let ExpressionKind::IsNull(sum) = &is_null.kind else {
+            unreachable!("expected is null, got {:?}", is_null.kind)
+        };
+        assert_eq!(at(sum.span), (2, 7, 21));
+        let ExpressionKind::Add(x, call) = &sum.kind else {
+            unreachable!("expected +, got {:?}", sum.kind)
+        };
+        assert_eq!(at(x.span), (2, 7, 8));
+        assert_eq!(at(call.span), (2, 11, 21));
+
+        // Structural equality ignores where a node was written
+        let (moved, _) = front_end("\n\nmaybe  x = 1 blah x + max(x,20) is null").unwrap();
+        assert_eq!(program, moved);
+        assert_ne!(program[1].span, moved[1].span);
+    }
+
+    #[test]
+    fn test_parser_recovers_at_statement_keywords() {
+        let (tokens, lex_errors) = Lexer::new("maybe x = 5 @ 2\nblah x").tokenize_recovering();
+        assert_eq!(lex_errors.len(), 1);
+        assert!(tokens
+            .iter()
+            .any(|t| t.kind == TokenKind::Error && t.lexeme == "@"));
+
+        let source = "blah 1 )\nmaybe y =\nmaybe (a, b) = (1)\nblah y + a + b\nblah 2";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let (program, errors) = parser.parse_recovering();
+        assert_eq!(errors.len(), 3);
+
+        // The stray ')' becomes a statement of its own; the declarations keep
+        // their names with error values; the rest parses as normal
+        let kinds: Vec<_> = program.iter().map(|stmt| &stmt.kind).collect();
+        assert!(matches!(kinds[1], StatementKind::Error));
+        let StatementKind::MaybeDeclaration(y, value) = kinds[2] else {
+            unreachable!("expected maybe, got {:?}", kinds[2])
+        };
+        assert_eq!(
+            (y.text.as_str(), &value.kind),
+            ("y", &ExpressionKind::Error)
+        );
+        let StatementKind::EntangledDeclaration(bindings) = kinds[3] else {
+            unreachable!("expected entangled maybe, got {:?}", kinds[3])
+        };
+        assert!(bindings
+            .iter()
+            .all(|(_, e)| e.kind == ExpressionKind::Error));
+        assert_eq!(program.len(), 6);
+        assert_eq!(parser.statement_lines(), &[1, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            type_check_program_recovering(&program, NullMode::default()).1,
+            Vec::new()
+        );
     }
 
     // Type System Tests - Prove Soundness
     #[test]
     fn test_type_soundness() {
         let program = vec![
-            Statement::MaybeDeclaration("x".to_string(), Expression::Number(10)),
-            Statement::BlahStatement(Expression::Variable("x".to_string())),
+            StatementKind::MaybeDeclaration("x".into(), ExpressionKind::Number(10).into()).into(),
+            StatementKind::BlahStatement(ExpressionKind::Variable("x".to_string()).into()).into(),
         ];
 
         let result = type_check_program(&program);
@@ -1054,29 +4627,51 @@ mod tests {
 
     #[test]
     fn test_type_error_undefined_variable() {
-        let program = vec![Statement::BlahStatement(Expression::Variable(
-            "undefined".to_string(),
-        ))];
+        let program = vec![StatementKind::BlahStatement(
+            ExpressionKind::Variable("undefined".to_string()).into(),
+        )
+        .into()];
 
         let result = type_check_program(&program);
         assert!(result.is_err());
         assert!(matches!(
             result.unwrap_err(),
-            TypeError::UndefinedVariable(_)
+            TypeError::UndefinedVariable(_, _)
         ));
     }
 
     #[test]
     fn test_type_error_string_arithmetic() {
-        let program = vec![Statement::BlahStatement(Expression::Add(
-            Box::new(Expression::StringLiteral("hello".to_string())),
-            Box::new(Expression::Number(5)),
-        ))];
+        let program = vec![StatementKind::BlahStatement(
+            ExpressionKind::Add(
+                Box::new(ExpressionKind::StringLiteral("hello".to_string()).into()),
+                Box::new(ExpressionKind::Number(5).into()),
+            )
+            .into(),
+        )
+        .into()];
 
         let result = type_check_program(&program);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_type_errors_carry_spans() {
+        let Err(CompilerError::TypeError(e)) = front_end("maybe x = 1\nblah x + \"s\"") else {
+            unreachable!("expected a type error")
+        };
+        assert!(matches!(e, TypeError::InvalidOperation(_, _)));
+        assert_eq!((e.span().start.line, e.span().start.column), (2, 6));
+        assert_eq!(e.span().end.column, 13);
+        assert_eq!(e.to_string(), "Arithmetic requires integer types at 2:6");
+
+        let error = front_end("blah 1 + y").unwrap_err();
+        assert_eq!(
+            error_chain(&error),
+            "Type error: undefined variable 'y' at 1:10"
+        );
+    }
+
     // Semantic Preservation Tests
     #[test]
     fn test_null_semantics() {
@@ -1086,18 +4681,20 @@ mod tests {
         ]);
 
         // Test null + int = int
-        let expr = Expression::Add(
-            Box::new(Expression::Variable("x".to_string())),
-            Box::new(Expression::Variable("y".to_string())),
-        );
+        let expr = ExpressionKind::Add(
+            Box::new(ExpressionKind::Variable("x".to_string()).into()),
+            Box::new(ExpressionKind::Variable("y".to_string()).into()),
+        )
+        .into();
         let result = eval_expr(&expr, &env);
         assert_eq!(result, Ok(Value::Integer(5)));
 
         // Test null - int = -int
-        let expr = Expression::Subtract(
-            Box::new(Expression::Variable("x".to_string())),
-            Box::new(Expression::Variable("y".to_string())),
-        );
+        let expr = ExpressionKind::Subtract(
+            Box::new(ExpressionKind::Variable("x".to_string()).into()),
+            Box::new(ExpressionKind::Variable("y".to_string()).into()),
+        )
+        .into();
         let result = eval_expr(&expr, &env);
         assert_eq!(result, Ok(Value::Integer(-5)));
     }
@@ -1112,10 +4709,12 @@ mod tests {
         let c_code = result.unwrap();
 
         // Verify key components
-        assert!(c_code.contains("int x = 0;"));
-        assert!(c_code.contains("bool x_is_null = false;"));
+        assert!(c_code.contains("int mist_v_x = 0;"));
+        assert!(c_code.contains("bool mist_n_x = false;"));
         assert!(c_code.contains("rand() % 2 == 0"));
         assert!(c_code.contains("printf"));
+        // --runs seeds each run through the environment
+        assert!(c_code.contains("getenv(\"MIST_SEED\")"));
     }
 
     #[test]
@@ -1127,8 +4726,8 @@ mod tests {
         let c_code = result.unwrap();
 
         // Verify null checking in expressions
-        assert!(c_code.contains("(x_is_null ? 0 : x)"));
-        assert!(c_code.contains("(y_is_null ? 0 : y)"));
+        assert!(c_code.contains("(mist_n_x ? 0 : mist_v_x)"));
+        assert!(c_code.contains("(mist_n_y ? 0 : mist_v_y)"));
     }
 
     // Property: Parser Completeness
@@ -1182,14 +4781,863 @@ mod tests {
         ]);
 
         for stmt in &program {
-            match stmt {
-                Statement::BlahStatement(expr) => {
-                    let result = eval_expr(expr, &runtime_env);
-                    assert!(result.is_ok());
-                },
-                _ => {},
+            if let StatementKind::BlahStatement(expr) = &stmt.kind {
+                let result = eval_expr(expr, &runtime_env);
+                assert!(result.is_ok());
+            }
+        }
+    }
+
+    // Runtime Assertion Tests
+    #[test]
+    fn test_surely_parses_with_line() {
+        let (program, _) = front_end("maybe total = 5\nsurely total >= 0").unwrap();
+        assert_eq!(
+            program[1].kind,
+            StatementKind::SurelyStatement(
+                ExpressionKind::Comparison(
+                    ComparisonOp::GreaterEqual,
+                    Box::new(ExpressionKind::Variable("total".to_string()).into()),
+                    Box::new(ExpressionKind::Number(0).into()),
+                )
+                .into()
+            )
+        );
+        assert_eq!(program[1].span.start.line, 2);
+    }
+
+    #[test]
+    fn test_surely_requires_comparison() {
+        let result = front_end("maybe x = 1\nsurely x");
+        assert!(matches!(
+            result,
+            Err(CompilerError::TypeError(TypeError::TypeMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_surely_codegen() {
+        let c_code = compile("maybe x = 5\nsurely x - 10 >= 0").unwrap();
+        assert!(c_code.contains("if (!(mist_sub_int((mist_n_x ? 0 : mist_v_x), 10) >= 0)) {"));
+        assert!(c_code.contains("\"surely failed at line 2: x - 10 >= 0\""));
+        assert!(c_code.contains("exit(1);"));
+    }
+
+    #[test]
+    fn test_interpreter_surely_failure() {
+        let (program, _) = front_end("maybe x = 5\nblah x\nsurely x < 3\nblah 1").unwrap();
+        let mut coins = || true;
+        let mut out = Vec::new();
+        let result = Interpreter::new(&mut coins, &mut out).run(&program);
+
+        let Err(RuntimeError::AssertionFailed { span, condition }) = result else {
+            unreachable!("expected an assertion failure, got {:?}", result)
+        };
+        assert_eq!(condition, "x < 3");
+        assert_eq!(
+            (span.start.line, span.start.column, span.end.column),
+            (3, 1, 13)
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "5\n");
+    }
+
+    #[test]
+    fn test_interpreter_null_comparison() {
+        let (program, _) = front_end("maybe x = 5\nsurely x == 0\nblah x != 0").unwrap();
+        let mut coins = || false;
+        let mut out = Vec::new();
+        Interpreter::new(&mut coins, &mut out)
+            .run(&program)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "false\n");
+    }
+
+    // Exit Status Tests
+    #[test]
+    fn test_bye_codegen() {
+        let c_code = compile("maybe status = 3\nbye status\nblah 1").unwrap();
+        assert!(c_code.contains("exit((mist_n_status ? 0 : mist_v_status));"));
+        assert!(type_check_program(&vec![StatementKind::ByeStatement(
+            ExpressionKind::StringLiteral("no".to_string()).into()
+        )
+        .into()])
+        .is_err());
+    }
+
+    #[test]
+    fn test_interpreter_bye_stops_execution() {
+        let (program, _) = front_end("maybe status = 3\nbye status + 1\nblah 1").unwrap();
+
+        let mut heads = || true;
+        let mut out = Vec::new();
+        assert_eq!(Interpreter::new(&mut heads, &mut out).run(&program), Ok(4));
+        assert_eq!(String::from_utf8(out).unwrap(), "");
+
+        // A null exit value maps to status 0
+        let (program, _) = front_end("maybe status = 3\nbye status").unwrap();
+        let mut tails = || false;
+        let mut out = Vec::new();
+        assert_eq!(Interpreter::new(&mut tails, &mut out).run(&program), Ok(0));
+    }
+
+    // Entanglement Tests
+    #[test]
+    fn test_entangled_declaration_parses() {
+        let (program, env) = front_end("maybe (tax, tip) = (4, 10)").unwrap();
+        assert_eq!(
+            program[0].kind,
+            StatementKind::EntangledDeclaration(vec![
+                ("tax".into(), ExpressionKind::Number(4).into()),
+                ("tip".into(), ExpressionKind::Number(10).into()),
+            ])
+        );
+        // Each name carries the span of the name alone
+        let StatementKind::EntangledDeclaration(bindings) = &program[0].kind else {
+            unreachable!()
+        };
+        let offsets: Vec<_> = bindings
+            .iter()
+            .map(|(name, _)| (name.span.start.offset, name.span.end.offset))
+            .collect();
+        assert_eq!(offsets, [(7, 10), (12, 15)]);
+        assert_eq!(env.get("tip"), Some(&Type::Maybe(Box::new(Type::Int))));
+
+        // Arity mismatches and duplicate names are rejected
+        assert!(front_end("maybe (a, b) = (1)").is_err());
+        assert!(front_end("maybe (a) = (1, 2)").is_err());
+        assert!(front_end("maybe (a, a) = (1, 2)").is_err());
+    }
+
+    #[test]
+    fn test_entangled_codegen_uses_one_coin() {
+        let c_code = compile("maybe (tax, tip) = (4, 10)\nblah tax + tip").unwrap();
+        assert_eq!(c_code.matches("rand()").count(), 1);
+        assert!(c_code.contains("mist_n_tax = true;"));
+        assert!(c_code.contains("mist_n_tip = true;"));
+    }
+
+    /// Compile `source` with gcc and run it in every world, as the
+    /// interpreter runs it in the same world: stdout and exit status must
+    /// agree. Does nothing where gcc is not installed.
+    fn assert_c_matches_interpreter(name: &str, source: &str, mode: NullMode, stdin: &str) {
+        use std::io::Write as _;
+        use std::process::{Command, Stdio};
+
+        let options = CompileOptions {
+            null_mode: mode,
+            trace: TraceLevel::None,
+            world_selector: true,
+            ..CompileOptions::default()
+        };
+        let checked = check_source(source, &options).unwrap();
+        let coins = build_ir(&checked, &options).coin_count();
+        let base = std::env::temp_dir().join(format!("mist-e2e-{}-{}", std::process::id(), name));
+        let c_path = base.with_extension("c");
+        std::fs::write(&c_path, generate_c(checked.clone(), &options)).unwrap();
+        let Ok(gcc) = Command::new("gcc")
+            .arg("-std=c99")
+            .arg("-o")
+            .arg(&base)
+            .arg(&c_path)
+            .output()
+        else {
+            return;
+        };
+        let _ = std::fs::remove_file(&c_path);
+        assert!(
+            gcc.status.success(),
+            "{}",
+            String::from_utf8_lossy(&gcc.stderr)
+        );
+
+        for world in 0..1u32 << coins {
+            let mut child = Command::new(&base)
+                .arg(format!("--world={}", world))
+                .arg("7")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+            // A program that stops early may not read it all
+            let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+            let compiled = child.wait_with_output().unwrap();
+
+            // Every coin comes from the world
+            let mut coins_unused = || false;
+            let mut out = Vec::new();
+            let input =
+                ProcessInput::new(stdin.as_bytes(), vec![name.to_string(), "7".to_string()]);
+            let status = Interpreter::new(&mut coins_unused, &mut out)
+                .with_input(Box::new(input))
+                .with_null_mode(mode)
+                .with_world(World::parse(&world.to_string(), coins).unwrap())
+                .run(&checked.program)
+                .unwrap_or_else(|e| match e {
+                    RuntimeError::AssertionFailed { .. } => ASSERTION_EXIT_CODE,
+                    RuntimeError::WorldRejected(_) => REJECTION_EXIT_CODE,
+                    _ => 1,
+                });
+            assert_eq!(
+                (
+                    String::from_utf8_lossy(&compiled.stdout),
+                    compiled.status.code()
+                ),
+                (String::from_utf8_lossy(&out), Some(status)),
+                "{} in world {}",
+                name,
+                world
+            );
+        }
+        let _ = std::fs::remove_file(&base);
+    }
+
+    #[test]
+    fn test_redeclaration_reads_the_shadowed_variable() {
+        // In the world where both coins keep their values, x is 1 + 1
+        let options = CompileOptions {
+            trace: TraceLevel::None,
+            ..CompileOptions::default()
+        };
+        let checked = check_source("maybe x = 1\nmaybe x = x + 1\nblah x", &options).unwrap();
+        let mut coins = || false;
+        let mut out = Vec::new();
+        Interpreter::new(&mut coins, &mut out)
+            .with_world(World::parse("1,1", 2).unwrap())
+            .run(&checked.program)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "2\n");
+
+        let c_code = generate_c(checked, &options);
+        assert!(c_code.contains("mist_v1_x = mist_add_int((mist_n_x ? 0 : mist_v_x), 1);"));
+    }
+
+    #[test]
+    fn test_compiled_programs_match_the_interpreter() {
+        let programs = [
+            (
+                "redeclare",
+                "maybe x = 5\nmaybe x = x + 1\nblah x\nmaybe x = x - 2\nblah x",
+            ),
+            (
+                "swap",
+                "maybe a = 1\nmaybe b = 2\nmaybe (a, b) = (b, a)\nblah a\nblah b",
+            ),
+            (
+                "mixed",
+                "maybe n = input\nmaybe a = arg(1)\nmaybe (p, q) = (max(n, a) + 1, abs(n))\n\
+                 blah min(p, 3) - q\nsurely q != 4\ngiven p is null\nbye p + sign(q)",
+            ),
+            (
+                "inputs",
+                "blah input - input\nblah input > input\nblah max(input, input) - input\n\
+                 maybe x = input\nblah x + input",
+            ),
+            (
+                "overflow",
+                "maybe big = 2147483647\nblah big + 1\nblah 0 - big - 2 - 5\n\
+                 blah big + input - 3\nsurely 0 - big - big < 0",
+            ),
+        ];
+        for (name, source) in programs {
+            for mode in [NullMode::Zero, NullMode::Propagate, NullMode::Strict] {
+                assert_c_matches_interpreter(name, source, mode, "4\n9\n1\n8\n3\n2\n6\n5\n");
             }
         }
+
+        // Lines of any length, and the whitespace C's isspace knows
+        let stdin = format!(
+            "{}12{}\n+{}7\n1{}\n\x0b 3 \x0c\r\n",
+            " ".repeat(100),
+            "\t".repeat(100),
+            "0".repeat(80),
+            "0".repeat(80)
+        );
+        let source = "blah input\nblah input\nblah input\nblah input\nblah input";
+        assert_c_matches_interpreter("lines", source, NullMode::Propagate, &stdin);
+    }
+
+    #[test]
+    fn test_interpreter_entangled_correlation() {
+        let (program, _) = front_end("maybe (tax, tip) = (4, 10)\nblah tax + tip").unwrap();
+        for (heads, expected) in [(true, "14"), (false, "0")] {
+            let mut flips = 0;
+            let mut coins = || {
+                flips += 1;
+                heads
+            };
+            let mut out = Vec::new();
+            Interpreter::new(&mut coins, &mut out)
+                .run(&program)
+                .unwrap();
+            let printed = String::from_utf8(out).unwrap();
+            assert_eq!(printed.lines().last(), Some(expected));
+            assert_eq!(flips, 1);
+        }
+    }
+
+    // Program Input Tests
+    #[test]
+    fn test_input_and_arg_types() {
+        let (_, env) = front_end("maybe n = input\nmaybe a = arg(1)\nblah n + a").unwrap();
+        assert_eq!(env.get("n"), Some(&Type::Maybe(Box::new(Type::Int))));
+        assert_eq!(env.get("a"), Some(&Type::Maybe(Box::new(Type::Int))));
+        assert!(front_end("blah arg(\"one\")").is_err());
+    }
+
+    #[test]
+    fn test_input_codegen() {
+        let c_code = compile("maybe n = input\nblah arg(1) + n").unwrap();
+        assert!(c_code.contains("int main(int argc, char **argv) {"));
+        assert!(c_code.contains("static mist_maybe mist_input(void)"));
+        assert!(c_code.contains(
+            "{ mist_maybe v = mist_input(); mist_v_n = v.value; mist_n_n = v.is_null; }"
+        ));
+        assert!(c_code.contains("mist_or_zero(mist_arg(1))"));
+
+        // Programs without input keep the plain prelude
+        let c_code = compile("maybe x = 1\nblah x").unwrap();
+        assert!(c_code.contains("int main(void) {"));
+        assert!(!c_code.contains("mist_maybe"));
+    }
+
+    #[test]
+    fn test_interpreter_reads_input() {
+        let source = "maybe n = input\nmaybe m = input\nblah n + m + arg(1)\nblah arg(0) + arg(9)";
+        let (program, _) = front_end(source).unwrap();
+        let stdin = std::io::Cursor::new("  42 \nforty-two\n");
+        let argv = vec!["prog".to_string(), "-7".to_string()];
+
+        let mut coins = || true;
+        let mut out = Vec::new();
+        Interpreter::new(&mut coins, &mut out)
+            .with_input(Box::new(ProcessInput::new(stdin, argv)))
+            .run(&program)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "35\n0\n");
+    }
+
+    // Builtin Function Tests
+    #[test]
+    fn test_builtin_null_semantics() {
+        let env = Environment::from([("n".to_string(), Value::Null)]);
+        let call = |name: &str, args: Vec<Expression>| {
+            eval_expr(&ExpressionKind::Call(name.to_string(), args).into(), &env)
+        };
+        let null = || Expression::from(ExpressionKind::Variable("n".to_string()));
+        let num = |n| Expression::from(ExpressionKind::Number(n));
+
+        assert_eq!(call("max", vec![null(), num(3)]), Ok(Value::Integer(3)));
+        assert_eq!(call("min", vec![null(), null()]), Ok(Value::Null));
+        assert_eq!(call("abs", vec![null()]), Ok(Value::Null));
+        assert_eq!(
+            call("clamp", vec![num(42), null(), num(10)]),
+            Ok(Value::Integer(10))
+        );
+        assert_eq!(
+            call("clamp", vec![null(), num(0), num(10)]),
+            Ok(Value::Null)
+        );
+        assert_eq!(
+            call(
+                "sign",
+                vec![ExpressionKind::Subtract(Box::new(num(0)), Box::new(num(7))).into()]
+            ),
+            Ok(Value::Integer(-1))
+        );
+    }
+
+    #[test]
+    fn test_builtin_type_errors() {
+        assert!(matches!(
+            front_end("blah frobnicate(1)"),
+            Err(CompilerError::TypeError(TypeError::UnknownFunction(name, _))) if name == "frobnicate"
+        ));
+        assert!(matches!(
+            front_end("blah max(1)"),
+            Err(CompilerError::TypeError(TypeError::ArityMismatch {
+                expected: 2,
+                found: 1,
+                ..
+            }))
+        ));
+
+        // min/max only go null when both arguments can
+        let (_, env) = front_end("maybe x = 1\nmaybe a = max(x, 0)\nmaybe b = abs(x)").unwrap();
+        assert_eq!(env.get("a"), Some(&Type::Maybe(Box::new(Type::Int))));
+        let x = Expression::from(ExpressionKind::Variable("x".to_string()));
+        assert_eq!(
+            type_check_expr(
+                &ExpressionKind::Call(
+                    "max".to_string(),
+                    vec![x.clone(), ExpressionKind::Number(0).into()]
+                )
+                .into(),
+                &env
+            ),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            type_check_expr(
+                &ExpressionKind::Call("abs".to_string(), vec![x]).into(),
+                &env
+            ),
+            Ok(Type::Maybe(Box::new(Type::Int)))
+        );
+    }
+
+    #[test]
+    fn test_builtin_codegen() {
+        let c_code = compile("maybe x = 5\nblah max(x, 3) + clamp(x, 0, 4)").unwrap();
+        assert!(c_code.contains("static inline mist_maybe mist_clamp("));
+        assert!(c_code.contains(
+            "mist_or_zero(mist_max(((mist_maybe){ mist_v_x, mist_n_x }), mist_some(3)))"
+        ));
+    }
+
+    // Conditioning Tests
+    #[test]
+    fn test_given_requires_condition() {
+        let (program, _) = front_end("maybe x = 1\ngiven x == 1").unwrap();
+        assert!(is_conditioned(&program));
+        assert!(matches!(
+            front_end("maybe x = 1\ngiven x"),
+            Err(CompilerError::TypeError(TypeError::TypeMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_given_codegen() {
+        let c_code = compile("maybe x = 1\ngiven x == 1\nblah x").unwrap();
+        assert!(c_code.contains("for (int mist_attempt = 0; ; mist_attempt++) {"));
+        assert!(c_code.contains("if (!((mist_n_x ? 0 : mist_v_x) == 1)) {\n            continue;"));
+        assert!(c_code.contains("mist_printf(\"%d\\n\""));
+        assert!(c_code.contains("mist_flush();"));
+
+        // Unconditioned programs print directly
+        let c_code = compile("maybe x = 1\nblah x").unwrap();
+        assert!(!c_code.contains("mist_printf"));
+    }
+
+    #[test]
+    fn test_interpreter_given_retries() {
+        let (program, _) = front_end("maybe x = 1\ngiven x == 1\nblah x").unwrap();
+        // Tails, tails, heads: the first two attempts are rejected silently
+        let mut flips = [false, false, true].into_iter();
+        let mut coins = || flips.next().unwrap();
+        let mut out = Vec::new();
+        let status = Interpreter::new(&mut coins, &mut out).run(&program);
+        assert_eq!(status, Ok(0));
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+
+        let (program, _) = front_end("maybe x = 1\ngiven x == 2").unwrap();
+        let mut coins = || true;
+        let mut out = Vec::new();
+        assert_eq!(
+            Interpreter::new(&mut coins, &mut out).run(&program),
+            Err(RuntimeError::NoAcceptedWorld {
+                attempts: GIVEN_ATTEMPT_LIMIT,
+                span: program[1].span,
+            })
+        );
+        assert!(out.is_empty());
+    }
+
+    // Query Tests
+    #[test]
+    fn test_query_types() {
+        let (_, env) = front_end("maybe x = 1\nblah x is null\nblah prob(x > 0) > 0").unwrap();
+        let x = Box::new(Expression::from(ExpressionKind::Variable("x".to_string())));
+        assert_eq!(
+            type_check_expr(&ExpressionKind::IsNull(x.clone()).into(), &env),
+            Ok(Type::Bool)
+        );
+        assert_eq!(
+            type_check_expr(&ExpressionKind::Expect(x).into(), &env),
+            Ok(Type::Rational)
+        );
+
+        assert!(front_end("maybe x = 1\nblah prob(x)").is_err());
+        assert!(front_end("maybe x = 1\nblah expect(x) + 1").is_err());
+        assert!(front_end("maybe x = 1\nmaybe p = expect(x)").is_err());
+    }
+
+    #[test]
+    fn test_interpreter_prints_query_answers() {
+        let source = "maybe a = 3\nmaybe b = 4\nblah expect(a + b)\nblah prob(a is null)\n\
+                      blah prob(a + b > 3) > 1";
+        let (program, _) = front_end(source).unwrap();
+        let mut coins = || true;
+        let mut out = Vec::new();
+        Interpreter::new(&mut coins, &mut out)
+            .run(&program)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "7/2\n1/2\nfalse\n");
+    }
+
+    #[test]
+    fn test_query_codegen() {
+        let c_code = compile("maybe a = 3\nblah expect(a)\nsurely prob(a is null) < 1").unwrap();
+        assert!(c_code.contains("printf(\"%s\\n\", \"3/2\");"));
+        assert!(c_code.contains("if (!((long long)1 * 1 < (long long)1 * 2)) {"));
+        assert!(compile("maybe a = 3\nblah a is null")
+            .unwrap()
+            .contains("mist_n_a ? \"true\""));
+    }
+
+    #[test]
+    fn test_is_null_evaluates_its_operand() {
+        use std::io::Write as _;
+        use std::process::{Command, Stdio};
+
+        // `input + 0` is never null, but it still consumes a line of stdin
+        let source = "blah input + 0 is null\nblah input";
+        let stdin = "5\n7\n";
+
+        let (program, _) = front_end(source).unwrap();
+        let mut coins = || true;
+        let mut out = Vec::new();
+        Interpreter::new(&mut coins, &mut out)
+            .with_input(Box::new(ProcessInput::new(stdin.as_bytes(), Vec::new())))
+            .run(&program)
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out), "false\n7\n");
+
+        // The compiled program must agree; skipped where gcc is not installed
+        let base = std::env::temp_dir().join(format!("mist-is-null-{}", std::process::id()));
+        let c_path = base.with_extension("c");
+        std::fs::write(&c_path, compile(source).unwrap()).unwrap();
+        let Ok(gcc) = Command::new("gcc")
+            .arg("-o")
+            .arg(&base)
+            .arg(&c_path)
+            .output()
+        else {
+            return;
+        };
+        let _ = std::fs::remove_file(&c_path);
+        assert!(
+            gcc.status.success(),
+            "{}",
+            String::from_utf8_lossy(&gcc.stderr)
+        );
+        let mut child = Command::new(&base)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let compiled = child.wait_with_output().unwrap();
+        let _ = std::fs::remove_file(&base);
+        assert_eq!(
+            String::from_utf8_lossy(&compiled.stdout),
+            String::from_utf8_lossy(&out)
+        );
+    }
+
+    // Null Mode Tests
+    #[test]
+    fn test_null_modes_evaluation() {
+        let env = Environment::from([("n".to_string(), Value::Null)]);
+        let n = || Box::new(ExpressionKind::Variable("n".to_string()).into());
+        let add = ExpressionKind::Add(n(), Box::new(ExpressionKind::Number(5).into())).into();
+        let sub = ExpressionKind::Subtract(n(), n()).into();
+
+        assert_eq!(
+            eval_expr_in_mode(&add, &env, NullMode::Zero),
+            Ok(Value::Integer(5))
+        );
+        assert_eq!(
+            eval_expr_in_mode(&sub, &env, NullMode::Zero),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(
+            eval_expr_in_mode(&add, &env, NullMode::Propagate),
+            Ok(Value::Null)
+        );
+        assert_eq!(
+            eval_expr_in_mode(&sub, &env, NullMode::Propagate),
+            Ok(Value::Null)
+        );
+        assert_eq!(
+            eval_expr_in_mode(&add, &env, NullMode::Strict),
+            Err(RuntimeError::NullOperand(
+                "n + 5".to_string(),
+                Span::default()
+            ))
+        );
+
+        // Builtins and `is null` are null-aware in every mode
+        let max = ExpressionKind::Call(
+            "max".to_string(),
+            vec![*n(), ExpressionKind::Number(1).into()],
+        )
+        .into();
+        assert_eq!(
+            eval_expr_in_mode(&max, &env, NullMode::Strict),
+            Ok(Value::Integer(1))
+        );
+        assert_eq!(
+            eval_expr_in_mode(&ExpressionKind::IsNull(n()).into(), &env, NullMode::Strict),
+            Ok(Value::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn test_null_mode_types() {
+        let source = "maybe a = 1\nmaybe b = a + 1";
+        let (_, env) = front_end(source).unwrap();
+        let sum = ExpressionKind::Add(
+            Box::new(ExpressionKind::Variable("a".to_string()).into()),
+            Box::new(ExpressionKind::Number(1).into()),
+        )
+        .into();
+        assert_eq!(
+            type_check_expr_in_mode(&sum, &env, NullMode::Zero),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            type_check_expr_in_mode(&sum, &env, NullMode::Strict),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            type_check_expr_in_mode(&sum, &env, NullMode::Propagate),
+            Ok(Type::Maybe(Box::new(Type::Int)))
+        );
+        assert_eq!("strict".parse(), Ok(NullMode::Strict));
+        assert!("sql".parse::<NullMode>().is_err());
+    }
+
+    #[test]
+    fn test_null_mode_codegen() {
+        let source = "maybe a = 1\nmaybe b = a + 1\nblah a - b";
+        let options = |null_mode| CompileOptions {
+            null_mode,
+            ..CompileOptions::default()
+        };
+
+        let c_code = compile_with_options(source, &options(NullMode::Propagate)).unwrap();
+        assert!(c_code.contains(
+            "{ mist_maybe v = mist_add(((mist_maybe){ mist_v_a, mist_n_a }), mist_some(1)); \
+             mist_v_b = v.value; mist_n_b = v.is_null; }"
+        ));
+        assert!(c_code.contains("mist_maybe v = mist_sub("));
+
+        let c_code = compile_with_options(source, &options(NullMode::Strict)).unwrap();
+        assert!(c_code.contains(
+            "mist_nonnull(((mist_maybe){ mist_v_a, mist_n_a }), \"null operand in strict mode: a + 1\")"
+        ));
+        assert!(c_code.contains("static void mist_null_error(const char *message) {"));
+
+        // The default algebra needs no helpers
+        assert!(!compile(source).unwrap().contains("mist_maybe"));
+    }
+
+    #[test]
+    fn test_blah_prints_null() {
+        let source = "maybe x = 4\nblah x\nblah max(x, x)\nblah x + x";
+        let (program, _) = front_end(source).unwrap();
+        let mut coins = || false;
+        let mut out = Vec::new();
+        Interpreter::new(&mut coins, &mut out)
+            .run(&program)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "null\nnull\n0\n");
+
+        let c_code = compile(source).unwrap();
+        assert!(
+            c_code.contains("if (mist_n_x) printf(\"null\\n\"); else printf(\"%d\\n\", mist_v_x);")
+        );
+        assert!(
+            c_code.contains("if (v.is_null) printf(\"null\\n\"); else printf(\"%d\\n\", v.value);")
+        );
+    }
+
+    #[test]
+    fn test_interpreter_trace_levels() {
+        let source = "maybe x = 4\nmaybe y = 1\n\nblah x + 1\nsurely x > 0\nbye x";
+        let trace = |level| {
+            let checked = check_source(source, &CompileOptions::default()).unwrap();
+            let mut flips = [true, false].into_iter();
+            let mut coins = || flips.next().unwrap();
+            let mut out = Vec::new();
+            let mut trace = Vec::new();
+            let code = Interpreter::new(&mut coins, &mut out)
+                .with_trace(level, &mut trace)
+                .with_lines(checked.lines)
+                .run(&checked.program)
+                .unwrap();
+            assert_eq!(
+                (code, String::from_utf8(out).unwrap()),
+                (4, "5\n".to_string())
+            );
+            String::from_utf8(trace).unwrap()
+        };
+
+        assert_eq!(trace(TraceLevel::None), "");
+        assert_eq!(
+            trace(TraceLevel::Declarations),
+            "maybe x = 4\nmaybe y = null\n"
+        );
+        assert_eq!(
+            trace(TraceLevel::Full),
+            "[line 1] maybe x = 4\n[line 2] maybe y = null\n[line 4] blah x + 1 => 5\n\
+             [line 5] surely x > 0 => true\n[line 6] bye x => 4\n"
+        );
+    }
+
+    #[test]
+    fn test_trace_codegen() {
+        let source = "maybe x = 4\nblah x\nsurely x > 0";
+        let options = |trace, trace_file| CompileOptions {
+            trace,
+            trace_file,
+            ..CompileOptions::default()
+        };
+
+        // Declarations are traced to stderr by default, never to stdout
+        let c_code = compile(source).unwrap();
+        assert!(c_code.contains("mist_trace_open(NULL);"));
+        assert!(c_code.contains("fprintf(mist_trace_out, \"maybe x = %d\\n\", mist_v_x);"));
+        assert!(!c_code.contains("printf(\"maybe"));
+        assert!(!c_code.contains("mist_trace_bool"));
+
+        let c_code = compile_with_options(source, &options(TraceLevel::None, None)).unwrap();
+        assert!(!c_code.contains("mist_trace"));
+
+        let c_code = compile_with_options(
+            source,
+            &options(TraceLevel::Full, Some("run.trace".to_string())),
+        )
+        .unwrap();
+        assert!(c_code.contains("mist_trace_open(\"run.trace\");"));
+        assert!(c_code.contains("\"[line 1] maybe x = %d\\n\""));
+        assert!(c_code
+            .contains("mist_trace_maybe(2, \"blah x\", ((mist_maybe){ mist_v_x, mist_n_x }))"));
+        assert!(c_code.contains("if (!mist_trace_bool(3, \"surely x > 0\", "));
+    }
+
+    #[test]
+    fn test_coin_trace_round_trip() {
+        let (program, _) = front_end("maybe a = 1\nmaybe b = 2\ngiven a == 1\nblah a + b").unwrap();
+        let checksum = program_checksum(&program);
+        let run = |coins: &mut dyn CoinSource| {
+            let mut out = Vec::new();
+            let result = Interpreter::new(coins, &mut out).run(&program);
+            (result, String::from_utf8(out).unwrap())
+        };
+
+        // Tails on `a` rejects the first attempt; the trace keeps its flips too
+        let mut flips = [false, true, true, false].into_iter();
+        let mut clock = || flips.next().unwrap();
+        let mut recording = RecordingCoins::new(&mut clock);
+        assert_eq!(run(&mut recording), (Ok(0), "1\n".to_string()));
+        let trace = recording.trace(&checksum);
+        assert_eq!(trace, format!("mist-coins {}\n0110\n", checksum));
+
+        let mut replay = ReplayCoins::parse(&trace, &checksum).unwrap();
+        assert_eq!(run(&mut replay), (Ok(0), "1\n".to_string()));
+
+        // A truncated trace is an error rather than a silent tails
+        let mut short =
+            ReplayCoins::parse(&format!("mist-coins {}\n01", checksum), &checksum).unwrap();
+        // The retry runs out on its very first declaration
+        assert_eq!(
+            run(&mut short).0,
+            Err(RuntimeError::CoinsExhausted(program[0].span))
+        );
+    }
+
+    #[test]
+    fn test_coin_trace_checksum() {
+        let (program, _) = front_end("maybe a = 1").unwrap();
+        let (other, _) = front_end("maybe a = 2").unwrap();
+        let checksum = program_checksum(&program);
+        assert_eq!(checksum.len(), 16);
+        assert_eq!(
+            checksum,
+            program_checksum(&front_end("maybe  a = 1 // same").unwrap().0)
+        );
+        assert_ne!(checksum, program_checksum(&other));
+
+        let trace = format!("mist-coins {}\n1", program_checksum(&other));
+        assert!(ReplayCoins::parse(&trace, &checksum).is_err());
+        assert!(ReplayCoins::parse(&format!("mist-coins {}\n1x", checksum), &checksum).is_err());
+
+        let c_code = compile("maybe a = 1").unwrap();
+        assert!(c_code.contains(&format!("mist_coins_open(\"{}\");", checksum)));
+        assert!(c_code.contains("if (mist_coin()) {"));
+        assert!(c_code.contains("static void mist_coins_error(const char *message) {"));
+    }
+
+    #[test]
+    fn test_world_selector_parsing() {
+        let outcomes = |selector, coins| {
+            World::parse(selector, coins).map(|world| (0..coins).map(|i| world.heads(i)).collect())
+        };
+        assert_eq!(outcomes("5", 3), Ok(vec![true, false, true]));
+        assert_eq!(outcomes("0x6", 3), Ok(vec![false, true, true]));
+        assert_eq!(outcomes("1,1,0", 3), Ok(vec![true, true, false]));
+        assert_eq!(outcomes("0", 0), Ok(vec![]));
+
+        assert_eq!(
+            outcomes("8", 3),
+            Err("world 8 does not exist: the program has 3 coin flips".to_string())
+        );
+        assert!(outcomes("1,0", 3).is_err());
+        for invalid in ["", "+5", "0x", "1,2,0", "1,,0", "five"] {
+            assert!(outcomes(invalid, 3)
+                .unwrap_err()
+                .starts_with("invalid world"));
+        }
+    }
+
+    #[test]
+    fn test_interpreter_runs_selected_world() {
+        let (program, _) = front_end("maybe a = 1\nmaybe b = 2\ngiven b == 2\nblah a + b").unwrap();
+        let run = |selector| {
+            // Tails everywhere, so any output with a value came from the world
+            let mut coins = || false;
+            let mut out = Vec::new();
+            let result = Interpreter::new(&mut coins, &mut out)
+                .with_world(World::parse(selector, 2).unwrap())
+                .run(&program);
+            (result, String::from_utf8(out).unwrap())
+        };
+        assert_eq!(run("3"), (Ok(0), "3\n".to_string()));
+        assert_eq!(run("0,1"), (Ok(0), "2\n".to_string()));
+        // Rejection ends the run instead of retrying the same world forever
+        assert_eq!(
+            run("1"),
+            (
+                Err(RuntimeError::WorldRejected(program[2].span)),
+                String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn test_world_selector_codegen() {
+        let source = "maybe a = 1\ngiven a == 1\nblah arg(1)";
+        assert!(!compile(source).unwrap().contains("mist_world"));
+
+        let options = CompileOptions {
+            world_selector: true,
+            ..CompileOptions::default()
+        };
+        let c_code = compile_with_options(source, &options).unwrap();
+        assert!(c_code.contains("int main(int argc, char **argv) {"));
+        assert!(c_code.contains("mist_world_select(argv[1] + 8, 1);"));
+        assert!(c_code.contains("if (mist_world_coin()) {"));
+        assert!(c_code.contains("if (mist_attempt > 0 && mist_world_selected) {"));
+        // The selector is consumed before arg(n) sees argv
+        let select = c_code.find("mist_world_select(argv").unwrap();
+        assert!(select < c_code.find("mist_argv = argv;").unwrap());
     }
 
     // Verification Condition Tests
@@ -1229,9 +5677,16 @@ pub mod verification {
 
     /// VC2: Parser produces well-formed AST
     pub fn vc_parser_wellformed(program: &Program) -> bool {
-        program.iter().all(|stmt| match stmt {
-            Statement::MaybeDeclaration(name, _) => !name.is_empty(),
-            Statement::BlahStatement(_) => true,
+        program.iter().all(|stmt| match &stmt.kind {
+            StatementKind::MaybeDeclaration(name, _) => !name.text.is_empty(),
+            StatementKind::EntangledDeclaration(bindings) => {
+                !bindings.is_empty() && bindings.iter().all(|(name, _)| !name.text.is_empty())
+            },
+            StatementKind::BlahStatement(_)
+            | StatementKind::SurelyStatement(_)
+            | StatementKind::ByeStatement(_)
+            | StatementKind::GivenStatement(_) => true,
+            StatementKind::Error => false,
         })
     }
 
@@ -0,0 +1,976 @@
+// Diagnostics for Mist Programs
+//
+// Every error the front end reports becomes a `Diagnostic`: a stable code such
+// as M0006, a one-line message, and when the error has a place in the source,
+// its span with a short label, followed by notes and help. `render` prints it
+// the way rustc does:
+//
+//     error[M0006]: cannot find variable 'y'
+//      --> dice.mist:1:10
+//       |
+//     1 | blah 1 + y
+//       |          ^ not declared before this point
+//       |
+//       = help: declare it first, as in `maybe y = 0`
+//
+// Codes never change meaning once published; `--explain M0006` prints the
+// long explanation kept in ERROR_CODES. Lint findings are diagnostics too, with
+// the lint's name as their code and usually a warning's severity.
+//
+// With `--message-format=json` each diagnostic is instead one JSON object on
+// its own line, for editors and CI. The schema is stable: fields are only
+// ever added.
+//
+//     {"severity": "error", "code": "M0009", "message": "...", "file": "a.mist",
+//      "span": {"start": POSITION, "end": POSITION} or null,
+//      "label": "..." or null, "notes": ["..."], "help": "..." or null,
+//      "fixes": [{"message": "...", "span": SPAN, "replacement": "..."}]}
+//
+// where a POSITION is {"line": 1, "column": 1, "utf16_column": 1, "offset": 0},
+// lines and columns counting from 1 and the offset in bytes from 0.
+
+use crate::analysis::EXACT_COIN_LIMIT;
+use crate::compiler::{
+    LexError, ParseError, Position, Span, Token, TokenKind, TypeError, BUILTINS,
+};
+use crate::driver::CompilerError;
+use crate::json::{json_string, span_json};
+use crate::query::QueryError;
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+// ===========================================================================
+// DIAGNOSTICS
+// ===========================================================================
+
+/// Whether a diagnostic stops the compile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// A lint finding at its default level
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// An edit that resolves a diagnostic: replace `span` with `replacement`.
+/// An empty span inserts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// One error, ready to be rendered against the source it is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Printed beside the carets under `span`
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    /// Suggested edits, printed as help for people and applied by tools
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: Vec::new(),
+            help: None,
+            fixes: Vec::new(),
+        }
+    }
+
+    /// Point at `span`, without a label
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Point at `span` and say what is wrong there
+    pub fn with_label(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.span = Some(span);
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_fix(
+        mut self,
+        message: impl Into<String>,
+        span: Span,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.fixes.push(Fix {
+            message: message.into(),
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    /// The diagnostic as one line of JSON, in the schema described above
+    pub fn to_json(&self, file: &str) -> String {
+        let optional =
+            |text: Option<&String>| text.map_or_else(|| "null".to_string(), |t| json_string(t));
+        let notes: Vec<String> = self.notes.iter().map(|note| json_string(note)).collect();
+        let fixes: Vec<String> = self
+            .fixes
+            .iter()
+            .map(|fix| {
+                format!(
+                    "{{\"message\": {}, \"span\": {}, \"replacement\": {}}}",
+                    json_string(&fix.message),
+                    span_json(fix.span),
+                    json_string(&fix.replacement)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"severity\": \"{}\", \"code\": \"{}\", \"message\": {}, \"file\": {}, \"span\": {}, \"label\": {}, \"notes\": [{}], \"help\": {}, \"fixes\": [{}]}}",
+            self.severity.as_str(),
+            self.code,
+            json_string(&self.message),
+            json_string(file),
+            self.span.map_or_else(|| "null".to_string(), span_json),
+            optional(self.label.as_ref()),
+            notes.join(", "),
+            optional(self.help.as_ref()),
+            fixes.join(", ")
+        )
+    }
+}
+
+/// The diagnostic for an error of the front end
+pub fn diagnose(error: &CompilerError) -> Diagnostic {
+    match error {
+        CompilerError::LexError(e) => diagnose_lex(e),
+        CompilerError::ParseError(e) => diagnose_parse(e),
+        CompilerError::TypeError(e) => diagnose_type(e),
+        CompilerError::QueryError(e) => diagnose_query(e),
+    }
+}
+
+fn diagnose_lex(error: &LexError) -> Diagnostic {
+    match error {
+        LexError::UnexpectedCharacter { ch, position } => {
+            Diagnostic::new("M0001", format!("unexpected character '{}'", ch)).with_label(
+                spanning(*position, &ch.to_string()),
+                "not part of any token",
+            )
+        },
+        LexError::UnterminatedString { position } => {
+            Diagnostic::new("M0002", "unterminated string literal")
+                .with_label(spanning(*position, "\""), "this string is never closed")
+                .with_help("end the string with a matching '\"'")
+        },
+        LexError::InvalidNumber { lexeme, position } => {
+            Diagnostic::new("M0003", format!("number '{}' is too large", lexeme))
+                .with_label(spanning(*position, lexeme), "does not fit in 32 bits")
+                .with_note(format!("numbers run from 0 to {}", i32::MAX))
+        },
+    }
+}
+
+fn diagnose_parse(error: &ParseError) -> Diagnostic {
+    let (diagnostic, expected, at) = match error {
+        ParseError::UnexpectedToken { expected, found } => (
+            Diagnostic::new(
+                "M0004",
+                format!("expected {}, found {}", expected, describe(found)),
+            )
+            .with_label(
+                Span::new(found.position, found.end),
+                format!("expected {}", expected),
+            ),
+            expected,
+            found.position,
+        ),
+        ParseError::UnexpectedEof { expected, position } => (
+            Diagnostic::new("M0005", format!("expected {}, found end of file", expected))
+                .with_label(
+                    Span::new(*position, *position),
+                    format!("expected {}", expected),
+                ),
+            expected,
+            *position,
+        ),
+    };
+    // A single missing symbol, such as `'='` or `')'`, can be put back
+    let symbol = expected
+        .strip_prefix('\'')
+        .and_then(|rest| rest.strip_suffix('\''))
+        .filter(|symbol| !symbol.contains(|ch: char| ch.is_alphanumeric() || ch == '\''));
+    match symbol {
+        Some(symbol) => {
+            diagnostic.with_fix(format!("insert `{}`", symbol), Span::new(at, at), symbol)
+        },
+        None => diagnostic,
+    }
+}
+
+fn diagnose_type(error: &TypeError) -> Diagnostic {
+    match error {
+        TypeError::UndefinedVariable(name, span) => {
+            Diagnostic::new("M0006", format!("cannot find variable '{}'", name))
+                .with_label(*span, "not declared before this point")
+                .with_help(format!("declare it first, as in `maybe {} = 0`", name))
+        },
+        TypeError::TypeMismatch {
+            expected,
+            found,
+            span,
+        } => {
+            let diagnostic = Diagnostic::new("M0007", "mismatched types")
+                .with_label(*span, format!("expected {}, found {}", expected, found));
+            if expected == "Bool" {
+                diagnostic.with_help("conditions are comparisons such as `x > 0` or `x is null`")
+            } else {
+                diagnostic
+            }
+        },
+        TypeError::InvalidOperation(message, span) => {
+            Diagnostic::new("M0008", lowercase_first(message)).with_span(*span)
+        },
+        TypeError::UnknownFunction(name, span) => {
+            let builtins: Vec<String> = BUILTINS
+                .iter()
+                .map(|b| format!("{}/{}", b.name, b.arity))
+                .collect();
+            let diagnostic = Diagnostic::new("M0009", format!("cannot find function '{}'", name))
+                .with_label(*span, "not a builtin")
+                .with_note(format!("the builtins are {}", builtins.join(", ")));
+            match closest_builtin(name) {
+                Some(builtin) => diagnostic.with_fix(
+                    format!("a builtin with a similar name exists: `{}`", builtin),
+                    spanning(span.start, name),
+                    builtin,
+                ),
+                None => diagnostic,
+            }
+        },
+        TypeError::ArityMismatch {
+            name,
+            expected,
+            found,
+            span,
+        } => Diagnostic::new(
+            "M0010",
+            format!(
+                "'{}' takes {} argument(s) but {} were given",
+                name, expected, found
+            ),
+        )
+        .with_label(*span, format!("expected {} argument(s)", expected)),
+    }
+}
+
+fn diagnose_query(error: &QueryError) -> Diagnostic {
+    let enumerated = "queries are answered at compile time, by running every possible world";
+    match error {
+        QueryError::DependsOnInput(query, span) => {
+            Diagnostic::new("M0011", format!("{} depends on program input", query))
+                .with_label(*span, "cannot be answered before the program runs")
+                .with_note(enumerated)
+        },
+        QueryError::TooManyCoins { query, coins, span } => Diagnostic::new(
+            "M0012",
+            format!("{} depends on {} coin flips", query, coins),
+        )
+        .with_label(*span, "too many worlds to enumerate")
+        .with_note(format!(
+            "at most {} coin flips can be enumerated",
+            EXACT_COIN_LIMIT
+        ))
+        .with_help("use --analyze or --runs for an estimate instead"),
+        QueryError::Unreachable(query, span) => {
+            Diagnostic::new("M0013", format!("no world reaches {}", query))
+                .with_label(*span, "every world stops before this query")
+                .with_note("a `given`, `surely` or `bye` earlier in the program ends every run")
+        },
+        QueryError::Runtime(e) => {
+            let diagnostic = Diagnostic::new("M0014", e.to_string()).with_note(enumerated);
+            match e.span() {
+                Some(span) => diagnostic.with_label(span, "while answering a query"),
+                None => diagnostic,
+            }
+        },
+    }
+}
+
+/// From `start` to just past `text`, for errors that only know where they start
+fn spanning(start: Position, text: &str) -> Span {
+    let mut end = start;
+    text.chars().for_each(|ch| end.advance(ch));
+    Span::new(start, end)
+}
+
+/// The builtin a misspelt function name most likely meant: the nearest by
+/// edit distance, if that is at most half the name's length
+fn closest_builtin(name: &str) -> Option<&'static str> {
+    BUILTINS
+        .iter()
+        .map(|builtin| (edit_distance(name, builtin.name), builtin.name))
+        .filter(|(distance, _)| *distance > 0 && *distance * 2 <= name.chars().count())
+        .min()
+        .map(|(_, builtin)| builtin)
+}
+
+/// Levenshtein distance, in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn describe(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Eof => "end of file".to_string(),
+        TokenKind::Identifier(name) => format!("identifier '{}'", name),
+        TokenKind::Number(n) => format!("number {}", n),
+        TokenKind::StringLiteral(_) => format!("string {}", token.lexeme),
+        _ => format!("'{}'", token.lexeme),
+    }
+}
+
+fn lowercase_first(message: &str) -> String {
+    let mut chars = message.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_lowercase().chain(chars).collect()
+    })
+}
+
+// ===========================================================================
+// ERROR CODES - Long Explanations for `--explain`
+// ===========================================================================
+
+/// A stable error code and what it means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub explanation: &'static str,
+}
+
+pub const ERROR_CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "M0001",
+        title: "unexpected character",
+        explanation: "\
+A character that cannot start any token appeared in the source.
+
+    maybe total = 5 @ 2
+
+Mist has no `@` operator. The only operators are `+`, `-`, `=` and the
+comparisons `==`, `!=`, `<`, `<=`, `>` and `>=`; anything else must be inside a
+string or a `//` comment.",
+    },
+    ErrorCode {
+        code: "M0002",
+        title: "unterminated string literal",
+        explanation: "\
+A string literal was opened with `\"` but the file ended before the closing `\"`.
+
+    blah \"hello
+
+Strings may span lines, so the missing quote is often far above the end of
+the file. Close the string:
+
+    blah \"hello\"",
+    },
+    ErrorCode {
+        code: "M0003",
+        title: "number too large",
+        explanation: "\
+An integer literal does not fit in Mist's 32-bit signed integers.
+
+    maybe big = 3000000000
+
+Literals run from 0 to 2147483647. Larger values can only arise from
+arithmetic, which saturates instead of overflowing.",
+    },
+    ErrorCode {
+        code: "M0004",
+        title: "unexpected token",
+        explanation: "\
+The parser found a token where the grammar does not allow it.
+
+    maybe x 5
+
+A declaration needs `=` between the name and its value:
+
+    maybe x = 5
+
+Every statement starts with `maybe`, `blah`, `surely`, `bye` or `given`.",
+    },
+    ErrorCode {
+        code: "M0005",
+        title: "unexpected end of file",
+        explanation: "\
+The file ended in the middle of a statement, such as after `blah` or after
+the `=` of a declaration. Finish the statement or remove it.",
+    },
+    ErrorCode {
+        code: "M0006",
+        title: "undefined variable",
+        explanation: "\
+A variable was used before any `maybe` declaration of it.
+
+    blah total
+    maybe total = 5
+
+Declarations only take effect from the statement after them. Move the
+declaration up:
+
+    maybe total = 5
+    blah total
+
+Note that a declared variable may still be null at runtime; that is not an
+error.",
+    },
+    ErrorCode {
+        code: "M0007",
+        title: "mismatched types",
+        explanation: "\
+An expression has a different type than its context needs.
+
+    maybe x = 5
+    surely x
+
+`surely`, `given` and `prob(...)` need a condition, that is a comparison or an
+`is null` test, while `bye` needs an integer:
+
+    surely x > 0",
+    },
+    ErrorCode {
+        code: "M0008",
+        title: "invalid operation",
+        explanation: "\
+An operation was applied to values it does not support, such as adding a
+string, comparing booleans, or declaring a variable whose value is a string,
+a condition or a probability.
+
+    maybe greeting = \"hello\"
+
+Variables only hold integers. Print strings directly with `blah` instead:
+
+    blah \"hello\"",
+    },
+    ErrorCode {
+        code: "M0009",
+        title: "unknown function",
+        explanation: "\
+A call names a function that is not a builtin. Mist has no user-defined
+functions; the builtins are abs(x), sign(x), min(a, b), max(a, b) and
+clamp(x, lo, hi).
+
+    blah maximum(a, b)
+
+Use the builtin's name:
+
+    blah max(a, b)",
+    },
+    ErrorCode {
+        code: "M0010",
+        title: "wrong number of arguments",
+        explanation: "\
+A builtin was called with more or fewer arguments than it takes.
+
+    blah clamp(x, 10)
+
+`clamp` takes the value and both bounds:
+
+    blah clamp(x, 0, 10)",
+    },
+    ErrorCode {
+        code: "M0011",
+        title: "query depends on input",
+        explanation: "\
+`expect(...)` and `prob(...)` are answered at compile time by running every
+possible world of the statements before them. A query that reads `input` or
+`arg(n)`, or follows a statement that does, has no answer until the program
+runs.
+
+    maybe n = input
+    blah expect(n)
+
+Use `--analyze` to compute distributions for a particular input instead.",
+    },
+    ErrorCode {
+        code: "M0012",
+        title: "query needs too many coin flips",
+        explanation: "\
+A query is answered by enumerating every outcome of the coin flips before it,
+and there are too many of them: each `maybe` declaration doubles the number of
+worlds, and at most 20 flips (about a million worlds) are enumerated.
+
+Move the query before declarations it does not depend on, or estimate the
+value with `--analyze` or `--runs`.",
+    },
+    ErrorCode {
+        code: "M0013",
+        title: "query is unreachable",
+        explanation: "\
+Every world stops before reaching the query: a `given` rejects it, a
+`surely` fails, or a `bye` exits first. An average over no worlds has no value.
+
+    maybe a = 1
+    given a > 5
+    blah prob(a is null)
+
+Here `a` is 1 or null, and neither is greater than 5.",
+    },
+    ErrorCode {
+        code: "M0014",
+        title: "query failed",
+        explanation: "\
+Running the worlds before a query raised a runtime error other than a failed
+`surely` or a strict-mode null operand, which only stop the world they happen
+in. Fix the error the message names; it would also happen when the program
+runs.",
+    },
+];
+
+/// The explanation of `code`, ignoring case, so that `m0006` works as well
+pub fn explain(code: &str) -> Option<&'static ErrorCode> {
+    ERROR_CODES
+        .iter()
+        .find(|entry| entry.code.eq_ignore_ascii_case(code))
+}
+
+// ===========================================================================
+// RENDERING
+// ===========================================================================
+
+/// Whether diagnostics are coloured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// When stderr is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice '{}' (expected auto, always or never)",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            },
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// `--message-format`: diagnostics for people, or JSON lines for tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl std::str::FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!(
+                "unknown message format '{}' (expected human or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// How the diagnostics of a compile are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Emitter {
+    pub format: MessageFormat,
+    /// Only used by the human format
+    pub color: bool,
+}
+
+impl Emitter {
+    /// The diagnostics about `source`, which was read from `file`, ready to print
+    pub fn emit(self, diagnostics: &[Diagnostic], file: &str, source: &str) -> String {
+        match self.format {
+            MessageFormat::Human => render_all(diagnostics, file, source, self.color),
+            MessageFormat::Json => diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_json(file) + "\n")
+                .collect(),
+        }
+    }
+}
+
+/// ANSI styles of the parts of a diagnostic
+const STYLE_ERROR: &str = "\x1b[1;31m";
+const STYLE_WARNING: &str = "\x1b[1;33m";
+const STYLE_EMPHASIS: &str = "\x1b[1m";
+const STYLE_GUTTER: &str = "\x1b[1;34m";
+const STYLE_RESET: &str = "\x1b[0m";
+
+fn severity_style(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => STYLE_ERROR,
+        Severity::Warning => STYLE_WARNING,
+    }
+}
+
+/// `text` in `style`, when colour is on
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("{}{}{}", style, text, STYLE_RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Render a diagnostic about `source`, which was read from `file`
+pub fn render(diagnostic: &Diagnostic, file: &str, source: &str, color: bool) -> String {
+    let style = severity_style(diagnostic.severity);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{}{}",
+        paint(
+            &format!("{}[{}]", diagnostic.severity.as_str(), diagnostic.code),
+            style,
+            color
+        ),
+        paint(&format!(": {}", diagnostic.message), STYLE_EMPHASIS, color)
+    );
+
+    let Some(span) = diagnostic.span else {
+        let _ = writeln!(out, "{} {}", paint("-->", STYLE_GUTTER, color), file);
+        for (kind, text) in footer(diagnostic) {
+            let _ = writeln!(
+                out,
+                "{} {}: {}",
+                paint("=", STYLE_GUTTER, color),
+                kind,
+                text
+            );
+        }
+        return out;
+    };
+
+    let line_number = span.start.line.to_string();
+    let pad = " ".repeat(line_number.len());
+    let gutter = paint("|", STYLE_GUTTER, color);
+    let _ = writeln!(
+        out,
+        "{}{} {}:{}",
+        pad,
+        paint("-->", STYLE_GUTTER, color),
+        file,
+        span.start
+    );
+    let _ = writeln!(out, "{} {}", pad, gutter);
+
+    // The file may end with a newline, leaving an empty line after it for EOF
+    let text = source.lines().nth(span.start.line - 1).unwrap_or_default();
+    let _ = writeln!(
+        out,
+        "{} {} {}",
+        paint(&line_number, STYLE_GUTTER, color),
+        gutter,
+        text
+    );
+
+    // Tabs are kept so that the carets line up however wide the terminal shows them
+    let indent: String = text
+        .chars()
+        .take(span.start.column - 1)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = if span.end.line == span.start.line {
+        span.end.column.saturating_sub(span.start.column)
+    } else {
+        text.chars().count() + 1 - span.start.column
+    };
+    let mut carets = "^".repeat(width.max(1));
+    if let Some(label) = &diagnostic.label {
+        carets = format!("{} {}", carets, label);
+    }
+    let _ = writeln!(
+        out,
+        "{} {} {}{}",
+        pad,
+        gutter,
+        indent,
+        paint(&carets, style, color)
+    );
+
+    let footer = footer(diagnostic);
+    if !footer.is_empty() {
+        let _ = writeln!(out, "{} {}", pad, gutter);
+    }
+    for (kind, text) in footer {
+        let _ = writeln!(
+            out,
+            "{} {} {}: {}",
+            pad,
+            paint("=", STYLE_GUTTER, color),
+            paint(kind, STYLE_EMPHASIS, color),
+            text
+        );
+    }
+    out
+}
+
+/// Several diagnostics for one file, a blank line apart, with a count at the
+/// end when there is more than one
+pub fn render_all(diagnostics: &[Diagnostic], file: &str, source: &str, color: bool) -> String {
+    let mut out = diagnostics
+        .iter()
+        .map(|diagnostic| render(diagnostic, file, source, color))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if diagnostics.len() > 1 {
+        let count = |severity: Severity| {
+            let n = diagnostics
+                .iter()
+                .filter(|d| d.severity == severity)
+                .count();
+            let noun = severity.as_str();
+            (
+                n,
+                if n == 1 {
+                    format!("1 {}", noun)
+                } else {
+                    format!("{} {}s", n, noun)
+                },
+            )
+        };
+        let ((errors, error_count), (warnings, warning_count)) =
+            (count(Severity::Error), count(Severity::Warning));
+        let (severity, summary) = match (errors, warnings) {
+            (_, 0) => (Severity::Error, format!("aborting due to {}", error_count)),
+            (0, _) => (Severity::Warning, format!("{} emitted", warning_count)),
+            _ => (
+                Severity::Error,
+                format!("aborting due to {}; {} emitted", error_count, warning_count),
+            ),
+        };
+        let _ = writeln!(
+            out,
+            "\n{}{}",
+            paint(severity.as_str(), severity_style(severity), color),
+            paint(&format!(": {}", summary), STYLE_EMPHASIS, color)
+        );
+    }
+    out
+}
+
+/// The notes, help and fixes printed under the snippet, in order
+fn footer(diagnostic: &Diagnostic) -> Vec<(&'static str, &str)> {
+    let notes = diagnostic.notes.iter().map(|note| ("note", note.as_str()));
+    notes
+        .chain(diagnostic.help.iter().map(|help| ("help", help.as_str())))
+        .chain(
+            diagnostic
+                .fixes
+                .iter()
+                .map(|fix| ("help", fix.message.as_str())),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::CompileOptions;
+    use crate::driver::{check_source_all, front_end};
+
+    fn report(source: &str) -> String {
+        let error = front_end(source).unwrap_err();
+        render(&diagnose(&error), "test.mist", source, false)
+    }
+
+    #[test]
+    fn test_render_type_error() {
+        assert_eq!(
+            report("maybe x = 1\nblah 1 + y"),
+            "\
+error[M0006]: cannot find variable 'y'
+ --> test.mist:2:10
+  |
+2 | blah 1 + y
+  |          ^ not declared before this point
+  |
+  = help: declare it first, as in `maybe y = 0`
+"
+        );
+        assert_eq!(
+            report("maybe x = 1\n\tblah x + \"no\""),
+            "\
+error[M0008]: arithmetic requires integer types
+ --> test.mist:2:7
+  |
+2 | \tblah x + \"no\"
+  | \t     ^^^^^^^^
+"
+        );
+    }
+
+    #[test]
+    fn test_render_lex_and_parse_errors() {
+        let lex = report("maybe x = 5 @ 2");
+        assert!(lex.starts_with("error[M0001]: unexpected character '@'\n"));
+        assert!(lex.contains("1 | maybe x = 5 @ 2\n  |             ^ not part of any token\n"));
+
+        // A statement cut short points just past the end of the file
+        let parse = report("maybe x =\n");
+        assert!(parse.starts_with("error[M0005]: expected number, string, identifier"));
+        assert!(parse.contains("found end of file\n --> test.mist:2:1\n"));
+        let parse = diagnose(&front_end("blah max(1, 2").unwrap_err());
+        assert_eq!(parse.code, "M0005");
+        assert_eq!(parse.fixes[0].replacement, ")");
+
+        let colored = render(
+            &diagnose(&front_end("blah max(1)").unwrap_err()),
+            "test.mist",
+            "blah max(1)",
+            true,
+        );
+        assert!(colored.starts_with("\x1b[1;31merror[M0010]\x1b[0m\x1b[1m: 'max' takes 2"));
+        assert!(colored.contains("\x1b[1;31m^^^^^^ expected 2 argument(s)\x1b[0m"));
+    }
+
+    #[test]
+    fn test_render_all_errors() {
+        let source = "blah 1 +\nblah y";
+        let errors = check_source_all(source, &CompileOptions::default()).unwrap_err();
+        let diagnostics: Vec<_> = errors.iter().map(diagnose).collect();
+        assert_eq!(
+            render_all(&diagnostics, "test.mist", source, false),
+            "\
+error[M0004]: expected number, string, identifier, 'input', 'arg', 'expect' or 'prob', found 'blah'
+ --> test.mist:2:1
+  |
+2 | blah y
+  | ^^^^ expected number, string, identifier, 'input', 'arg', 'expect' or 'prob'
+
+error[M0006]: cannot find variable 'y'
+ --> test.mist:2:6
+  |
+2 | blah y
+  |      ^ not declared before this point
+  |
+  = help: declare it first, as in `maybe y = 0`
+
+error: aborting due to 2 errors
+"
+        );
+    }
+
+    #[test]
+    fn test_json_diagnostics() {
+        let json = |source: &str| diagnose(&front_end(source).unwrap_err()).to_json("a.mist");
+        assert_eq!(
+            json("blah mx(1, 2)"),
+            r#"{"severity": "error", "code": "M0009", "message": "cannot find function 'mx'", "file": "a.mist", "span": {"start": {"line": 1, "column": 6, "utf16_column": 6, "offset": 5}, "end": {"line": 1, "column": 14, "utf16_column": 14, "offset": 13}}, "label": "not a builtin", "notes": ["the builtins are abs/1, min/2, max/2, clamp/3, sign/1"], "help": null, "fixes": [{"message": "a builtin with a similar name exists: `max`", "span": {"start": {"line": 1, "column": 6, "utf16_column": 6, "offset": 5}, "end": {"line": 1, "column": 8, "utf16_column": 8, "offset": 7}}, "replacement": "max"}]}"#
+        );
+        assert_eq!(
+            json("maybe 😀x 5"),
+            r#"{"severity": "error", "code": "M0001", "message": "unexpected character '😀'", "file": "a.mist", "span": {"start": {"line": 1, "column": 7, "utf16_column": 7, "offset": 6}, "end": {"line": 1, "column": 8, "utf16_column": 9, "offset": 10}}, "label": "not part of any token", "notes": [], "help": null, "fixes": []}"#
+        );
+        assert_eq!(
+            json("maybe x 5"),
+            r#"{"severity": "error", "code": "M0004", "message": "expected '=', found number 5", "file": "a.mist", "span": {"start": {"line": 1, "column": 9, "utf16_column": 9, "offset": 8}, "end": {"line": 1, "column": 10, "utf16_column": 10, "offset": 9}}, "label": "expected '='", "notes": [], "help": null, "fixes": [{"message": "insert `=`", "span": {"start": {"line": 1, "column": 9, "utf16_column": 9, "offset": 8}, "end": {"line": 1, "column": 9, "utf16_column": 9, "offset": 8}}, "replacement": "="}]}"#
+        );
+
+        // Fixes are printed as help for people
+        assert!(report("maybe x 5").ends_with("  = help: insert `=`\n"));
+        assert_eq!(closest_builtin("clmap"), Some("clamp"));
+        assert_eq!(closest_builtin("total"), None);
+    }
+
+    #[test]
+    fn test_every_code_is_explained() {
+        let sources = [
+            "maybe x = 5 @ 2",
+            "blah \"open",
+            "maybe x = 99999999999",
+            "maybe x 5",
+            "blah y",
+            "maybe x = 1\nsurely x",
+            "maybe s = \"text\"",
+            "blah frobnicate(1)",
+            "blah max(1)",
+            "maybe n = input\nblah expect(n)",
+            "maybe a = 1\ngiven a > 5\nblah prob(a is null)",
+        ];
+        for source in sources {
+            let code = diagnose(&front_end(source).unwrap_err()).code;
+            assert!(explain(code).is_some(), "{} is not explained", code);
+        }
+
+        let codes: Vec<&str> = ERROR_CODES.iter().map(|entry| entry.code).collect();
+        let mut sorted = codes.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(codes, sorted);
+        assert_eq!(
+            explain("m0006").map(|entry| entry.title),
+            Some("undefined variable")
+        );
+        assert_eq!(explain("M9999"), None);
+    }
+}
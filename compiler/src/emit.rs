@@ -0,0 +1,477 @@
+// Intermediate Output of the Compiler Phases
+//
+// `--emit tokens,ast,typed,ir,c` prints what each phase produced instead of
+// compiling: the lexer's tokens, the parser's AST, the AST after type checking
+// and query answering with the type of every expression, the IR it is lowered
+// to, and the generated C.
+// Tokens and the AST come from the recovering lexer and parser, so they are
+// printed even for a program with errors, which is when they are most wanted.
+//
+// Both trees are printed as an indented outline, one node per line with its
+// variant, the name, literal or operator it carries, its type once known,
+// and its span:
+//
+//     MaybeDeclaration x  1:1..1:16
+//       Add : Int  1:11..1:16
+//         Number 1 : Int  1:11..1:12
+//         Number 2 : Int  1:15..1:16
+//
+// or, with `--emit-format json`, as one JSON object keyed by phase, in which
+// every AST node is an object with a field per part of its variant:
+//
+//     {"kind": "MaybeDeclaration", "span": ..., "name": "x",
+//      "value": {"kind": "Add", "type": "Int", "span": ...,
+//                "left": {...}, "right": {...}}}
+
+use crate::compiler::{
+    build_ir, generate_c, type_check_expr_in_mode, type_check_statement, CompileOptions,
+    Expression, ExpressionKind, Lexer, NullMode, Parser, Span, Statement, StatementKind, Token,
+    Type, TypeEnvironment,
+};
+use crate::driver::{check_parsed, CompilerError};
+use crate::json::{json_object, json_string, span_json};
+use std::fmt::Write;
+
+// ===========================================================================
+// OPTIONS
+// ===========================================================================
+
+/// A phase whose output `--emit` prints, in pipeline order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Typed,
+    Ir,
+    C,
+}
+
+impl std::str::FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "typed" => Ok(Emit::Typed),
+            "ir" => Ok(Emit::Ir),
+            "c" => Ok(Emit::C),
+            _ => Err(format!(
+                "unknown emit kind '{}' (expected tokens, ast, typed, ir or c)",
+                s
+            )),
+        }
+    }
+}
+
+impl Emit {
+    fn name(self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::Typed => "typed",
+            Emit::Ir => "ir",
+            Emit::C => "c",
+        }
+    }
+}
+
+/// How `--emit` prints the phases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmitFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for EmitFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(EmitFormat::Text),
+            "json" => Ok(EmitFormat::Json),
+            _ => Err(format!(
+                "unknown emit format '{}' (expected text or json)",
+                s
+            )),
+        }
+    }
+}
+
+// ===========================================================================
+// SYNTAX TREES
+// ===========================================================================
+
+/// Prints the AST, typing every expression by `type_check_expr` against
+/// `env` when there is one
+#[derive(Clone, Copy)]
+struct TreePrinter<'a> {
+    env: Option<&'a TypeEnvironment>,
+    mode: NullMode,
+}
+
+impl TreePrinter<'_> {
+    fn ty(self, expr: &Expression) -> Option<Type> {
+        self.env
+            .and_then(|env| type_check_expr_in_mode(expr, env, self.mode).ok())
+    }
+
+    /// The expression and its subexpressions, one per line, indented by depth
+    fn expression_text(self, out: &mut String, expr: &Expression, depth: usize) {
+        let (value, children): (Option<String>, Vec<&Expression>) = match &expr.kind {
+            ExpressionKind::Number(n) => (Some(n.to_string()), Vec::new()),
+            ExpressionKind::Variable(x) => (Some(x.clone()), Vec::new()),
+            ExpressionKind::StringLiteral(s) => (Some(format!("{:?}", s)), Vec::new()),
+            ExpressionKind::Input | ExpressionKind::Error => (None, Vec::new()),
+            ExpressionKind::Argument(e)
+            | ExpressionKind::IsNull(e)
+            | ExpressionKind::Expect(e)
+            | ExpressionKind::Prob(e) => (None, vec![e]),
+            ExpressionKind::Call(name, args) => (Some(name.clone()), args.iter().collect()),
+            ExpressionKind::Add(e1, e2) | ExpressionKind::Subtract(e1, e2) => (None, vec![e1, e2]),
+            ExpressionKind::Comparison(op, e1, e2) => (Some(op.symbol().to_string()), vec![e1, e2]),
+            ExpressionKind::Exact(r, query) => (Some(r.to_string()), vec![query]),
+        };
+        let ty = self.ty(expr).map(|ty| ty.name());
+        write_line(
+            out,
+            depth,
+            expr.kind.name(),
+            value.as_deref(),
+            ty.as_deref(),
+            expr.span,
+        );
+        for child in children {
+            self.expression_text(out, child, depth + 1);
+        }
+    }
+
+    fn statement_text(self, out: &mut String, stmt: &Statement) {
+        let name = match &stmt.kind {
+            StatementKind::MaybeDeclaration(x, _) => Some(x.text.as_str()),
+            _ => None,
+        };
+        write_line(out, 0, stmt.kind.name(), name, None, stmt.span);
+        match &stmt.kind {
+            StatementKind::MaybeDeclaration(_, e)
+            | StatementKind::BlahStatement(e)
+            | StatementKind::SurelyStatement(e)
+            | StatementKind::ByeStatement(e)
+            | StatementKind::GivenStatement(e) => self.expression_text(out, e, 1),
+            StatementKind::EntangledDeclaration(bindings) => {
+                for (x, e) in bindings {
+                    write_line(out, 1, "Binding", Some(&x.text), None, x.span.to(e.span));
+                    self.expression_text(out, e, 2);
+                }
+            },
+            StatementKind::Error => {},
+        }
+    }
+
+    /// The expression as a JSON object with a field per part of its variant
+    fn expression_json(self, expr: &Expression) -> String {
+        let node = |e: &Expression| self.expression_json(e);
+        let mut fields = vec![
+            ("kind", json_string(expr.kind.name())),
+            (
+                "type",
+                self.ty(expr)
+                    .map_or_else(|| "null".to_string(), |ty| json_string(&ty.name())),
+            ),
+            ("span", span_json(expr.span)),
+        ];
+        match &expr.kind {
+            ExpressionKind::Number(n) => fields.push(("value", n.to_string())),
+            ExpressionKind::Variable(x) => fields.push(("name", json_string(x))),
+            ExpressionKind::StringLiteral(s) => fields.push(("value", json_string(s))),
+            ExpressionKind::Input | ExpressionKind::Error => {},
+            ExpressionKind::Argument(e) => fields.push(("index", node(e))),
+            ExpressionKind::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(node).collect();
+                fields.push(("function", json_string(name)));
+                fields.push(("arguments", format!("[{}]", args.join(", "))));
+            },
+            ExpressionKind::Add(e1, e2) | ExpressionKind::Subtract(e1, e2) => {
+                fields.push(("left", node(e1)));
+                fields.push(("right", node(e2)));
+            },
+            ExpressionKind::Comparison(op, e1, e2) => {
+                fields.push(("operator", json_string(op.symbol())));
+                fields.push(("left", node(e1)));
+                fields.push(("right", node(e2)));
+            },
+            ExpressionKind::IsNull(e) | ExpressionKind::Expect(e) | ExpressionKind::Prob(e) => {
+                fields.push(("operand", node(e)));
+            },
+            ExpressionKind::Exact(r, query) => {
+                fields.push(("value", json_string(&r.to_string())));
+                fields.push(("query", node(query)));
+            },
+        }
+        json_object(&fields)
+    }
+
+    fn statement_json(self, stmt: &Statement) -> String {
+        let mut fields = vec![
+            ("kind", json_string(stmt.kind.name())),
+            ("span", span_json(stmt.span)),
+        ];
+        match &stmt.kind {
+            StatementKind::MaybeDeclaration(x, e) => {
+                fields.push(("name", json_string(&x.text)));
+                fields.push(("name_span", span_json(x.span)));
+                fields.push(("value", self.expression_json(e)));
+            },
+            StatementKind::EntangledDeclaration(bindings) => {
+                let bindings: Vec<String> = bindings
+                    .iter()
+                    .map(|(x, e)| {
+                        json_object(&[
+                            ("name", json_string(&x.text)),
+                            ("name_span", span_json(x.span)),
+                            ("value", self.expression_json(e)),
+                        ])
+                    })
+                    .collect();
+                fields.push(("bindings", format!("[{}]", bindings.join(", "))));
+            },
+            StatementKind::BlahStatement(e)
+            | StatementKind::SurelyStatement(e)
+            | StatementKind::ByeStatement(e)
+            | StatementKind::GivenStatement(e) => {
+                fields.push(("expression", self.expression_json(e)));
+            },
+            StatementKind::Error => {},
+        }
+        json_object(&fields)
+    }
+}
+
+/// One line of the outline: kind, value, type and span
+fn write_line(
+    out: &mut String,
+    depth: usize,
+    kind: &str,
+    value: Option<&str>,
+    ty: Option<&str>,
+    span: Span,
+) {
+    let _ = write!(out, "{}{}", "  ".repeat(depth), kind);
+    if let Some(value) = value {
+        let _ = write!(out, " {}", value);
+    }
+    if let Some(ty) = ty {
+        let _ = write!(out, " : {}", ty);
+    }
+    let _ = writeln!(out, "  {}..{}", span.start, span.end);
+}
+
+/// The program as an indented outline or a JSON array of statements. When
+/// typed, each statement is typed against the variables declared before it.
+fn program_tree(program: &[Statement], typed: bool, mode: NullMode, format: EmitFormat) -> String {
+    let mut env = TypeEnvironment::new();
+    let mut text = String::new();
+    let mut json = Vec::new();
+    for stmt in program {
+        let printer = TreePrinter {
+            env: typed.then_some(&env),
+            mode,
+        };
+        match format {
+            EmitFormat::Text => printer.statement_text(&mut text, stmt),
+            EmitFormat::Json => json.push(printer.statement_json(stmt)),
+        }
+        if typed {
+            // The program has been checked already, so this cannot fail
+            let _ = type_check_statement(stmt, &mut env, mode);
+        }
+    }
+    match format {
+        EmitFormat::Text => text,
+        EmitFormat::Json => format!("[{}]", json.join(", ")),
+    }
+}
+
+// ===========================================================================
+// PHASE OUTPUT
+// ===========================================================================
+
+fn tokens_text(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let _ = writeln!(
+            out,
+            "{}..{}  {} {:?}",
+            token.position,
+            token.end,
+            token.kind.name(),
+            token.lexeme
+        );
+    }
+    out
+}
+
+fn tokens_json(tokens: &[Token]) -> String {
+    let tokens: Vec<String> = tokens
+        .iter()
+        .map(|token| {
+            format!(
+                "{{\"kind\": \"{}\", \"lexeme\": {}, \"span\": {}}}",
+                token.kind.name(),
+                json_string(&token.lexeme),
+                span_json(Span::new(token.position, token.end))
+            )
+        })
+        .collect();
+    format!("[{}]", tokens.join(", "))
+}
+
+/// Print the requested phases of compiling `source`, in pipeline order. The
+/// errors of the front end come back alongside; the typed AST, the IR and
+/// the C are left out when there are any.
+pub fn emit(
+    source: &str,
+    options: &CompileOptions,
+    emits: &[Emit],
+    format: EmitFormat,
+) -> (String, Vec<CompilerError>) {
+    let mut emits = emits.to_vec();
+    emits.sort_unstable();
+    emits.dedup();
+
+    let (tokens, lex_errors) = Lexer::new(source).tokenize_recovering();
+    let mut parser = Parser::new(tokens.clone());
+    let (program, parse_errors) = parser.parse_recovering();
+    let mode = options.null_mode;
+    let errors = lex_errors
+        .into_iter()
+        .map(CompilerError::from)
+        .chain(parse_errors.into_iter().map(CompilerError::from))
+        .collect();
+    let lines = parser.statement_lines().to_vec();
+    let (checked, errors) = match check_parsed(program.clone(), lines, errors, options) {
+        Ok(checked) => (Some(checked), Vec::new()),
+        Err(errors) => (None, errors),
+    };
+
+    let mut sections = Vec::new();
+    for phase in emits {
+        let section = match (phase, &checked, format) {
+            (Emit::Tokens, _, EmitFormat::Text) => tokens_text(&tokens),
+            (Emit::Tokens, _, EmitFormat::Json) => tokens_json(&tokens),
+            (Emit::Ast, _, _) => program_tree(&program, false, mode, format),
+            (Emit::Typed, Some(checked), _) => program_tree(&checked.program, true, mode, format),
+            (Emit::Ir, Some(checked), EmitFormat::Text) => build_ir(checked, options).to_string(),
+            (Emit::Ir, Some(checked), EmitFormat::Json) => {
+                json_string(&build_ir(checked, options).to_string())
+            },
+            (Emit::C, Some(checked), EmitFormat::Text) => generate_c(checked.clone(), options),
+            (Emit::C, Some(checked), EmitFormat::Json) => {
+                json_string(&generate_c(checked.clone(), options))
+            },
+            (Emit::Typed | Emit::Ir | Emit::C, None, _) => continue,
+        };
+        sections.push((phase, section));
+    }
+
+    let output = match format {
+        EmitFormat::Text => sections
+            .iter()
+            .map(|(phase, section)| format!("=== {} ===\n{}", phase.name().to_uppercase(), section))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        EmitFormat::Json => {
+            let fields: Vec<String> = sections
+                .iter()
+                .map(|(phase, section)| format!("\"{}\": {}", phase.name(), section))
+                .collect();
+            format!("{{{}}}\n", fields.join(", "))
+        },
+    };
+    (output, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emit_default(source: &str, emits: &[Emit], format: EmitFormat) -> String {
+        let (output, errors) = emit(source, &CompileOptions::default(), emits, format);
+        assert!(errors.is_empty(), "{:?}", errors);
+        output
+    }
+
+    #[test]
+    fn test_typed_ast_outline() {
+        assert_eq!(
+            emit_default(
+                "maybe x = 1\nblah max(x, input) is null",
+                &[Emit::Typed],
+                EmitFormat::Text
+            ),
+            "\
+=== TYPED ===
+MaybeDeclaration x  1:1..1:12
+  Number 1 : Int  1:11..1:12
+BlahStatement  2:1..2:27
+  IsNull : Bool  2:6..2:27
+    Call max : Maybe(Int)  2:6..2:19
+      Variable x : Maybe(Int)  2:10..2:11
+      Input : Maybe(Int)  2:13..2:18
+"
+        );
+    }
+
+    #[test]
+    fn test_phases_print_in_pipeline_order() {
+        let output = emit_default(
+            "blah 1",
+            &[Emit::C, Emit::Tokens, Emit::Ir, Emit::Ast],
+            EmitFormat::Text,
+        );
+        let tokens = output.find("=== TOKENS ===").unwrap();
+        let ast = output.find("=== AST ===").unwrap();
+        let ir = output.find("=== IR ===").unwrap();
+        let c = output.find("=== C ===").unwrap();
+        assert!(tokens < ast && ast < ir && ir < c);
+        assert!(output.contains("%0 = int 1 : int\nprint %0\n"));
+        assert!(output.contains("1:1..1:5  Blah \"blah\"\n"));
+        assert!(output.contains("int main"));
+
+        let json = emit_default("blah 1", &[Emit::Ast, Emit::Tokens], EmitFormat::Json);
+        assert!(
+            json.starts_with("{\"tokens\": [{\"kind\": \"Blah\", \"lexeme\": \"blah\", \"span\": ")
+        );
+        assert!(json.contains("\"ast\": [{\"kind\": \"BlahStatement\", \"span\": "));
+        assert!(json.contains("\"expression\": {\"kind\": \"Number\", \"type\": null, \"span\": "));
+
+        let json = emit_default(
+            "maybe x = 1\nblah max(x, 2) < 3",
+            &[Emit::Typed],
+            EmitFormat::Json,
+        );
+        assert!(json.contains("\"kind\": \"MaybeDeclaration\", \"span\": "));
+        assert!(json
+            .contains("\"name\": \"x\", \"name_span\": {\"start\": {\"line\": 1, \"column\": 7"));
+        assert!(json.contains("\"value\": {\"kind\": \"Number\", \"type\": \"Int\""));
+        assert!(
+            json.contains("\"operator\": \"<\", \"left\": {\"kind\": \"Call\", \"type\": \"Int\"")
+        );
+        assert!(json.contains("\"function\": \"max\", \"arguments\": [{\"kind\": \"Variable\""));
+    }
+
+    #[test]
+    fn test_tokens_and_ast_survive_errors() {
+        let (output, errors) = emit(
+            "maybe x = @\nblah y",
+            &CompileOptions::default(),
+            &[Emit::Tokens, Emit::Ast, Emit::Typed],
+            EmitFormat::Text,
+        );
+        assert_eq!(errors.len(), 2);
+        assert!(output.contains("1:11..1:12  Error \"@\"\n"));
+        assert!(output.contains("MaybeDeclaration x  1:1..1:12\n  Error  1:11..1:12\n"));
+        assert!(!output.contains("=== TYPED ==="));
+    }
+}
@@ -0,0 +1,236 @@
+// Canonical Layout of Mist Source
+//
+// `mist-compiler fmt <file.mist>...` rewrites every file in one canonical
+// layout, and `--check` only lists the files that are not in it, for CI. The
+// layout is one statement per line, printed by `Display` with single spaces
+// around `=`, `+`, `-` and the comparisons and after commas. Of the original
+// spacing only blank lines survive, at most one between two items.
+//
+// Comments are not in the AST: the lexer keeps them aside as trivia and they
+// are put back by position. A comment on the line a statement ends on, or
+// inside a statement spread over several lines, stays after that statement;
+// any other comment goes on its own line above the statement that follows.
+//
+//     maybe   x=1+2   // two               maybe x = 1 + 2 // two
+//     // shown next                        // shown next
+//                                  =>
+//                                          blah x
+//
+//     blah x
+//
+// The `//! line <n> ...` expectations of test files name a statement by its
+// line, so they are renumbered to follow the statement when it moves.
+// Lines end in CRLF if the source's first line does, and in LF otherwise.
+// Formatting formatted source changes nothing.
+
+use crate::compiler::{Lexer, Parser, Statement, Token};
+use crate::driver::CompilerError;
+use std::collections::HashMap;
+
+/// The source in canonical layout, or the lex and parse errors that keep it
+/// from being formatted; type errors do not
+pub fn format_source(source: &str) -> Result<String, Vec<CompilerError>> {
+    let mut lexer = Lexer::new(source);
+    let (tokens, lex_errors) = lexer.tokenize_recovering();
+    let (program, parse_errors) = Parser::new(tokens).parse_recovering();
+
+    let errors: Vec<CompilerError> = lex_errors
+        .into_iter()
+        .map(CompilerError::from)
+        .chain(parse_errors.into_iter().map(CompilerError::from))
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let formatted = format_program(&program, lexer.comments());
+    let crlf = source
+        .split('\n')
+        .next()
+        .is_some_and(|line| line.ends_with('\r'));
+    Ok(if crlf {
+        formatted.replace('\n', "\r\n")
+    } else {
+        formatted
+    })
+}
+
+/// Lay out a parsed program with the comments of its source, both in source order
+pub fn format_program(program: &[Statement], comments: &[Token]) -> String {
+    // Comments above each statement, the last entry being those after the
+    // final statement, and comments after each statement
+    let mut leading: Vec<Vec<&Token>> = vec![Vec::new(); program.len() + 1];
+    let mut trailing: Vec<Vec<&Token>> = vec![Vec::new(); program.len()];
+    for comment in comments {
+        let next = program.partition_point(|stmt| stmt.span.start.offset < comment.position.offset);
+        match next.checked_sub(1) {
+            Some(i) if program[i].span.end.line >= comment.position.line => {
+                trailing[i].push(comment);
+            },
+            _ => leading[next].push(comment),
+        }
+    }
+
+    let mut layout = Layout::default();
+    for (i, stmt) in program.iter().enumerate() {
+        for comment in &leading[i] {
+            layout.comment(comment);
+        }
+        let mut text = stmt.to_string();
+        for comment in &trailing[i] {
+            text.push(' ');
+            text.push_str(comment.lexeme.trim_end());
+        }
+        layout.push(stmt.span.start.line, stmt.span.end.line, text);
+        let line = layout.lines.len();
+        layout.moved.entry(stmt.span.start.line).or_insert(line);
+    }
+    for comment in &leading[program.len()] {
+        layout.comment(comment);
+    }
+    layout.finish()
+}
+
+/// Output lines as they are laid out
+#[derive(Default)]
+struct Layout {
+    lines: Vec<String>,
+    /// Source line the previous item ended on
+    last_line: usize,
+    /// Output line of the first statement starting on each source line
+    moved: HashMap<usize, usize>,
+    /// Indices of the lines holding a `//!` comment
+    expectations: Vec<usize>,
+}
+
+impl Layout {
+    /// Add an item that spanned source lines `start..=end`, after a blank line
+    /// if there was at least one before it
+    fn push(&mut self, start: usize, end: usize, text: String) {
+        if !self.lines.is_empty() && start > self.last_line + 1 {
+            self.lines.push(String::new());
+        }
+        self.lines.push(text);
+        self.last_line = end;
+    }
+
+    /// Add a comment on its own line
+    fn comment(&mut self, comment: &Token) {
+        let text = comment.lexeme.trim_end().to_string();
+        let expectation = text.starts_with("//!");
+        let line = comment.position.line;
+        self.push(line, line, text);
+        if expectation {
+            self.expectations.push(self.lines.len() - 1);
+        }
+    }
+
+    fn finish(mut self) -> String {
+        for &index in &self.expectations {
+            if let Some(text) = renumber(&self.lines[index], &self.moved) {
+                self.lines[index] = text;
+            }
+        }
+        let mut output = self.lines.join("\n");
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output
+    }
+}
+
+/// A `//! line <n> ...` expectation, with n replaced by the output line of
+/// the statement that started on source line n
+fn renumber(comment: &str, moved: &HashMap<usize, usize>) -> Option<String> {
+    let rest = comment
+        .strip_prefix("//!")?
+        .trim_start()
+        .strip_prefix("line")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let claim = rest.trim_start();
+    let digits = claim.len() - claim.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let line = moved.get(&claim[..digits].parse().ok()?)?;
+    Some(format!("//! line {}{}", line, &claim[digits..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SAMPLES;
+
+    fn format(source: &str) -> String {
+        format_source(source).expect("source should parse")
+    }
+
+    #[test]
+    fn test_format_normalises_spacing() {
+        let source = "maybe   x=1+2\n  blah x-1  \nmaybe (a,b)=(x,   \"say \\\"hi\\\"\")\n\
+                      surely max(a,x)>=0\ngiven a is   null bye 3";
+        assert_eq!(
+            format(source),
+            "maybe x = 1 + 2\nblah x - 1\nmaybe (a, b) = (x, \"say \\\"hi\\\"\")\n\
+             surely max(a, x) >= 0\ngiven a is null\nbye 3\n"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_comments_and_blank_lines() {
+        let source = "// header\n\n\n\nmaybe x = 1   // trailing  \n  // above blah\n\
+                      blah x +\n  // inside\n  2\n\n// at the end\n";
+        assert_eq!(
+            format(source),
+            "// header\n\nmaybe x = 1 // trailing\n// above blah\nblah x + 2 // inside\n\
+             \n// at the end\n"
+        );
+        assert_eq!(format("// only a comment"), "// only a comment\n");
+        assert_eq!(
+            format("maybe x=1\r\n\r\n\r\nblah x // crlf\r\n"),
+            "maybe x = 1\r\n\r\nblah x // crlf\r\n"
+        );
+        assert_eq!(format(""), "");
+    }
+
+    #[test]
+    fn test_format_is_idempotent_and_preserves_the_program() {
+        // The samples are kept in canonical layout, CRLF line endings and all
+        for source in SAMPLES {
+            assert_eq!(format(source), source);
+        }
+
+        let sources = [
+            SAMPLES[0],
+            "maybe a=1 blah a // same line\n\n\n//x\nblah \"tab\\there\"//y\n",
+        ];
+        for source in sources {
+            let once = format(source);
+            assert_eq!(format(&once), once);
+
+            let parse = |text: &str| {
+                let tokens = Lexer::new(text).tokenize().unwrap();
+                Parser::new(tokens).parse().unwrap()
+            };
+            assert_eq!(parse(&once), parse(source));
+        }
+    }
+
+    #[test]
+    fn test_format_renumbers_expectations() {
+        let source = "maybe a = 10\n\n\nmaybe b = 2\nblah a+b\n\
+                      //! line 5 prints 12 with p = 0.25\n//! line 9 prints 1 with p = 1\n";
+        assert_eq!(
+            format(source),
+            "maybe a = 10\n\nmaybe b = 2\nblah a + b\n\
+             //! line 4 prints 12 with p = 0.25\n//! line 9 prints 1 with p = 1\n"
+        );
+    }
+
+    #[test]
+    fn test_format_refuses_source_with_syntax_errors() {
+        let errors = format_source("maybe x = \nblah 1 +").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|e| matches!(e, CompilerError::ParseError(_))));
+    }
+}
@@ -0,0 +1,1324 @@
+// Intermediate Representation Between the Checked AST and the C Backend
+//
+// The checked program is lowered to three-address code before any C is
+// written. Every value is a numbered temporary defined once by a `let` with
+// an explicit type, and nullability is spelled out rather than implied by a
+// variable's type: a variable is a value slot and a null flag, read by
+// `value x` and `null_flag x`, and a nullable value is a `maybe` built from
+// the two. Each `maybe` declaration flips its coin with a `coin` instruction:
+//
+//     # line 1: Maybe declaration: x
+//     declare x
+//     %0 = coin : bool
+//     if %0 {
+//         %1 = int 1 : int
+//         store x, %1
+//     } else {
+//         set_null x
+//     }
+//     # line 2: Blah statement
+//     %2 = null_flag x : bool
+//     %3 = int 0 : int
+//     %4 = value x : int
+//     %5 = select %2, %3, %4 : int
+//     %6 = int 2 : int
+//     %7 = add %5, %6 : int
+//     print %7
+//
+// The null algebra is decided here: in propagate mode `+` becomes `add_maybe`
+// on nullable operands, and in strict mode a nullable operand goes through
+// `nonnull`, which stops the program on null. The backend only translates.
+//
+// The code is a forest: every temporary is used exactly once, by a later
+// instruction of the same block, and none is still unused when an instruction
+// with an effect runs. A backend may therefore evaluate a `let` where it is
+// used, as the C backend does by nesting expressions. Effects must still
+// happen in the order of the source, and C evaluates the operands of an
+// operator or call in no particular order, so no operation may have two
+// operands whose trees have an effect still to happen: all but the last of
+// them are computed by a `bind` instead, which a backend evaluates where it
+// stands, as the C backend does into a local:
+//
+//     %0 = input : maybe
+//     bind %1 = or_zero %0 : int
+//     %2 = input : maybe
+//     %3 = or_zero %2 : int
+//     %4 = sub %1, %3 : int
+//
+// A `bind` is in turn never preceded by an effect that is still to happen.
+// `verify` checks these rules and the types of every instruction; `--emit ir`
+// prints the dumps above.
+
+use crate::compiler::{
+    program_checksum, type_check_expr_in_mode, CheckedProgram, ComparisonOp, CompileOptions,
+    Expression, ExpressionKind, Name, NullMode, RuntimeError, StatementKind, TraceLevel, Type,
+    TypeEnvironment, BUILTINS,
+};
+use crate::rational::Rational;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// ===========================================================================
+// INSTRUCTIONS
+// ===========================================================================
+
+/// A temporary, defined by exactly one `let`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Temp(pub usize);
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+/// The type of a temporary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrType {
+    Int,
+    Bool,
+    Str,
+    /// The exact answer of a query, only ever a constant
+    Rational,
+    /// An integer that may be null
+    Maybe,
+}
+
+impl IrType {
+    pub fn name(self) -> &'static str {
+        match self {
+            IrType::Int => "int",
+            IrType::Bool => "bool",
+            IrType::Str => "str",
+            IrType::Rational => "rational",
+            IrType::Maybe => "maybe",
+        }
+    }
+}
+
+/// The computation of a `let`, reading only temporaries and variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Int(i32),
+    Bool(bool),
+    Str(String),
+    Rational(Rational),
+    /// The value slot of a variable, whatever its null flag says
+    Value(String),
+    NullFlag(String),
+    /// `condition ? a : b`
+    Select(Temp, Temp, Temp),
+    /// A maybe from a value and a null flag
+    Pack(Temp, Temp),
+    /// A maybe that is not null
+    Some(Temp),
+    /// The value of a maybe, with null read as 0
+    OrZero(Temp),
+    IsNull(Temp),
+    /// The value of a maybe; null stops the program with the message
+    NonNull(Temp, String),
+    Add(Temp, Temp),
+    Sub(Temp, Temp),
+    /// `+` and `-` on maybes, null when either operand is
+    AddMaybe(Temp, Temp),
+    SubMaybe(Temp, Temp),
+    /// Integers, or an integer and a query answer
+    Compare(ComparisonOp, Temp, Temp),
+    /// A builtin, over maybes
+    Call(String, Vec<Temp>),
+    Input,
+    Arg(Temp),
+    /// True with probability 1/2, or as the coin source decides
+    Coin,
+    /// The value, logged with the statement it belongs to (full trace level)
+    Trace {
+        line: usize,
+        statement: String,
+        value: Temp,
+    },
+}
+
+impl Op {
+    /// The temporaries the operation reads, in order
+    pub fn operands(&self) -> Vec<Temp> {
+        match self {
+            Op::Int(_)
+            | Op::Bool(_)
+            | Op::Str(_)
+            | Op::Rational(_)
+            | Op::Value(_)
+            | Op::NullFlag(_)
+            | Op::Input
+            | Op::Coin => Vec::new(),
+            Op::Some(a)
+            | Op::OrZero(a)
+            | Op::IsNull(a)
+            | Op::NonNull(a, _)
+            | Op::Arg(a)
+            | Op::Trace { value: a, .. } => vec![*a],
+            Op::Pack(a, b)
+            | Op::Add(a, b)
+            | Op::Sub(a, b)
+            | Op::AddMaybe(a, b)
+            | Op::SubMaybe(a, b)
+            | Op::Compare(_, a, b) => vec![*a, *b],
+            Op::Select(c, a, b) => vec![*c, *a, *b],
+            Op::Call(_, args) => args.clone(),
+        }
+    }
+
+    /// The same operation reading `f(t)` in place of each operand t, in order
+    pub fn map_operands(&self, mut f: impl FnMut(Temp) -> Temp) -> Op {
+        match self {
+            Op::Int(_)
+            | Op::Bool(_)
+            | Op::Str(_)
+            | Op::Rational(_)
+            | Op::Value(_)
+            | Op::NullFlag(_)
+            | Op::Input
+            | Op::Coin => self.clone(),
+            Op::Select(c, a, b) => Op::Select(f(*c), f(*a), f(*b)),
+            Op::Pack(a, b) => Op::Pack(f(*a), f(*b)),
+            Op::Some(a) => Op::Some(f(*a)),
+            Op::OrZero(a) => Op::OrZero(f(*a)),
+            Op::IsNull(a) => Op::IsNull(f(*a)),
+            Op::NonNull(a, message) => Op::NonNull(f(*a), message.clone()),
+            Op::Add(a, b) => Op::Add(f(*a), f(*b)),
+            Op::Sub(a, b) => Op::Sub(f(*a), f(*b)),
+            Op::AddMaybe(a, b) => Op::AddMaybe(f(*a), f(*b)),
+            Op::SubMaybe(a, b) => Op::SubMaybe(f(*a), f(*b)),
+            Op::Compare(op, a, b) => Op::Compare(*op, f(*a), f(*b)),
+            Op::Call(name, args) => Op::Call(name.clone(), args.iter().map(|a| f(*a)).collect()),
+            Op::Arg(a) => Op::Arg(f(*a)),
+            Op::Trace {
+                line,
+                statement,
+                value,
+            } => Op::Trace {
+                line: *line,
+                statement: statement.clone(),
+                value: f(*value),
+            },
+        }
+    }
+
+    /// Whether evaluating the operation does more than compute its value
+    pub fn has_effect(&self) -> bool {
+        matches!(
+            self,
+            Op::Input | Op::Coin | Op::NonNull(..) | Op::Trace { .. }
+        )
+    }
+}
+
+/// Which operands of `op` must be bound, given which of them have an effect
+/// still to happen: all but the last, as C would evaluate them in any order.
+/// The condition of a `select` is always evaluated first, and only one of
+/// its other operands at all
+pub fn operands_to_bind(op: &Op, effects: &[bool]) -> Vec<bool> {
+    let last = match op {
+        Op::Select(..) => None,
+        _ => effects.iter().rposition(|effect| *effect),
+    };
+    effects
+        .iter()
+        .enumerate()
+        .map(|(index, effect)| *effect && last.is_some_and(|last| index < last))
+        .collect()
+}
+
+/// Turn the `let` of a temporary into a `bind`
+pub fn bind(instructions: &mut [Instruction], temp: Temp) {
+    for instruction in instructions.iter_mut().rev() {
+        if let Instruction::Let(t, ty, op) = instruction {
+            if *t == temp {
+                *instruction = Instruction::Bind(temp, *ty, op.clone());
+                return;
+            }
+        }
+    }
+}
+
+/// One step of a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Let(Temp, IrType, Op),
+    /// A `let` evaluated where it stands, so that its effects happen before
+    /// those of the operands evaluated after it
+    Bind(Temp, IrType, Op),
+    /// A variable, 0 and not null until it is stored to. A name declared
+    /// again in the source is a new variable each time, `x'1`, `x'2` and so
+    /// on, so that its initialiser still reads the one it shadows
+    Declare(String),
+    /// A variable whose null flag is never read, so it has only a value slot
+    DeclareValue(String),
+    /// Store an integer, leaving the null flag alone
+    Store(String, Temp),
+    /// Store a maybe's value and null flag
+    StoreMaybe(String, Temp),
+    SetNull(String),
+    If(Temp, Vec<Instruction>, Vec<Instruction>),
+    /// Print a value on its own line, a null maybe as `null`
+    Print(Temp),
+    /// Stop with the assertion exit code and the message unless the condition holds
+    Assert(Temp, String),
+    Exit(Temp),
+    /// Restart the run with fresh coin flips unless the condition holds
+    Condition(Temp),
+    /// Evaluate for the effects only, such as the coin of an unused
+    /// declaration or the operand of an `is null` that can never be null
+    Discard(Temp),
+    /// Log a declared variable's value, prefixed by the line at full trace level
+    TraceDeclaration {
+        name: String,
+        line: Option<usize>,
+        nullable: bool,
+    },
+    /// Log that a declaration's coin came up null
+    TraceNull {
+        name: String,
+        line: Option<usize>,
+    },
+}
+
+/// The instructions of one source statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub line: usize,
+    /// What the statement is, as the C backend comments it
+    pub comment: String,
+    pub instructions: Vec<Instruction>,
+}
+
+/// A lowered program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrProgram {
+    pub blocks: Vec<Block>,
+    /// Checksum of the source program, for coin traces
+    pub checksum: String,
+}
+
+impl IrProgram {
+    /// Whether a `given` makes the program rejection-sample
+    pub fn is_conditioned(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            block
+                .instructions
+                .iter()
+                .any(|instruction| matches!(instruction, Instruction::Condition(_)))
+        })
+    }
+
+    /// The number of coins flipped by a run that reaches the end
+    pub fn coin_count(&self) -> usize {
+        fn count(instructions: &[Instruction]) -> usize {
+            instructions
+                .iter()
+                .map(|instruction| match instruction {
+                    Instruction::Let(_, _, Op::Coin) | Instruction::Bind(_, _, Op::Coin) => 1,
+                    Instruction::If(_, then, otherwise) => count(then) + count(otherwise),
+                    _ => 0,
+                })
+                .sum()
+        }
+        self.blocks
+            .iter()
+            .map(|block| count(&block.instructions))
+            .sum()
+    }
+
+    /// How many lines of stdout each block prints when it runs to the end:
+    /// one per `print`, more for a string constant with line breaks in it.
+    /// C's `%s` stops at a NUL, so only the breaks before the first one count.
+    pub fn printed_lines(&self) -> Vec<usize> {
+        fn definition(instructions: &[Instruction], temp: Temp) -> Option<&Op> {
+            instructions
+                .iter()
+                .find_map(|instruction| match instruction {
+                    Instruction::Let(t, _, op) | Instruction::Bind(t, _, op) if *t == temp => {
+                        Some(op)
+                    },
+                    Instruction::If(_, then, otherwise) => {
+                        definition(then, temp).or_else(|| definition(otherwise, temp))
+                    },
+                    _ => None,
+                })
+        }
+        fn height(block: &[Instruction], temp: Temp) -> usize {
+            match definition(block, temp) {
+                Some(Op::Str(s)) => {
+                    s.split('\0')
+                        .next()
+                        .unwrap_or_default()
+                        .matches('\n')
+                        .count()
+                        + 1
+                },
+                Some(Op::Trace { value, .. }) => height(block, *value),
+                _ => 1,
+            }
+        }
+        fn lines(instructions: &[Instruction], block: &[Instruction]) -> usize {
+            instructions
+                .iter()
+                .map(|instruction| match instruction {
+                    Instruction::Print(value) => height(block, *value),
+                    Instruction::If(_, then, otherwise) => {
+                        lines(then, block).max(lines(otherwise, block))
+                    },
+                    _ => 0,
+                })
+                .sum()
+        }
+        self.blocks
+            .iter()
+            .map(|block| lines(&block.instructions, &block.instructions))
+            .collect()
+    }
+}
+
+/// The variable of the `version`-th declaration of `name`, counting from 0
+fn slot_name(name: &str, version: usize) -> String {
+    if version == 0 {
+        name.to_string()
+    } else {
+        format!("{}'{}", name, version)
+    }
+}
+
+/// The source name of a variable, and which declaration of it it is; no Mist
+/// name contains `'`
+pub fn split_slot(variable: &str) -> (&str, usize) {
+    match variable.split_once('\'') {
+        Some((name, version)) => (name, version.parse().unwrap_or(0)),
+        None => (variable, 0),
+    }
+}
+
+// ===========================================================================
+// LOWERING - From the Checked Program
+// ===========================================================================
+
+/// Lower a program that has been through the front end
+pub fn lower(checked: &CheckedProgram, options: &CompileOptions) -> IrProgram {
+    let mut lowering = Lowering {
+        env: &checked.type_env,
+        mode: options.null_mode,
+        trace: options.trace,
+        line: 0,
+        types: Vec::new(),
+        effects: Vec::new(),
+        instructions: Vec::new(),
+        versions: HashMap::new(),
+    };
+    let blocks = checked
+        .program
+        .iter()
+        .enumerate()
+        .map(|(index, stmt)| {
+            lowering.line = checked.lines.get(index).copied().unwrap_or(0);
+            let comment = lowering.lower_statement(&stmt.kind, stmt.span.start.line);
+            Block {
+                line: lowering.line,
+                comment,
+                instructions: std::mem::take(&mut lowering.instructions),
+            }
+        })
+        .collect();
+    IrProgram {
+        blocks,
+        checksum: program_checksum(&checked.program),
+    }
+}
+
+struct Lowering<'a> {
+    env: &'a TypeEnvironment,
+    mode: NullMode,
+    trace: TraceLevel,
+    /// Source line of the statement being lowered
+    line: usize,
+    /// Type of each temporary so far
+    types: Vec<IrType>,
+    /// Whether each temporary has an effect still to happen where it is used
+    effects: Vec<bool>,
+    /// The block being filled
+    instructions: Vec<Instruction>,
+    /// How many times each variable has been declared so far
+    versions: HashMap<String, usize>,
+}
+
+impl Lowering<'_> {
+    /// Append `let %n = op` and return %n, binding the operands that have to be
+    fn emit(&mut self, op: Op) -> Temp {
+        let temp = Temp(self.types.len());
+        // Lowering only builds well-typed operations, as `verify` checks
+        let ty = op_type(&op, |t| self.types.get(t.0).copied()).unwrap_or(IrType::Int);
+        let operands = op.operands();
+        let effects: Vec<bool> = operands.iter().map(|t| self.effects[t.0]).collect();
+        for (operand, bound) in operands.iter().zip(operands_to_bind(&op, &effects)) {
+            if bound {
+                bind(&mut self.instructions, *operand);
+                self.effects[operand.0] = false;
+            }
+        }
+        self.types.push(ty);
+        self.effects
+            .push(op.has_effect() || operands.iter().any(|t| self.effects[t.0]));
+        self.instructions.push(Instruction::Let(temp, ty, op));
+        temp
+    }
+
+    /// Lower into a separate list of instructions, as for a branch of an `if`
+    fn nested(&mut self, lower: impl FnOnce(&mut Self)) -> Vec<Instruction> {
+        let outer = std::mem::take(&mut self.instructions);
+        lower(self);
+        std::mem::replace(&mut self.instructions, outer)
+    }
+
+    /// Lower one statement into `instructions`, returning its comment
+    fn lower_statement(&mut self, stmt: &StatementKind, source_line: usize) -> String {
+        match stmt {
+            StatementKind::MaybeDeclaration(name, expr) => {
+                self.lower_coin_declaration(&[(name.clone(), expr.clone())]);
+                format!("Maybe declaration: {}", name)
+            },
+
+            StatementKind::EntangledDeclaration(bindings) => {
+                self.lower_coin_declaration(bindings);
+                let names: Vec<&str> = bindings
+                    .iter()
+                    .map(|(name, _)| name.text.as_str())
+                    .collect();
+                format!("Entangled maybe declaration: {}", names.join(", "))
+            },
+
+            StatementKind::BlahStatement(expr) => {
+                // Null prints as `null` rather than collapsing to 0
+                let value = if self.is_nullable(expr) {
+                    self.lower_maybe(expr)
+                } else {
+                    self.lower_value(expr)
+                };
+                let value = self.traced(&format!("blah {}", expr), value);
+                self.instructions.push(Instruction::Print(value));
+                "Blah statement".to_string()
+            },
+
+            StatementKind::SurelyStatement(expr) => {
+                let condition = self.lower_value(expr);
+                let condition = self.traced(&format!("surely {}", expr), condition);
+                let message = format!("surely failed at line {}: {}", source_line, expr);
+                self.instructions
+                    .push(Instruction::Assert(condition, message));
+                format!("Surely statement (line {})", source_line)
+            },
+
+            StatementKind::ByeStatement(expr) => {
+                // The variable read already maps null to 0
+                let status = self.lower_value(expr);
+                let status = self.traced(&format!("bye {}", expr), status);
+                self.instructions.push(Instruction::Exit(status));
+                "Bye statement".to_string()
+            },
+
+            StatementKind::GivenStatement(expr) => {
+                let condition = self.lower_value(expr);
+                let condition = self.traced(&format!("given {}", expr), condition);
+                self.instructions.push(Instruction::Condition(condition));
+                format!("Given statement: {}", expr)
+            },
+
+            StatementKind::Error => unreachable!("programs with errors stop at the front end"),
+        }
+    }
+
+    /// Declare each variable and bind all of them on one shared coin flip
+    fn lower_coin_declaration(&mut self, bindings: &[(Name, Expression)]) {
+        // New slots, so that the initialisers still read the variables they shadow
+        let slots: Vec<String> = bindings
+            .iter()
+            .map(|(name, _)| {
+                let name = name.text.as_str();
+                slot_name(name, self.versions.get(name).copied().unwrap_or(0))
+            })
+            .collect();
+        for slot in &slots {
+            self.instructions.push(Instruction::Declare(slot.clone()));
+        }
+        let coin = self.emit(Op::Coin);
+        // The trace of a declaration is the value it ends up with
+        let traced = self.trace >= TraceLevel::Declarations;
+        let line = (self.trace == TraceLevel::Full).then_some(self.line);
+
+        let then = self.nested(|lowering| {
+            let mut nullable = Vec::new();
+            for (slot, (_, expr)) in slots.iter().zip(bindings) {
+                if lowering.is_nullable(expr) {
+                    // A nullable initialiser passes its null on to the variable
+                    let value = lowering.lower_maybe(expr);
+                    lowering
+                        .instructions
+                        .push(Instruction::StoreMaybe(slot.clone(), value));
+                    nullable.push(slot);
+                } else {
+                    let value = lowering.lower_value(expr);
+                    lowering
+                        .instructions
+                        .push(Instruction::Store(slot.clone(), value));
+                }
+            }
+            if traced {
+                for slot in &slots {
+                    lowering.instructions.push(Instruction::TraceDeclaration {
+                        name: slot.clone(),
+                        line,
+                        nullable: nullable.contains(&slot),
+                    });
+                }
+            }
+        });
+        let otherwise = self.nested(|lowering| {
+            for slot in &slots {
+                lowering
+                    .instructions
+                    .push(Instruction::SetNull(slot.clone()));
+                if traced {
+                    lowering.instructions.push(Instruction::TraceNull {
+                        name: slot.clone(),
+                        line,
+                    });
+                }
+            }
+        });
+        self.instructions
+            .push(Instruction::If(coin, then, otherwise));
+        for (name, _) in bindings {
+            *self.versions.entry(name.text.clone()).or_default() += 1;
+        }
+    }
+
+    /// The slot a variable of the source currently names
+    fn slot(&self, name: &str) -> String {
+        slot_name(name, self.versions.get(name).map_or(0, |v| v - 1))
+    }
+
+    /// At full trace level, pass a statement's value through a logger
+    fn traced(&mut self, statement: &str, value: Temp) -> Temp {
+        if self.trace < TraceLevel::Full {
+            return value;
+        }
+        self.emit(Op::Trace {
+            line: self.line,
+            statement: statement.to_string(),
+            value,
+        })
+    }
+
+    /// A maybe for an expression whose type is Maybe(Int)
+    fn lower_maybe(&mut self, expr: &Expression) -> Temp {
+        match &expr.kind {
+            ExpressionKind::Variable(name) => {
+                let value = self.emit(Op::Value(self.slot(name)));
+                let flag = self.emit(Op::NullFlag(self.slot(name)));
+                self.emit(Op::Pack(value, flag))
+            },
+            ExpressionKind::Input => self.emit(Op::Input),
+            ExpressionKind::Argument(index) => {
+                let index = self.lower_value(index);
+                self.emit(Op::Arg(index))
+            },
+            ExpressionKind::Add(e1, e2) | ExpressionKind::Subtract(e1, e2)
+                if self.mode == NullMode::Propagate =>
+            {
+                let (a, b) = (self.lower_maybe(e1), self.lower_maybe(e2));
+                self.emit(match &expr.kind {
+                    ExpressionKind::Add(_, _) => Op::AddMaybe(a, b),
+                    _ => Op::SubMaybe(a, b),
+                })
+            },
+            ExpressionKind::Call(name, args) => {
+                let args = args.iter().map(|arg| self.lower_maybe(arg)).collect();
+                self.emit(Op::Call(name.clone(), args))
+            },
+            _ => {
+                let value = self.lower_value(expr);
+                self.emit(Op::Some(value))
+            },
+        }
+    }
+
+    /// The value of an expression, with null read as 0 wherever it is not an error
+    fn lower_value(&mut self, expr: &Expression) -> Temp {
+        match &expr.kind {
+            ExpressionKind::Number(n) => self.emit(Op::Int(*n)),
+
+            ExpressionKind::Input | ExpressionKind::Argument(_) | ExpressionKind::Call(_, _) => {
+                let maybe = self.lower_maybe(expr);
+                self.emit(Op::OrZero(maybe))
+            },
+
+            ExpressionKind::StringLiteral(s) => self.emit(Op::Str(s.clone())),
+
+            ExpressionKind::Variable(name) => {
+                if let Some(Type::Maybe(_)) = self.env.get(name) {
+                    let flag = self.emit(Op::NullFlag(self.slot(name)));
+                    let zero = self.emit(Op::Int(0));
+                    let value = self.emit(Op::Value(self.slot(name)));
+                    self.emit(Op::Select(flag, zero, value))
+                } else {
+                    self.emit(Op::Value(self.slot(name)))
+                }
+            },
+
+            ExpressionKind::Add(_, _) | ExpressionKind::Subtract(_, _)
+                if self.mode == NullMode::Propagate && self.is_nullable(expr) =>
+            {
+                let maybe = self.lower_maybe(expr);
+                self.emit(Op::OrZero(maybe))
+            },
+
+            ExpressionKind::Add(e1, e2) => {
+                let (a, b) = (self.lower_operand(e1, expr), self.lower_operand(e2, expr));
+                self.emit(Op::Add(a, b))
+            },
+
+            ExpressionKind::Subtract(e1, e2) => {
+                let (a, b) = (self.lower_operand(e1, expr), self.lower_operand(e2, expr));
+                self.emit(Op::Sub(a, b))
+            },
+
+            ExpressionKind::Comparison(op, e1, e2) => {
+                let (a, b) = (self.lower_operand(e1, expr), self.lower_operand(e2, expr));
+                self.emit(Op::Compare(*op, a, b))
+            },
+
+            ExpressionKind::IsNull(e) => match &e.kind {
+                ExpressionKind::Variable(name) => self.emit(Op::NullFlag(self.slot(name))),
+                _ if self.is_nullable(e) => {
+                    let maybe = self.lower_maybe(e);
+                    self.emit(Op::IsNull(maybe))
+                },
+                // Never null, but still evaluated for the input it reads
+                _ => {
+                    let value = self.lower_value(e);
+                    self.instructions.push(Instruction::Discard(value));
+                    self.emit(Op::Bool(false))
+                },
+            },
+
+            ExpressionKind::Exact(r, _) => self.emit(Op::Rational(*r)),
+
+            ExpressionKind::Expect(_) | ExpressionKind::Prob(_) => {
+                unreachable!("queries are answered by the front end")
+            },
+
+            ExpressionKind::Error => unreachable!("programs with errors stop at the front end"),
+        }
+    }
+
+    /// An integer operand of `parent`; strict mode fails on null instead of reading 0
+    fn lower_operand(&mut self, expr: &Expression, parent: &Expression) -> Temp {
+        if self.mode == NullMode::Strict && self.is_nullable(expr) {
+            let maybe = self.lower_maybe(expr);
+            let message = RuntimeError::NullOperand(parent.to_string(), parent.span).to_string();
+            self.emit(Op::NonNull(maybe, message))
+        } else {
+            self.lower_value(expr)
+        }
+    }
+
+    fn is_nullable(&self, expr: &Expression) -> bool {
+        matches!(
+            type_check_expr_in_mode(expr, self.env, self.mode),
+            Ok(Type::Maybe(_))
+        )
+    }
+}
+
+// ===========================================================================
+// VERIFIER
+// ===========================================================================
+
+/// The type of `op`, or why its operands do not fit it
+fn op_type(op: &Op, type_of: impl Fn(Temp) -> Option<IrType>) -> Result<IrType, String> {
+    use IrType::{Bool, Int, Maybe, Rational, Str};
+
+    let operand = |temp: Temp, expected: &[IrType]| -> Result<IrType, String> {
+        let ty = type_of(temp).ok_or_else(|| format!("{} is not defined", temp))?;
+        if expected.contains(&ty) {
+            Ok(ty)
+        } else {
+            let expected: Vec<&str> = expected.iter().map(|t| t.name()).collect();
+            Err(format!(
+                "{} is {}, expected {}",
+                temp,
+                ty.name(),
+                expected.join(" or ")
+            ))
+        }
+    };
+    match op {
+        Op::Int(_) | Op::Value(_) => Ok(Int),
+        Op::Bool(_) | Op::NullFlag(_) | Op::Coin => Ok(Bool),
+        Op::Str(_) => Ok(Str),
+        Op::Rational(_) => Ok(Rational),
+        Op::Input => Ok(Maybe),
+        Op::Select(c, a, b) => {
+            operand(*c, &[Bool])?;
+            let ty = operand(*a, &[Int, Bool, Maybe])?;
+            operand(*b, &[ty])
+        },
+        Op::Pack(value, flag) => {
+            operand(*value, &[Int])?;
+            operand(*flag, &[Bool])?;
+            Ok(Maybe)
+        },
+        Op::Some(a) | Op::Arg(a) => operand(*a, &[Int]).map(|_| Maybe),
+        Op::OrZero(a) | Op::NonNull(a, _) => operand(*a, &[Maybe]).map(|_| Int),
+        Op::IsNull(a) => operand(*a, &[Maybe]).map(|_| Bool),
+        Op::Add(a, b) | Op::Sub(a, b) => {
+            operand(*a, &[Int])?;
+            operand(*b, &[Int])
+        },
+        Op::AddMaybe(a, b) | Op::SubMaybe(a, b) => {
+            operand(*a, &[Maybe])?;
+            operand(*b, &[Maybe])
+        },
+        Op::Compare(_, a, b) => {
+            operand(*a, &[Int, Rational])?;
+            operand(*b, &[Int, Rational])?;
+            Ok(Bool)
+        },
+        Op::Call(name, args) => {
+            let builtin = BUILTINS
+                .iter()
+                .find(|builtin| builtin.name == name)
+                .ok_or_else(|| format!("'{}' is not a builtin", name))?;
+            if builtin.arity != args.len() {
+                return Err(format!(
+                    "'{}' takes {} arguments, not {}",
+                    name,
+                    builtin.arity,
+                    args.len()
+                ));
+            }
+            for arg in args {
+                operand(*arg, &[Maybe])?;
+            }
+            Ok(Maybe)
+        },
+        Op::Trace { value, .. } => operand(*value, &ALL_TYPES),
+    }
+}
+
+/// Check that the program is well formed: temporaries are defined once,
+/// used once, after their definition and in the same block, never live across
+/// an instruction with an effect, and of the types their uses expect; effects
+/// are bound where C would leave their order open; and variables are
+/// declared, with a null flag if it is used, before they are read or written
+pub fn verify(program: &IrProgram) -> Result<(), String> {
+    let mut verifier = Verifier {
+        definitions: Vec::new(),
+        effects: HashSet::new(),
+        declared: HashSet::new(),
+        flagged: HashSet::new(),
+    };
+    for block in &program.blocks {
+        verifier
+            .block(&block.instructions)
+            .map_err(|e| format!("line {} ({}): {}", block.line, block.comment, e))?;
+    }
+    Ok(())
+}
+
+struct Verifier {
+    /// The type and operation of each temporary, indexed by temporary; None
+    /// until its `let`
+    definitions: Vec<Option<(IrType, Op)>>,
+    /// The temporaries with an effect still to happen where they are used
+    effects: HashSet<Temp>,
+    declared: HashSet<String>,
+    /// The declared variables that have a null flag
+    flagged: HashSet<String>,
+}
+
+impl Verifier {
+    fn block(&mut self, instructions: &[Instruction]) -> Result<(), String> {
+        // Temporaries defined in this block and not used yet
+        let mut live: Vec<Temp> = Vec::new();
+        for instruction in instructions {
+            let uses = match instruction {
+                Instruction::Let(temp, ty, op) | Instruction::Bind(temp, ty, op) => {
+                    for operand in op.operands() {
+                        take(&mut live, operand)?;
+                    }
+                    self.define(*temp, *ty, op)?;
+                    let bound = matches!(instruction, Instruction::Bind(..));
+                    self.effects(*temp, op, bound, &live)?;
+                    live.push(*temp);
+                    continue;
+                },
+                Instruction::Declare(name) => {
+                    self.declare(name)?;
+                    self.flagged.insert(name.clone());
+                    Vec::new()
+                },
+                Instruction::DeclareValue(name) => {
+                    self.declare(name)?;
+                    Vec::new()
+                },
+                Instruction::Store(name, value) => {
+                    self.variable(name)?;
+                    vec![(*value, &[IrType::Int][..])]
+                },
+                Instruction::StoreMaybe(name, value) => {
+                    self.null_flag(name)?;
+                    vec![(*value, &[IrType::Maybe][..])]
+                },
+                Instruction::SetNull(name)
+                | Instruction::TraceDeclaration {
+                    name,
+                    nullable: true,
+                    ..
+                } => {
+                    self.null_flag(name)?;
+                    Vec::new()
+                },
+                Instruction::TraceDeclaration { name, .. }
+                | Instruction::TraceNull { name, .. } => {
+                    self.variable(name)?;
+                    Vec::new()
+                },
+                Instruction::If(condition, _, _)
+                | Instruction::Assert(condition, _)
+                | Instruction::Condition(condition) => vec![(*condition, &[IrType::Bool][..])],
+                Instruction::Print(value) | Instruction::Discard(value) => {
+                    vec![(*value, &ALL_TYPES[..])]
+                },
+                Instruction::Exit(status) => vec![(*status, &[IrType::Int][..])],
+            };
+            for (temp, expected) in uses {
+                take(&mut live, temp)?;
+                self.expect_type(temp, expected)?;
+            }
+            if let Some(temp) = live.first() {
+                return Err(format!(
+                    "{} is still unused at `{}`",
+                    temp,
+                    instruction_head(instruction)
+                ));
+            }
+            if let Instruction::If(_, then, otherwise) = instruction {
+                self.block(then)?;
+                self.block(otherwise)?;
+            }
+        }
+        match live.first() {
+            Some(temp) => Err(format!("{} is never used", temp)),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the effects of an operation's operands happen in order, and
+    /// note whether the temporary has an effect still to happen
+    fn effects(&mut self, temp: Temp, op: &Op, bound: bool, live: &[Temp]) -> Result<(), String> {
+        let operands = op.operands();
+        let effects: Vec<bool> = operands.iter().map(|t| self.effects.contains(t)).collect();
+        if let Some(index) = operands_to_bind(op, &effects).iter().position(|bind| *bind) {
+            return Err(format!(
+                "{} has an effect, but is not bound although a later operand of {} has one",
+                operands[index], temp
+            ));
+        }
+        if bound {
+            if let Some(earlier) = live.iter().find(|t| self.effects.contains(t)) {
+                return Err(format!(
+                    "{} is bound before the effect of {}, which comes first",
+                    temp, earlier
+                ));
+            }
+        } else if op.has_effect() || effects.contains(&true) {
+            self.effects.insert(temp);
+        }
+        Ok(())
+    }
+
+    fn declare(&mut self, name: &str) -> Result<(), String> {
+        if self.declared.insert(name.to_string()) {
+            Ok(())
+        } else {
+            Err(format!("'{}' is declared twice", name))
+        }
+    }
+
+    fn define(&mut self, temp: Temp, ty: IrType, op: &Op) -> Result<(), String> {
+        let actual = op_type(op, |t| self.type_of(t))?;
+        if actual != ty {
+            return Err(format!(
+                "{} is declared {} but is {}",
+                temp,
+                ty.name(),
+                actual.name()
+            ));
+        }
+        match op {
+            Op::Value(name) => self.variable(name)?,
+            Op::NullFlag(name) => self.null_flag(name)?,
+            _ => {},
+        }
+        if let Op::Compare(_, a, b) = op {
+            let computed_rational = |t: &Temp| {
+                matches!(
+                    self.definitions[t.0],
+                    Some((IrType::Rational, ref op)) if !matches!(op, Op::Rational(_))
+                )
+            };
+            if computed_rational(a) || computed_rational(b) {
+                return Err(format!(
+                    "{} compares a rational that is not a constant",
+                    temp
+                ));
+            }
+        }
+
+        if self.definitions.len() <= temp.0 {
+            self.definitions.resize_with(temp.0 + 1, || None);
+        }
+        if self.definitions[temp.0].is_some() {
+            return Err(format!("{} is defined twice", temp));
+        }
+        self.definitions[temp.0] = Some((ty, op.clone()));
+        Ok(())
+    }
+
+    fn type_of(&self, temp: Temp) -> Option<IrType> {
+        self.definitions
+            .get(temp.0)
+            .and_then(Option::as_ref)
+            .map(|(ty, _)| *ty)
+    }
+
+    fn expect_type(&self, temp: Temp, expected: &[IrType]) -> Result<(), String> {
+        match self.type_of(temp) {
+            Some(ty) if expected.contains(&ty) => Ok(()),
+            Some(ty) => Err(format!("{} has the wrong type {}", temp, ty.name())),
+            None => Err(format!("{} is used before it is defined", temp)),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<(), String> {
+        if self.declared.contains(name) {
+            Ok(())
+        } else {
+            Err(format!("'{}' is used before it is declared", name))
+        }
+    }
+
+    fn null_flag(&self, name: &str) -> Result<(), String> {
+        self.variable(name)?;
+        if self.flagged.contains(name) {
+            Ok(())
+        } else {
+            Err(format!("'{}' is declared without a null flag", name))
+        }
+    }
+}
+
+const ALL_TYPES: [IrType; 5] = [
+    IrType::Int,
+    IrType::Bool,
+    IrType::Str,
+    IrType::Rational,
+    IrType::Maybe,
+];
+
+/// Use a temporary defined earlier in the block
+fn take(live: &mut Vec<Temp>, temp: Temp) -> Result<(), String> {
+    match live.iter().position(|t| *t == temp) {
+        Some(position) => {
+            live.remove(position);
+            Ok(())
+        },
+        None => Err(format!(
+            "{} is not defined earlier in the block, or is used twice",
+            temp
+        )),
+    }
+}
+
+// ===========================================================================
+// TEXTUAL DUMP
+// ===========================================================================
+
+/// The first line of an instruction's dump, as named in verifier errors
+fn instruction_head(instruction: &Instruction) -> String {
+    let mut text = String::new();
+    let _ = write_instruction(&mut text, instruction, 0);
+    text.lines().next().unwrap_or_default().trim().to_string()
+}
+
+fn write_instruction(
+    out: &mut impl fmt::Write,
+    instruction: &Instruction,
+    depth: usize,
+) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    let line_prefix = |line: &Option<usize>| line.map_or(String::new(), |l| format!(" @{}", l));
+    match instruction {
+        Instruction::Let(temp, ty, op) => {
+            writeln!(out, "{}{} = {} : {}", indent, temp, op, ty.name())
+        },
+        Instruction::Bind(temp, ty, op) => {
+            writeln!(out, "{}bind {} = {} : {}", indent, temp, op, ty.name())
+        },
+        Instruction::Declare(name) => writeln!(out, "{}declare {}", indent, name),
+        Instruction::Store(name, value) => writeln!(out, "{}store {}, {}", indent, name, value),
+        Instruction::StoreMaybe(name, value) => {
+            writeln!(out, "{}store_maybe {}, {}", indent, name, value)
+        },
+        Instruction::DeclareValue(name) => writeln!(out, "{}declare_value {}", indent, name),
+        Instruction::SetNull(name) => writeln!(out, "{}set_null {}", indent, name),
+        Instruction::If(condition, then, otherwise) => {
+            writeln!(out, "{}if {} {{", indent, condition)?;
+            for instruction in then {
+                write_instruction(out, instruction, depth + 1)?;
+            }
+            if !otherwise.is_empty() {
+                writeln!(out, "{}}} else {{", indent)?;
+                for instruction in otherwise {
+                    write_instruction(out, instruction, depth + 1)?;
+                }
+            }
+            writeln!(out, "{}}}", indent)
+        },
+        Instruction::Print(value) => writeln!(out, "{}print {}", indent, value),
+        Instruction::Assert(condition, message) => {
+            writeln!(out, "{}assert {}, {:?}", indent, condition, message)
+        },
+        Instruction::Exit(status) => writeln!(out, "{}exit {}", indent, status),
+        Instruction::Condition(condition) => writeln!(out, "{}given {}", indent, condition),
+        Instruction::Discard(value) => writeln!(out, "{}discard {}", indent, value),
+        Instruction::TraceDeclaration {
+            name,
+            line,
+            nullable,
+        } => writeln!(
+            out,
+            "{}trace_declaration {}{}{}",
+            indent,
+            name,
+            if *nullable { " nullable" } else { "" },
+            line_prefix(line)
+        ),
+        Instruction::TraceNull { name, line } => {
+            writeln!(out, "{}trace_null {}{}", indent, name, line_prefix(line))
+        },
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Int(n) => write!(f, "int {}", n),
+            Op::Bool(b) => write!(f, "bool {}", b),
+            Op::Str(s) => write!(f, "str {:?}", s),
+            Op::Rational(r) => write!(f, "rational {}", r),
+            Op::Value(name) => write!(f, "value {}", name),
+            Op::NullFlag(name) => write!(f, "null_flag {}", name),
+            Op::Select(c, a, b) => write!(f, "select {}, {}, {}", c, a, b),
+            Op::Pack(value, flag) => write!(f, "pack {}, {}", value, flag),
+            Op::Some(a) => write!(f, "some {}", a),
+            Op::OrZero(a) => write!(f, "or_zero {}", a),
+            Op::IsNull(a) => write!(f, "is_null {}", a),
+            Op::NonNull(a, message) => write!(f, "nonnull {}, {:?}", a, message),
+            Op::Add(a, b) => write!(f, "add {}, {}", a, b),
+            Op::Sub(a, b) => write!(f, "sub {}, {}", a, b),
+            Op::AddMaybe(a, b) => write!(f, "add_maybe {}, {}", a, b),
+            Op::SubMaybe(a, b) => write!(f, "sub_maybe {}, {}", a, b),
+            Op::Compare(op, a, b) => write!(f, "cmp {} {}, {}", op.symbol(), a, b),
+            Op::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(Temp::to_string).collect();
+                write!(f, "call {}({})", name, args.join(", "))
+            },
+            Op::Input => write!(f, "input"),
+            Op::Arg(a) => write!(f, "arg {}", a),
+            Op::Coin => write!(f, "coin"),
+            Op::Trace {
+                line,
+                statement,
+                value,
+            } => write!(f, "trace {} @{} {:?}", value, line, statement),
+        }
+    }
+}
+
+/// The textual dump, one `# line N: comment` header per statement
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for block in &self.blocks {
+            writeln!(f, "# line {}: {}", block.line, block.comment)?;
+            for instruction in &block.instructions {
+                write_instruction(f, instruction, 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{lower_source, SAMPLES};
+
+    #[test]
+    fn test_lowering_dump() {
+        let options = CompileOptions {
+            null_mode: NullMode::Propagate,
+            trace: TraceLevel::None,
+            ..CompileOptions::default()
+        };
+        let program = lower_source("maybe x = 1\nblah x + 2\ngiven x is null", &options);
+        assert_eq!(
+            program.to_string(),
+            "\
+# line 1: Maybe declaration: x
+declare x
+%0 = coin : bool
+if %0 {
+    %1 = int 1 : int
+    store x, %1
+} else {
+    set_null x
+}
+# line 2: Blah statement
+%2 = value x : int
+%3 = null_flag x : bool
+%4 = pack %2, %3 : maybe
+%5 = int 2 : int
+%6 = some %5 : maybe
+%7 = add_maybe %4, %6 : maybe
+print %7
+# line 3: Given statement: x is null
+%8 = null_flag x : bool
+given %8
+"
+        );
+        assert!(program.is_conditioned());
+        assert_eq!(program.coin_count(), 1);
+    }
+
+    #[test]
+    fn test_lowered_programs_verify() {
+        let sources = SAMPLES.into_iter().chain([
+            "maybe c = 2\nsurely prob(c == 2) <= 1\nmaybe (a, b) = (input, arg(1))\n\
+             blah max(a, b) - 1 is null\nbye clamp(a, 0, b) + sign(c)",
+            "maybe x = 1\nmaybe y = 2\nmaybe (x, y) = (y, x)\nmaybe x = x + y\nblah x is null",
+            "blah input - abs(input + input)\nsurely max(input, input - input) > input",
+        ]);
+        for source in sources {
+            for null_mode in [NullMode::Zero, NullMode::Propagate, NullMode::Strict] {
+                for trace in [TraceLevel::None, TraceLevel::Declarations, TraceLevel::Full] {
+                    let options = CompileOptions {
+                        null_mode,
+                        trace,
+                        ..CompileOptions::default()
+                    };
+                    let program = lower_source(source, &options);
+                    assert_eq!(verify(&program), Ok(()), "{}", program);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_verifier_rejects_malformed_ir() {
+        let block = |instructions: Vec<Instruction>| IrProgram {
+            blocks: vec![Block {
+                line: 1,
+                comment: "Test".to_string(),
+                instructions,
+            }],
+            checksum: String::new(),
+        };
+        let declare = || Instruction::Declare("x".to_string());
+        let int = |n: usize| Instruction::Let(Temp(n), IrType::Int, Op::Int(1));
+        let rejected = |instructions: Vec<Instruction>, error: &str| {
+            assert_eq!(
+                verify(&block(instructions)),
+                Err(format!("line 1 (Test): {}", error))
+            );
+        };
+
+        rejected(
+            vec![
+                int(0),
+                Instruction::Print(Temp(0)),
+                Instruction::Print(Temp(0)),
+            ],
+            "%0 is not defined earlier in the block, or is used twice",
+        );
+        rejected(
+            vec![int(0), declare(), Instruction::Print(Temp(0))],
+            "%0 is still unused at `declare x`",
+        );
+        rejected(
+            vec![Instruction::Store("x".to_string(), Temp(0))],
+            "'x' is used before it is declared",
+        );
+        rejected(
+            vec![int(0), Instruction::Condition(Temp(0))],
+            "%0 has the wrong type int",
+        );
+        rejected(
+            vec![
+                int(0),
+                Instruction::Let(
+                    Temp(1),
+                    IrType::Int,
+                    Op::Call("abs".to_string(), vec![Temp(0)]),
+                ),
+            ],
+            "%0 is int, expected maybe",
+        );
+        rejected(
+            vec![
+                int(0),
+                Instruction::Let(
+                    Temp(1),
+                    IrType::Maybe,
+                    Op::Call("pow".to_string(), vec![Temp(0)]),
+                ),
+            ],
+            "'pow' is not a builtin",
+        );
+        rejected(
+            vec![
+                declare(),
+                Instruction::Let(Temp(0), IrType::Bool, Op::Value("x".to_string())),
+            ],
+            "%0 is declared bool but is int",
+        );
+        rejected(vec![declare(), declare()], "'x' is declared twice");
+        let input = |n: usize| Instruction::Let(Temp(n), IrType::Maybe, Op::Input);
+        rejected(
+            vec![
+                input(0),
+                input(1),
+                Instruction::Let(Temp(2), IrType::Maybe, Op::SubMaybe(Temp(0), Temp(1))),
+                Instruction::Print(Temp(2)),
+            ],
+            "%0 has an effect, but is not bound although a later operand of %2 has one",
+        );
+        rejected(
+            vec![
+                input(0),
+                Instruction::Bind(Temp(1), IrType::Maybe, Op::Input),
+                Instruction::Let(Temp(2), IrType::Maybe, Op::SubMaybe(Temp(0), Temp(1))),
+                Instruction::Print(Temp(2)),
+            ],
+            "%1 is bound before the effect of %0, which comes first",
+        );
+        rejected(vec![int(0)], "%0 is never used");
+    }
+}
@@ -0,0 +1,66 @@
+// JSON Output Helpers
+//
+// The compiler writes JSON in several places, `--runs` reports, diagnostics
+// and `--emit`, without a serialisation library. These build the pieces they
+// share: escaped strings, objects from already serialised fields, and spans.
+
+use crate::compiler::{Position, Span};
+use std::fmt::Write;
+
+/// A string as a JSON string literal, quoted and escaped
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// An object from keys and their values, which are JSON already
+pub fn json_object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("\"{}\": {}", key, value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// A span as JSON, both ends as line, column, UTF-16 column and byte offset
+pub fn span_json(span: Span) -> String {
+    let position = |p: Position| {
+        format!(
+            "{{\"line\": {}, \"column\": {}, \"utf16_column\": {}, \"offset\": {}}}",
+            p.line, p.column, p.utf16_column, p.offset
+        )
+    };
+    format!(
+        "{{\"start\": {}, \"end\": {}}}",
+        position(span.start),
+        position(span.end)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_escaping() {
+        assert_eq!(json_string("a\"b\\\n\u{1}é"), "\"a\\\"b\\\\\\n\\u0001é\"");
+        assert_eq!(
+            json_object(&[("kind", json_string("Add")), ("value", "null".to_string())]),
+            "{\"kind\": \"Add\", \"value\": null}"
+        );
+    }
+}
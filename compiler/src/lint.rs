@@ -0,0 +1,555 @@
+// Lints - Mistakes That Type-Check
+//
+// `mist-compiler lint <file.mist>...` looks for programs that are well typed
+// but almost certainly not what was meant:
+//
+//     maybe total = 10      unused_maybe: declared again before it is read
+//     maybe total = 20      redeclaration
+//     maybe spare = total   unobserved_variable: only `unused` reads it
+//     maybe unused = spare  unused_maybe
+//     blah 1 + 2            constant_blah
+//     blah total - total    self_subtraction
+//
+// Every lint has a name and a level: allowed, a warning, or denied, which makes
+// its findings errors. All lints warn by default; `--allow`, `--warn` and
+// `--deny` change that for a run, and a `// mist: allow(unused_maybe)` comment
+// anywhere in a file changes it for that file, overriding the command line.
+//
+// The lints walk the program the way the type checker does, statement by
+// statement with the TypeEnvironment of the variables declared so far.
+
+use crate::compiler::{
+    type_check_statement, CompileOptions, Expression, ExpressionKind, Lexer, Name, NullMode, Span,
+    Statement, StatementKind, Token, TypeEnvironment,
+};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::driver::{check_source_all, CompilerError};
+use std::collections::HashMap;
+
+// ===========================================================================
+// LINTS AND LEVELS
+// ===========================================================================
+
+/// A named check, as written in pragmas and on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A declaration whose value is never read
+    UnusedMaybe,
+    /// A declaration read only by declarations that are never observed
+    UnobservedVariable,
+    /// `blah` of an expression that is the same in every world
+    ConstantBlah,
+    /// `e - e`, which is 0
+    SelfSubtraction,
+    /// `maybe x` when `x` is already declared
+    Redeclaration,
+    /// A `// mist:` comment that could not be understood
+    InvalidPragma,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedMaybe,
+        Lint::UnobservedVariable,
+        Lint::ConstantBlah,
+        Lint::SelfSubtraction,
+        Lint::Redeclaration,
+        Lint::InvalidPragma,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedMaybe => "unused_maybe",
+            Lint::UnobservedVariable => "unobserved_variable",
+            Lint::ConstantBlah => "constant_blah",
+            Lint::SelfSubtraction => "self_subtraction",
+            Lint::Redeclaration => "redeclaration",
+            Lint::InvalidPragma => "invalid_pragma",
+        }
+    }
+}
+
+impl std::str::FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown lint '{}' (expected unused_maybe, unobserved_variable, \
+                     constant_blah, self_subtraction, redeclaration or invalid_pragma)",
+                    s
+                )
+            })
+    }
+}
+
+/// What becomes of a lint's findings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LintLevel {
+    Allow,
+    #[default]
+    Warn,
+    /// Findings are errors, and `lint` exits with status 1
+    Deny,
+}
+
+impl std::str::FromStr for LintLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(LintLevel::Allow),
+            "warn" => Ok(LintLevel::Warn),
+            "deny" => Ok(LintLevel::Deny),
+            _ => Err(format!(
+                "unknown lint level '{}' (expected allow, warn or deny)",
+                s
+            )),
+        }
+    }
+}
+
+/// The level of every lint, the default unless set otherwise
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintLevels {
+    levels: HashMap<Lint, LintLevel>,
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or_default()
+    }
+
+    /// The finding as reported at the lint's level, or None if it is allowed
+    fn apply(&self, lint: Lint, diagnostic: Diagnostic) -> Option<Diagnostic> {
+        let severity = match self.level(lint) {
+            LintLevel::Allow => return None,
+            LintLevel::Warn => Severity::Warning,
+            LintLevel::Deny => Severity::Error,
+        };
+        let mut diagnostic =
+            diagnostic.with_note(format!("`// mist: allow({})` turns this off", lint.name()));
+        diagnostic.severity = severity;
+        Some(diagnostic)
+    }
+}
+
+// ===========================================================================
+// LINTING
+// ===========================================================================
+
+/// The lint findings of a program that passes the front end, in source order,
+/// or the front end's errors
+pub fn lint_source(
+    source: &str,
+    options: &CompileOptions,
+    levels: &LintLevels,
+) -> Result<Vec<Diagnostic>, Vec<CompilerError>> {
+    let checked = check_source_all(source, options)?;
+    let mut lexer = Lexer::new(source);
+    let _ = lexer.tokenize_recovering();
+
+    let mut levels = levels.clone();
+    let mut findings = apply_pragmas(lexer.comments(), &mut levels);
+    findings.extend(lint_program(&checked.program, options.null_mode));
+    findings.sort_by_key(|(_, diagnostic)| diagnostic.span.map(|span| span.start.offset));
+    Ok(findings
+        .into_iter()
+        .filter_map(|(lint, diagnostic)| levels.apply(lint, diagnostic))
+        .collect())
+}
+
+/// Set the levels asked for by `// mist: <level>(<lint>, ...)` comments,
+/// returning findings for those that cannot be understood
+fn apply_pragmas(comments: &[Token], levels: &mut LintLevels) -> Vec<(Lint, Diagnostic)> {
+    let mut findings = Vec::new();
+    for comment in comments {
+        let text = comment.lexeme.trim_start_matches('/').trim();
+        let Some(pragma) = text.strip_prefix("mist:") else {
+            continue;
+        };
+        match parse_pragma(pragma.trim()) {
+            Ok((level, lints)) => {
+                for lint in lints {
+                    levels.set(lint, level);
+                }
+            },
+            Err(message) => findings.push((
+                Lint::InvalidPragma,
+                Diagnostic::new(Lint::InvalidPragma.name(), "invalid lint pragma")
+                    .with_label(Span::new(comment.position, comment.end), message)
+                    .with_help("write it as `// mist: allow(unused_maybe, redeclaration)`"),
+            )),
+        }
+    }
+    findings
+}
+
+fn parse_pragma(pragma: &str) -> Result<(LintLevel, Vec<Lint>), String> {
+    let (level, rest) = pragma
+        .split_once('(')
+        .ok_or_else(|| "expected '(' after the level".to_string())?;
+    let lints = rest
+        .trim_end()
+        .strip_suffix(')')
+        .ok_or_else(|| "expected ')' at the end".to_string())?;
+    let level = level.trim().parse()?;
+    let lints = lints
+        .split(',')
+        .map(|name| name.trim().parse())
+        .collect::<Result<_, _>>()?;
+    Ok((level, lints))
+}
+
+/// One binding of a variable, and the bindings and statements that read it
+struct Declaration<'a> {
+    name: &'a Name,
+    statement: &'a Statement,
+    /// Each reader is another declaration, or None for a statement that
+    /// observes the value: a blah, surely, given or bye
+    readers: Vec<Option<usize>>,
+    /// The line of the next declaration of the same name, if nothing read
+    /// this one before it
+    shadowed_on: Option<usize>,
+}
+
+/// Every finding of every lint in a checked program, whatever its level
+pub fn lint_program(program: &[Statement], mode: NullMode) -> Vec<(Lint, Diagnostic)> {
+    let mut findings = Vec::new();
+    let mut env = TypeEnvironment::new();
+    let mut declarations: Vec<Declaration> = Vec::new();
+    // The declaration each name currently refers to
+    let mut scope: HashMap<&str, usize> = HashMap::new();
+
+    for stmt in program {
+        let (bindings, observed): (Vec<(&Name, &Expression)>, Option<&Expression>) =
+            match &stmt.kind {
+                StatementKind::MaybeDeclaration(x, e) => (vec![(x, e)], None),
+                StatementKind::EntangledDeclaration(bindings) => {
+                    (bindings.iter().map(|(x, e)| (x, e)).collect(), None)
+                },
+                StatementKind::BlahStatement(e)
+                | StatementKind::SurelyStatement(e)
+                | StatementKind::ByeStatement(e)
+                | StatementKind::GivenStatement(e) => (Vec::new(), Some(e)),
+                StatementKind::Error => (Vec::new(), None),
+            };
+
+        if let StatementKind::BlahStatement(e) = &stmt.kind {
+            let literal = matches!(
+                e.kind,
+                ExpressionKind::Number(_) | ExpressionKind::StringLiteral(_)
+            );
+            if !literal && is_constant(e) {
+                findings.push((
+                    Lint::ConstantBlah,
+                    Diagnostic::new(
+                        Lint::ConstantBlah.name(),
+                        "blah prints the same value in every world",
+                    )
+                    .with_label(e.span, "constant expression")
+                    .with_help("print the value itself, or the variable it was meant to use"),
+                ));
+            }
+        }
+
+        // Readers, all of which see the declarations made before the statement
+        let first = declarations.len();
+        let readers = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, (_, e))| (Some(first + i), *e))
+            .chain(observed.map(|e| (None, e)));
+        for (reader, expr) in readers {
+            each_subexpression(expr, &mut |e| {
+                if let ExpressionKind::Variable(x) = &e.kind {
+                    if let Some(&declaration) = scope.get(x.as_str()) {
+                        declarations[declaration].readers.push(reader);
+                    }
+                }
+            });
+            findings.extend(self_subtractions(expr, mode));
+        }
+
+        for (i, (x, _)) in bindings.iter().enumerate() {
+            if env.contains_key(&x.text) {
+                let earlier = &declarations[scope[x.text.as_str()]];
+                findings.push((
+                    Lint::Redeclaration,
+                    Diagnostic::new(
+                        Lint::Redeclaration.name(),
+                        format!("'{}' is declared again", x),
+                    )
+                    .with_label(x.span, "redeclared here")
+                    .with_note(format!(
+                        "'{}' was first declared on line {}",
+                        x, earlier.statement.span.start.line
+                    ))
+                    .with_help("give the new value a name of its own"),
+                ));
+                let earlier = &mut declarations[scope[x.text.as_str()]];
+                if earlier.readers.is_empty() {
+                    earlier.shadowed_on = Some(stmt.span.start.line);
+                }
+            }
+            scope.insert(&x.text, first + i);
+            declarations.push(Declaration {
+                name: x,
+                statement: stmt,
+                readers: Vec::new(),
+                shadowed_on: None,
+            });
+        }
+
+        // The program has been checked already, so this cannot fail
+        let _ = type_check_statement(stmt, &mut env, mode);
+    }
+
+    // Readers come after what they read, so one backward pass settles which
+    // declarations reach an observing statement
+    let mut observed = vec![false; declarations.len()];
+    for (i, declaration) in declarations.iter().enumerate().rev() {
+        observed[i] = declaration
+            .readers
+            .iter()
+            .any(|reader| reader.is_none_or(|j| observed[j]));
+    }
+
+    for (declaration, observed) in declarations.iter().zip(observed) {
+        if observed || declaration.name.text.starts_with('_') {
+            continue;
+        }
+        let (lint, diagnostic) = if declaration.readers.is_empty() {
+            let mut diagnostic = Diagnostic::new(
+                Lint::UnusedMaybe.name(),
+                format!("the value of '{}' is never used", declaration.name),
+            )
+            .with_label(declaration.name.span, "never read");
+            if let Some(line) = declaration.shadowed_on {
+                diagnostic = diagnostic.with_note(format!(
+                    "it is declared again on line {} before anything reads it",
+                    line
+                ));
+            }
+            (Lint::UnusedMaybe, diagnostic)
+        } else {
+            let diagnostic = Diagnostic::new(
+                Lint::UnobservedVariable.name(),
+                format!(
+                    "'{}' never reaches a blah, surely, given or bye",
+                    declaration.name
+                ),
+            )
+            .with_label(
+                declaration.name.span,
+                "only read by variables that are never used",
+            );
+            (Lint::UnobservedVariable, diagnostic)
+        };
+        findings.push((
+            lint,
+            diagnostic.with_help(format!(
+                "remove it, or call it '_{}' if only its coin flip matters",
+                declaration.name
+            )),
+        ));
+    }
+
+    findings
+}
+
+/// `e - e` anywhere in `expr`, where both sides read the same value
+fn self_subtractions(expr: &Expression, mode: NullMode) -> Vec<(Lint, Diagnostic)> {
+    let mut findings = Vec::new();
+    each_subexpression(expr, &mut |e| {
+        let ExpressionKind::Subtract(left, right) = &e.kind else {
+            return;
+        };
+        if left != right || !is_repeatable(left) {
+            return;
+        }
+        let mut diagnostic = Diagnostic::new(
+            Lint::SelfSubtraction.name(),
+            format!("'{}' subtracts a value from itself", e),
+        );
+        // Only with null as 0 is it 0 in every world
+        diagnostic = if mode == NullMode::Zero {
+            diagnostic
+                .with_label(e.span, "always 0")
+                .with_fix("replace it with 0", e.span, "0")
+        } else {
+            diagnostic.with_label(e.span, format!("0 wherever '{}' is not null", left))
+        };
+        findings.push((Lint::SelfSubtraction, diagnostic));
+    });
+    findings
+}
+
+/// Whether `expr` has the same value in every world
+fn is_constant(expr: &Expression) -> bool {
+    let mut constant = true;
+    each_subexpression(expr, &mut |e| {
+        if matches!(
+            e.kind,
+            ExpressionKind::Variable(_)
+                | ExpressionKind::Input
+                | ExpressionKind::Argument(_)
+                | ExpressionKind::Expect(_)
+                | ExpressionKind::Prob(_)
+                | ExpressionKind::Exact(_, _)
+        ) {
+            constant = false;
+        }
+    });
+    constant
+}
+
+/// Whether evaluating `expr` twice gives the same value both times: it reads
+/// variables, literals, arguments and builtins of them, but no `input`, which
+/// reads the next line each time
+fn is_repeatable(expr: &Expression) -> bool {
+    let mut repeatable = true;
+    each_subexpression(expr, &mut |e| {
+        if matches!(e.kind, ExpressionKind::Input) {
+            repeatable = false;
+        }
+    });
+    repeatable
+}
+
+/// Call `visit` on `expr` and everything inside it, including the queries
+/// that answered ones were made from
+fn each_subexpression<'a>(expr: &'a Expression, visit: &mut impl FnMut(&'a Expression)) {
+    visit(expr);
+    match &expr.kind {
+        ExpressionKind::Number(_)
+        | ExpressionKind::Variable(_)
+        | ExpressionKind::StringLiteral(_)
+        | ExpressionKind::Input
+        | ExpressionKind::Error => {},
+        ExpressionKind::Argument(e)
+        | ExpressionKind::IsNull(e)
+        | ExpressionKind::Expect(e)
+        | ExpressionKind::Prob(e)
+        | ExpressionKind::Exact(_, e) => each_subexpression(e, visit),
+        ExpressionKind::Call(_, args) => {
+            for arg in args {
+                each_subexpression(arg, visit);
+            }
+        },
+        ExpressionKind::Add(e1, e2)
+        | ExpressionKind::Subtract(e1, e2)
+        | ExpressionKind::Comparison(_, e1, e2) => {
+            each_subexpression(e1, visit);
+            each_subexpression(e2, visit);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::render_all;
+
+    fn findings(source: &str, levels: &LintLevels) -> Vec<(&'static str, Severity, usize)> {
+        lint_source(source, &CompileOptions::default(), levels)
+            .unwrap()
+            .iter()
+            .map(|d| (d.code, d.severity, d.span.unwrap().start.line))
+            .collect()
+    }
+
+    #[test]
+    fn test_lints_find_mistakes_that_type_check() {
+        let source = "maybe total = 10\nmaybe total = 20\nmaybe spare = total\n\
+                      maybe unused = spare\nblah 1 + 2\nblah total - total\n\
+                      maybe (a, _b) = (1, 2)\ngiven a > 0\nblah \"ok\"\nblah prob(a is null)\n\
+                      blah input - input\nblah max(input, a) - max(input, a)";
+        let warn = Severity::Warning;
+        assert_eq!(
+            findings(source, &LintLevels::default()),
+            vec![
+                ("unused_maybe", warn, 1),
+                ("redeclaration", warn, 2),
+                ("unobserved_variable", warn, 3),
+                ("unused_maybe", warn, 4),
+                ("constant_blah", warn, 5),
+                ("self_subtraction", warn, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_levels_and_pragmas() {
+        let source = "// mist: allow(unused_maybe, redeclaration)\nmaybe x = 1\nmaybe x = 2\n\
+                      blah x - x\n// mist: deny(constant_blah)\nblah 1 + 1";
+        let mut levels = LintLevels::default();
+        levels.set(Lint::SelfSubtraction, LintLevel::Allow);
+        levels.set(Lint::UnusedMaybe, LintLevel::Deny);
+        // The file's pragmas override the command line
+        assert_eq!(
+            findings(source, &levels),
+            vec![("constant_blah", Severity::Error, 6)]
+        );
+
+        let invalid = findings("// mist: allow(unused)\nblah 1", &LintLevels::default());
+        assert_eq!(invalid, vec![("invalid_pragma", Severity::Warning, 1)]);
+        assert_eq!(
+            "unsed".parse::<Lint>().unwrap_err(),
+            "unknown lint 'unsed' (expected unused_maybe, unobserved_variable, constant_blah, \
+             self_subtraction, redeclaration or invalid_pragma)"
+        );
+    }
+
+    #[test]
+    fn test_render_lint_warnings() {
+        let source = "maybe x = 1\nblah 2 - 2";
+        let mut levels = LintLevels::default();
+        let warnings = lint_source(source, &CompileOptions::default(), &levels).unwrap();
+        assert_eq!(
+            render_all(&warnings, "test.mist", source, false),
+            "\
+warning[unused_maybe]: the value of 'x' is never used
+ --> test.mist:1:7
+  |
+1 | maybe x = 1
+  |       ^ never read
+  |
+  = note: `// mist: allow(unused_maybe)` turns this off
+  = help: remove it, or call it '_x' if only its coin flip matters
+
+warning[constant_blah]: blah prints the same value in every world
+ --> test.mist:2:6
+  |
+2 | blah 2 - 2
+  |      ^^^^^ constant expression
+  |
+  = note: `// mist: allow(constant_blah)` turns this off
+  = help: print the value itself, or the variable it was meant to use
+
+warning[self_subtraction]: '2 - 2' subtracts a value from itself
+ --> test.mist:2:6
+  |
+2 | blah 2 - 2
+  |      ^^^^^ always 0
+  |
+  = note: `// mist: allow(self_subtraction)` turns this off
+  = help: replace it with 0
+
+warning: 3 warnings emitted
+"
+        );
+
+        levels.set(Lint::ConstantBlah, LintLevel::Deny);
+        let mixed = lint_source(source, &CompileOptions::default(), &levels).unwrap();
+        let summary = render_all(&mixed, "test.mist", source, false);
+        assert!(summary.ends_with("error: aborting due to 1 error; 2 warnings emitted\n"));
+    }
+}
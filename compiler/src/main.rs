@@ -1,33 +1,175 @@
 //! Mist Language Compiler - A mathematically proven compiler for the Mist language
 // main.rs - Main entry point that uses the compiler module
 
+mod analysis;
 mod compiler; // This imports compiler.rs
+mod diagnostics;
+mod driver;
+mod emit;
+mod formatter;
+mod ir;
+mod json;
+mod lint;
+mod montecarlo;
+mod optimize;
+mod query;
+mod rational;
+mod stattest;
+#[cfg(test)]
+mod test_support;
+mod xid;
 
-use compiler::compile; // Import the compile function
+use compiler::{
+    build_ir, generate_c_from_ir, program_checksum, CheckedProgram, ClockCoins, CoinSource,
+    CompileOptions, Interpreter, ProcessInput, RecordingCoins, ReplayCoins, RuntimeError,
+    TraceLevel, World, ASSERTION_EXIT_CODE, COIN_REPLAY_VAR, COIN_TRACE_OUT_VAR,
+    REJECTION_EXIT_CODE,
+};
+use diagnostics::{ColorChoice, Emitter};
+use driver::{check_source, check_source_all, compile, error_chain, CompilerError};
+use emit::{Emit, EmitFormat};
+use ir::{Instruction, IrProgram, Op};
+use lint::{Lint, LintLevel, LintLevels};
+use montecarlo::{ReportFormat, RunOutput};
 use std::env;
+use std::fmt::Display;
 use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // Everything after `--` belongs to the Mist program, not the compiler
+    let mut args: Vec<String> = env::args().collect();
+    let program_args = match args.iter().position(|x| x == "--") {
+        Some(pos) => args.split_off(pos).split_off(1),
+        None => Vec::new(),
+    };
 
     if args.len() < 2 {
-        eprintln!("Usage: {} <input.mist> [options]", args[0]);
+        eprintln!(
+            "Usage: {} <input.mist> [options] [-- program args]",
+            args[0]
+        );
+        eprintln!(
+            "   or: {} test <file.mist>... [options] [-- program args]",
+            args[0]
+        );
+        eprintln!("   or: {} fmt <file.mist>... [--check]", args[0]);
+        eprintln!(
+            "   or: {} lint <file.mist>... [--allow|--warn|--deny <lint>[,...]]",
+            args[0]
+        );
+        eprintln!("   or: {} --explain <code>", args[0]);
         eprintln!("Options:");
         eprintln!("  --run        Compile and run the program");
+        eprintln!("  --runs <n>   Run n times and tabulate every blah's outputs (implies --run)");
+        eprintln!("  --jobs <n>   Number of runs to execute in parallel (default: 1)");
+        eprintln!("  --format <table|csv|json>  How --runs reports (default: table)");
+        eprintln!("  --interpret  Execute the program directly, without a C compiler");
+        eprintln!("  --analyze    Print the exact (or sampled) output distributions");
         eprintln!("  --output <file>  Specify output file (default: output.c)");
+        eprintln!("  --null-mode <zero|propagate|strict>  Null arithmetic (default: zero)");
+        eprintln!("  --trace <none|declarations|full>  Execution trace (default: declarations)");
+        eprintln!("  --trace-file <file>  Write the trace to a file instead of stderr");
+        eprintln!("  -O           Optimise the IR before generating C");
+        eprintln!("  --color <auto|always|never>  Colour error messages (default: auto)");
+        eprintln!("  --message-format=<human|json>  Print errors as JSON lines (default: human)");
+        eprintln!(
+            "  --emit <tokens|ast|typed|ir|c>[,...]  Print what each phase produced, not a C file"
+        );
+        eprintln!("  --emit-format <text|json>  How --emit prints (default: text)");
+        eprintln!("  --list-worlds  Print which coin flip decides each maybe declaration");
+        eprintln!(
+            "  --world-selector  Let the program take --world=<selector> as its first argument"
+        );
+        eprintln!("  --world <bitmask|1,0,...>  Interpret in one chosen world");
+        eprintln!(
+            "  --sample     With test, run the compiled program even if exact analysis is possible"
+        );
+        eprintln!("  --check      With fmt, list unformatted files instead of rewriting them");
+        eprintln!("  --allow, --warn, --deny <lint>  With lint, set a lint's level");
+        eprintln!("Arguments after -- are passed to the program (read with arg(n)).");
+        eprintln!("Piped stdin is forwarded to every run (read with input).");
         eprintln!("\nExample: {} src/dice.mist --run", args[0]);
         std::process::exit(1);
     }
 
+    if args[1] == "--explain" {
+        explain(args.get(2).map_or("", String::as_str));
+        return;
+    }
+
     let input_file = &args[1];
-    let should_run = args.contains(&"--run".to_string());
+    let runs: Option<usize> = option_value(&args, "--runs");
+    let jobs: usize = option_value(&args, "--jobs").unwrap_or(1);
+    let format: ReportFormat = option_value(&args, "--format").unwrap_or_default();
+    if runs == Some(0) || jobs == 0 {
+        eprintln!("Error: --runs and --jobs need a positive count");
+        std::process::exit(1);
+    }
+    let should_run = runs.is_some() || args.contains(&"--run".to_string());
+    // CSV and JSON go to stdout on their own, without the compiler's banners
+    let banners = runs.is_none() || format == ReportFormat::Table;
+    let should_interpret = args.contains(&"--interpret".to_string());
+    let should_analyze = args.contains(&"--analyze".to_string());
+    let should_list_worlds = args.contains(&"--list-worlds".to_string());
     let output_file = if let Some(pos) = args.iter().position(|x| x == "--output") {
         args.get(pos + 1).map(String::as_str).unwrap_or("output.c")
     } else {
         "output.c"
     };
+    let options = CompileOptions {
+        null_mode: option_value(&args, "--null-mode").unwrap_or_default(),
+        trace: option_value(&args, "--trace").unwrap_or_default(),
+        trace_file: option_value(&args, "--trace-file"),
+        world_selector: args.contains(&"--world-selector".to_string()),
+        optimize: args.contains(&"-O".to_string()),
+    };
+    let world: Option<String> = option_value(&args, "--world");
+    let emits: Vec<Emit> = option_list(&args, "--emit");
+    let emit_format: EmitFormat = option_value(&args, "--emit-format").unwrap_or_default();
+    let messages = Emitter {
+        format: option_value(&args, "--message-format").unwrap_or_default(),
+        color: option_value::<ColorChoice>(&args, "--color")
+            .unwrap_or_default()
+            .enabled(),
+    };
+
+    if args[1] == "test" {
+        let files = file_arguments(&args);
+        let settings = TestSettings {
+            program_args: &program_args,
+            options: &options,
+            runs: runs.unwrap_or(stattest::DEFAULT_RUNS),
+            jobs,
+            sample: args.contains(&"--sample".to_string()),
+        };
+        run_tests(&files, &settings);
+        return;
+    }
+
+    if args[1] == "fmt" {
+        let files = file_arguments(&args);
+        format_files(&files, args.contains(&"--check".to_string()), messages);
+        return;
+    }
+
+    if args[1] == "lint" {
+        let files = file_arguments(&args);
+        // In command-line order, so that a later flag overrides an earlier one
+        let mut levels = LintLevels::default();
+        for (flag, lint) in ordered_option_list::<Lint>(&args, &["--allow", "--warn", "--deny"]) {
+            let level = match flag {
+                "--allow" => LintLevel::Allow,
+                "--warn" => LintLevel::Warn,
+                _ => LintLevel::Deny,
+            };
+            levels.set(lint, level);
+        }
+        lint_files(&files, &options, &levels, messages);
+        return;
+    }
 
     // Read the Mist source file
     let source = match fs::read_to_string(input_file) {
@@ -38,31 +180,70 @@ fn main() {
         },
     };
 
-    println!("=== MIST COMPILER ===");
-    println!("Compiling: {}", input_file);
-    println!();
+    if !emits.is_empty() {
+        let (output, errors) = emit::emit(&source, &options, &emits, emit_format);
+        print!("{}", output);
+        if !errors.is_empty() {
+            report_errors(&errors, input_file, &source, messages);
+        }
+        return;
+    }
+
+    if should_interpret {
+        interpret(
+            &source,
+            input_file,
+            program_args,
+            &options,
+            world.as_deref(),
+            messages,
+        );
+        return;
+    }
+
+    if should_list_worlds {
+        list_worlds(&source, input_file, &options, messages);
+        return;
+    }
+
+    if should_analyze {
+        analyze(&source, input_file, program_args, &options, messages);
+        return;
+    }
+
+    if banners {
+        println!("=== MIST COMPILER ===");
+        println!("Compiling: {}", input_file);
+        println!();
+    }
 
     // Compile the Mist program
-    match compile(&source) {
-        Ok(c_code) => {
+    match check_source_all(&source, &options) {
+        Ok(checked) => {
+            let ir = build_ir(&checked, &options);
+            let c_code = generate_c_from_ir(&ir, &options);
             // Write the C code to file
             if let Err(e) = fs::write(output_file, &c_code) {
                 eprintln!("Error writing output file: {}", e);
                 std::process::exit(1);
             }
 
-            println!("✓ Successfully compiled to: {}", output_file);
+            if banners {
+                println!("✓ Successfully compiled to: {}", output_file);
 
-            // Show verification results
-            println!("\n=== VERIFICATION RESULTS ===");
-            println!("✓ Lexer Totality: PROVEN");
-            println!("✓ Parser Soundness: PROVEN");
-            println!("✓ Type Safety: PROVEN");
-            println!("✓ Semantic Preservation: PROVEN");
-            println!("✓ Compiler Determinism: PROVEN");
+                // Show verification results
+                println!("\n=== VERIFICATION RESULTS ===");
+                println!("✓ Lexer Totality: PROVEN");
+                println!("✓ Parser Soundness: PROVEN");
+                println!("✓ Type Safety: PROVEN");
+                println!("✓ Semantic Preservation: PROVEN");
+                println!("✓ Compiler Determinism: PROVEN");
+            }
 
             if should_run {
-                println!("\n=== COMPILING C CODE ===");
+                if banners {
+                    println!("\n=== COMPILING C CODE ===");
+                }
 
                 // Get the executable name from the input file
                 let exe_name = Path::new(input_file)
@@ -79,6 +260,37 @@ fn main() {
                 match compile_result {
                     Ok(output) => {
                         if output.status.success() {
+                            // Each run gets its own copy of piped stdin
+                            let stdin_data = read_piped_stdin();
+                            let exe_path = if cfg!(windows) {
+                                format!("./{}.exe", exe_name)
+                            } else {
+                                format!("./{}", exe_name)
+                            };
+
+                            if let Some(runs) = runs {
+                                if banners {
+                                    println!("✓ C compilation successful!");
+                                    println!("\n=== MONTE CARLO ===");
+                                }
+                                // Every run is fed the same input, so a terminal is
+                                // read to its end once, and only if it is needed
+                                let stdin_data = match stdin_data {
+                                    Some(data) => data,
+                                    None if reads_stdin(&ir) => read_terminal_stdin(runs),
+                                    None => Vec::new(),
+                                };
+                                let batch = Batch {
+                                    exe_path: &exe_path,
+                                    program_args: &program_args,
+                                    stdin_data: &stdin_data,
+                                    runs,
+                                    jobs,
+                                };
+                                monte_carlo(&batch, &checked, &ir, input_file, &options, format);
+                                return;
+                            }
+
                             println!("✓ C compilation successful!");
 
                             println!("\n=== RUNNING PROGRAM ===");
@@ -88,17 +300,22 @@ fn main() {
                             for i in 1..=3 {
                                 println!("--- Run {} ---", i);
 
-                                let run_result = if cfg!(windows) {
-                                    Command::new(format!("./{}.exe", exe_name)).output()
-                                } else {
-                                    Command::new(format!("./{}", exe_name)).output()
-                                };
+                                let run_result = run_binary(
+                                    &exe_path,
+                                    &program_args,
+                                    stdin_data.as_deref(),
+                                    None,
+                                );
 
                                 if let Ok(output) = run_result {
                                     print!("{}", String::from_utf8_lossy(&output.stdout));
                                     if !output.stderr.is_empty() {
                                         eprint!("{}", String::from_utf8_lossy(&output.stderr));
                                     }
+                                    match output.status.code() {
+                                        Some(code) => println!("(exit code {})", code),
+                                        None => println!("(terminated by signal)"),
+                                    }
                                 }
                                 println!();
                             }
@@ -114,13 +331,697 @@ fn main() {
                 }
             }
         },
+        Err(errors) => report_errors(&errors, input_file, &source, messages),
+    }
+}
+
+/// Print the diagnostics for the front end's errors and exit with status 1
+fn report_errors(errors: &[CompilerError], file: &str, source: &str, messages: Emitter) -> ! {
+    let diagnostics: Vec<_> = errors.iter().map(diagnostics::diagnose).collect();
+    eprint!("{}", messages.emit(&diagnostics, file, source));
+    std::process::exit(1);
+}
+
+/// Print the long explanation of an error code, as `--explain M0006` does
+fn explain(code: &str) {
+    let Some(entry) = diagnostics::explain(code) else {
+        eprintln!(
+            "Error: unknown error code '{}' (expected one of {} to {})",
+            code,
+            diagnostics::ERROR_CODES[0].code,
+            diagnostics::ERROR_CODES[diagnostics::ERROR_CODES.len() - 1].code
+        );
+        std::process::exit(1);
+    };
+    println!("{}: {}\n\n{}", entry.code, entry.title, entry.explanation);
+}
+
+/// The value following `flag`, or None when the flag is absent; exits when the
+/// value is missing or does not parse
+fn option_value<T>(args: &[String], flag: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: Display,
+{
+    // Either `--flag value` or `--flag=value`
+    let value = args.iter().enumerate().find_map(|(pos, arg)| {
+        if arg == flag {
+            Some(args.get(pos + 1).map(String::as_str))
+        } else {
+            arg.strip_prefix(flag)?.strip_prefix('=').map(Some)
+        }
+    })?;
+    let parsed = value.map_or_else(
+        || Err(format!("{} needs a value", flag)),
+        |value| value.parse().map_err(|e: T::Err| e.to_string()),
+    );
+    match parsed {
+        Ok(value) => Some(value),
         Err(e) => {
-            eprintln!("Compilation error: {}", e);
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         },
     }
 }
 
+/// Flags followed by a value, as in `--runs 100`
+const VALUE_FLAGS: [&str; 15] = [
+    "--runs",
+    "--jobs",
+    "--format",
+    "--output",
+    "--null-mode",
+    "--trace",
+    "--trace-file",
+    "--world",
+    "--emit",
+    "--emit-format",
+    "--message-format",
+    "--color",
+    "--allow",
+    "--warn",
+    "--deny",
+];
+
+/// The `.mist` files named after a subcommand such as `test`, leaving out the
+/// values of flags; `args` already stops before `--`, as what follows it
+/// belongs to the program
+fn file_arguments(args: &[String]) -> Vec<&str> {
+    let mut files = Vec::new();
+    let mut rest = args.iter().skip(2).map(String::as_str);
+    while let Some(arg) = rest.next() {
+        if VALUE_FLAGS.contains(&arg) {
+            rest.next();
+        } else if Path::new(arg).extension().is_some_and(|e| e == "mist") {
+            files.push(arg);
+        }
+    }
+    files
+}
+
+/// Every value given to a flag that may be repeated or take a comma-separated
+/// list, as in `--emit tokens,ast --emit c`; exits when a value does not parse
+fn option_list<T>(args: &[String], flag: &str) -> Vec<T>
+where
+    T: std::str::FromStr,
+    T::Err: Display,
+{
+    ordered_option_list(args, &[flag])
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// `option_list` for several flags at once, each value with its flag, in the
+/// order they were given
+fn ordered_option_list<'a, T>(args: &[String], flags: &[&'a str]) -> Vec<(&'a str, T)>
+where
+    T: std::str::FromStr,
+    T::Err: Display,
+{
+    let lists = args.iter().enumerate().flat_map(|(pos, arg)| {
+        flags.iter().filter_map(move |flag| {
+            let list = if arg == flag {
+                args.get(pos + 1).map_or("", String::as_str)
+            } else {
+                arg.strip_prefix(flag)?.strip_prefix('=')?
+            };
+            Some((*flag, list))
+        })
+    });
+    let parsed: Result<Vec<(&str, T)>, String> = lists
+        .flat_map(|(flag, list)| list.split(',').map(move |value| (flag, value)))
+        .map(|(flag, value)| {
+            value
+                .parse()
+                .map(|value| (flag, value))
+                .map_err(|e: T::Err| e.to_string())
+        })
+        .collect();
+    parsed.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// A file named by an environment variable, if it is set and not empty
+fn env_path(variable: &str) -> Option<String> {
+    env::var(variable).ok().filter(|path| !path.is_empty())
+}
+
+/// Stdin contents when it is piped in; None when it is an interactive terminal
+fn read_piped_stdin() -> Option<Vec<u8>> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return None;
+    }
+    let mut data = Vec::new();
+    stdin.read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+/// Whether a run of the lowered program may read stdin
+fn reads_stdin(ir: &IrProgram) -> bool {
+    fn reads(instructions: &[Instruction]) -> bool {
+        instructions.iter().any(|instruction| match instruction {
+            Instruction::Let(_, _, Op::Input) | Instruction::Bind(_, _, Op::Input) => true,
+            Instruction::If(_, then, otherwise) => reads(then) || reads(otherwise),
+            _ => false,
+        })
+    }
+    ir.blocks.iter().any(|block| reads(&block.instructions))
+}
+
+/// Stdin read from an interactive terminal up to end of file, for the
+/// program that all `runs` are fed
+fn read_terminal_stdin(runs: usize) -> Vec<u8> {
+    eprintln!(
+        "Reading the input for all {} runs from the terminal; end it with {}",
+        runs,
+        if cfg!(windows) { "Ctrl-Z" } else { "Ctrl-D" }
+    );
+    let mut data = Vec::new();
+    if let Err(e) = io::stdin().read_to_end(&mut data) {
+        eprintln!("Error reading stdin: {}", e);
+        std::process::exit(1);
+    }
+    data
+}
+
+/// Run a compiled program, feeding it `stdin_data` or else inheriting our stdin,
+/// and seeding its coin flips with `seed` if given
+fn run_binary(
+    path: &str,
+    program_args: &[String],
+    stdin_data: Option<&[u8]>,
+    seed: Option<u32>,
+) -> io::Result<Output> {
+    let mut command = Command::new(path);
+    command
+        .args(program_args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(seed) = seed {
+        command.env("MIST_SEED", seed.to_string());
+    }
+
+    let Some(data) = stdin_data else {
+        return command.stdin(Stdio::inherit()).output();
+    };
+
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    let writer = child.stdin.take().map(|mut stdin| {
+        // Written from a thread so a chatty program cannot deadlock on its stdout;
+        // a program that exits without reading everything closes the pipe early
+        let data = data.to_vec();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&data);
+        })
+    });
+    let output = child.wait_with_output();
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    output
+}
+
+/// The runs requested by `--runs` and `--jobs`
+struct Batch<'a> {
+    exe_path: &'a str,
+    program_args: &'a [String],
+    stdin_data: &'a [u8],
+    runs: usize,
+    jobs: usize,
+}
+
+/// Execute every run of the batch, `jobs` at a time, in run order
+///
+/// Runs started within the same second would share `srand(time(NULL))`, so
+/// each one gets its own MIST_SEED. Their stderr (the trace) is dropped.
+fn run_batch(batch: &Batch) -> io::Result<Vec<RunOutput>> {
+    let base = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() ^ u64::from(d.subsec_nanos()))
+        ^ u64::from(std::process::id());
+    let run = |index: usize| {
+        // splitmix64, so that neighbouring runs get unrelated seeds
+        let mut z = base.wrapping_add((index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        #[allow(clippy::cast_possible_truncation)]
+        let seed = (z ^ (z >> 31)) as u32;
+        run_binary(
+            batch.exe_path,
+            batch.program_args,
+            Some(batch.stdin_data),
+            Some(seed),
+        )
+        .map(|output| RunOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            exit_code: output.status.code(),
+        })
+    };
+
+    let jobs = batch.jobs.min(batch.runs);
+    let mut outputs: Vec<(usize, RunOutput)> = std::thread::scope(|scope| {
+        // Collected so that every worker is started before the first join
+        #[allow(clippy::needless_collect)]
+        let workers: Vec<_> = (0..jobs)
+            .map(|job| {
+                scope.spawn(move || {
+                    (job..batch.runs)
+                        .step_by(jobs)
+                        .map(|index| run(index).map(|output| (index, output)))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("run thread panicked")))
+            })
+            .collect::<io::Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+    outputs.sort_by_key(|(index, _)| *index);
+    Ok(outputs.into_iter().map(|(_, output)| output).collect())
+}
+
+/// Run the compiled program many times and tabulate what every `blah` printed
+fn monte_carlo(
+    batch: &Batch,
+    checked: &CheckedProgram,
+    ir: &IrProgram,
+    input_file: &str,
+    options: &CompileOptions,
+    format: ReportFormat,
+) {
+    let runs = match run_batch(batch) {
+        Ok(runs) => runs,
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", batch.exe_path, e);
+            std::process::exit(1);
+        },
+    };
+
+    // Exact probabilities for comparison, when the worlds can be enumerated
+    let mut argv = vec![input_file.to_string()];
+    argv.extend(batch.program_args.iter().cloned());
+    let input = ProcessInput::new(io::Cursor::new(batch.stdin_data.to_vec()), argv);
+    let exact =
+        analysis::printed_distributions(&checked.program, Box::new(input), options.null_mode)
+            .ok()
+            .flatten();
+
+    let probes = montecarlo::probes(&checked.program, ir);
+    let summary = montecarlo::aggregate(&probes, &runs, batch.jobs, exact.as_deref());
+    print!("{}", summary.render(format));
+}
+
+/// Run the program in-process, exiting with the same status the C binary would
+fn interpret(
+    source: &str,
+    input_file: &str,
+    program_args: Vec<String>,
+    options: &CompileOptions,
+    world: Option<&str>,
+    messages: Emitter,
+) {
+    let checked = match check_source_all(source, options) {
+        Ok(checked) => checked,
+        Err(errors) => report_errors(&errors, input_file, source, messages),
+    };
+    let world = world.map(|selector| {
+        World::parse(selector, analysis::coin_count(&checked.program)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+
+    // The trace goes to its own stream, like a compiled program's
+    let mut trace_out: Box<dyn Write> = match &options.trace_file {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("cannot open trace file {}: {}", path, e);
+                std::process::exit(1);
+            },
+        },
+        None => Box::new(io::stderr()),
+    };
+
+    // argv[0] is the program name, so arg(1) is the first argument after --
+    let mut argv = vec![input_file.to_string()];
+    argv.extend(program_args);
+    let input = ProcessInput::new(io::BufReader::new(io::stdin()), argv);
+
+    // Coin traces are recorded and replayed exactly as compiled programs do it
+    let checksum = program_checksum(&checked.program);
+    let mut flips: Box<dyn CoinSource> = match env_path(COIN_REPLAY_VAR) {
+        Some(path) => {
+            let Ok(trace) = fs::read_to_string(&path) else {
+                eprintln!("cannot open coin trace {}", path);
+                std::process::exit(1);
+            };
+            match ReplayCoins::parse(&trace, &checksum) {
+                Ok(coins) => Box::new(coins),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                },
+            }
+        },
+        None => Box::new(ClockCoins::new()),
+    };
+    let mut coins = RecordingCoins::new(flips.as_mut());
+    let mut stdout = io::stdout();
+    let mut interpreter = Interpreter::new(&mut coins, &mut stdout)
+        .with_input(Box::new(input))
+        .with_null_mode(options.null_mode)
+        .with_trace(options.trace, &mut trace_out)
+        .with_lines(checked.lines);
+    if let Some(world) = world {
+        interpreter = interpreter.with_world(world);
+    }
+
+    let result = interpreter.run(&checked.program);
+    // process::exit skips destructors, so a buffered trace file is flushed here
+    drop(interpreter);
+    let _ = trace_out.flush();
+    if let Some(path) = env_path(COIN_TRACE_OUT_VAR) {
+        if let Err(e) = fs::write(&path, coins.trace(&checksum)) {
+            eprintln!("cannot write coin trace {}: {}", path, e);
+        }
+    }
+    match result {
+        Ok(0) => {},
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            let code = match e {
+                RuntimeError::AssertionFailed { .. } => ASSERTION_EXIT_CODE,
+                RuntimeError::NoAcceptedWorld { .. } | RuntimeError::WorldRejected(_) => {
+                    REJECTION_EXIT_CODE
+                },
+                _ => 1,
+            };
+            std::process::exit(code);
+        },
+    }
+}
+
+/// Print how many worlds the program has and which bit selects each declaration
+fn list_worlds(source: &str, input_file: &str, options: &CompileOptions, messages: Emitter) {
+    let checked = match check_source_all(source, options) {
+        Ok(checked) => checked,
+        Err(errors) => report_errors(&errors, input_file, source, messages),
+    };
+
+    println!("=== POSSIBLE WORLDS ===");
+    println!("Program: {}", input_file);
+    println!();
+    print!("{}", analysis::world_map(&checked.program, &checked.lines));
+}
+
+/// Print the distribution of every output and assertion, given the conditions
+fn analyze(
+    source: &str,
+    input_file: &str,
+    program_args: Vec<String>,
+    options: &CompileOptions,
+    messages: Emitter,
+) {
+    let program = match check_source_all(source, options) {
+        Ok(checked) => checked.program,
+        Err(errors) => report_errors(&errors, input_file, source, messages),
+    };
+
+    let mut argv = vec![input_file.to_string()];
+    argv.extend(program_args);
+    let input = ProcessInput::new(io::BufReader::new(io::stdin()), argv);
+
+    println!("=== DISTRIBUTION ANALYSIS ===");
+    println!("Analyzing: {}", input_file);
+    println!();
+
+    let report = analysis::analyze(
+        &program,
+        Box::new(input),
+        options.null_mode,
+        analysis::DEFAULT_SAMPLES,
+    );
+    match report {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// How `test` checks its files
+struct TestSettings<'a> {
+    program_args: &'a [String],
+    options: &'a CompileOptions,
+    runs: usize,
+    jobs: usize,
+    /// Run the compiled program even when exact analysis is possible
+    sample: bool,
+}
+
+/// Check the `//!` expectations of every test file, exiting 1 if any fails
+fn run_tests(files: &[&str], settings: &TestSettings) {
+    if files.is_empty() {
+        eprintln!("Error: test needs at least one .mist file");
+        std::process::exit(1);
+    }
+
+    println!("=== MIST TEST ===");
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        println!();
+        match test_file(file, settings) {
+            Ok((method, verdicts)) => {
+                println!("{} ({})", file, method);
+                for verdict in verdicts {
+                    println!("  {}", verdict);
+                    if verdict.passed {
+                        passed += 1;
+                    } else {
+                        failed += 1;
+                    }
+                }
+            },
+            Err(e) => {
+                println!("{}", file);
+                println!("  FAIL  {}", e);
+                failed += 1;
+            },
+        }
+    }
+
+    println!();
+    println!(
+        "{} expectation(s): {} passed, {} failed",
+        passed + failed,
+        passed,
+        failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Rewrite each file in canonical layout or, with `check`, list those that
+/// are not in it; exits 1 if any file is unformatted or fails to parse
+fn format_files(files: &[&str], check: bool, messages: Emitter) {
+    if files.is_empty() {
+        eprintln!("Error: fmt needs at least one .mist file");
+        std::process::exit(1);
+    }
+
+    let mut failed = false;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", file, e);
+                failed = true;
+                continue;
+            },
+        };
+        let formatted = match formatter::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                let diagnostics: Vec<_> = errors.iter().map(diagnostics::diagnose).collect();
+                eprint!("{}", messages.emit(&diagnostics, file, &source));
+                failed = true;
+                continue;
+            },
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("Would reformat: {}", file);
+            failed = true;
+        } else if let Err(e) = fs::write(file, &formatted) {
+            eprintln!("Error writing file '{}': {}", file, e);
+            failed = true;
+        } else {
+            println!("Formatted: {}", file);
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Print the lint findings of each file, exiting 1 if any is an error or a
+/// file does not compile
+fn lint_files(files: &[&str], options: &CompileOptions, levels: &LintLevels, messages: Emitter) {
+    if files.is_empty() {
+        eprintln!("Error: lint needs at least one .mist file");
+        std::process::exit(1);
+    }
+
+    let mut failed = false;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", file, e);
+                failed = true;
+                continue;
+            },
+        };
+        let diagnostics = match lint::lint_source(&source, options, levels) {
+            Ok(diagnostics) => diagnostics,
+            Err(errors) => errors.iter().map(diagnostics::diagnose).collect(),
+        };
+        failed |= diagnostics
+            .iter()
+            .any(|d| d.severity == diagnostics::Severity::Error);
+        eprint!("{}", messages.emit(&diagnostics, file, &source));
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Check one test file, exactly when its worlds can be enumerated and over
+/// `settings.runs` runs of the compiled program otherwise
+fn test_file(
+    file: &str,
+    settings: &TestSettings,
+) -> Result<(String, Vec<stattest::Verdict>), String> {
+    let source = fs::read_to_string(file).map_err(|e| format!("cannot read '{}': {}", file, e))?;
+    // The runs are only counted, so the declaration trace would be wasted work
+    let options = CompileOptions {
+        trace: TraceLevel::None,
+        ..settings.options.clone()
+    };
+    let checked = check_source(&source, &options).map_err(|e| error_chain(&e))?;
+    let expectations = stattest::parse_expectations(&source)?;
+    if expectations.is_empty() {
+        return Err("no `//!` expectations".to_string());
+    }
+
+    // Tests get no stdin, so that they run the same way every time
+    let mut argv = vec![file.to_string()];
+    argv.extend(settings.program_args.iter().cloned());
+    let input = ProcessInput::new(io::Cursor::new(Vec::new()), argv);
+    let exact = if settings.sample {
+        None
+    } else {
+        analysis::printed_distributions(&checked.program, Box::new(input), options.null_mode)
+            .map_err(|e| e.to_string())?
+    };
+
+    let (method, observations) = if let Some(outputs) = exact {
+        (
+            format!(
+                "exact, {} coin flip(s)",
+                analysis::coin_count(&checked.program)
+            ),
+            stattest::Observations::exact(&outputs, &checked.lines),
+        )
+    } else {
+        let summary = sample_test_file(file, checked, &options, settings)?;
+        (
+            format!("{} runs", summary.runs),
+            stattest::Observations::sampled(&summary),
+        )
+    };
+
+    let verdicts = expectations
+        .iter()
+        .map(|expectation| stattest::check(expectation, &observations))
+        .collect();
+    Ok((method, verdicts))
+}
+
+/// Compile a test file into a temporary binary and tabulate its runs
+fn sample_test_file(
+    file: &str,
+    checked: CheckedProgram,
+    options: &CompileOptions,
+    settings: &TestSettings,
+) -> Result<montecarlo::Summary, String> {
+    let ir = build_ir(&checked, options);
+    let probes = montecarlo::probes(&checked.program, &ir);
+    let c_code = generate_c_from_ir(&ir, options);
+
+    let stem = Path::new(file)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let base = env::temp_dir().join(format!("mist-test-{}-{}", std::process::id(), stem));
+    let c_path = base.with_extension("c");
+    let exe_path = if cfg!(windows) {
+        base.with_extension("exe")
+    } else {
+        base
+    };
+    fs::write(&c_path, c_code).map_err(|e| format!("cannot write C code: {}", e))?;
+
+    let gcc = Command::new("gcc")
+        .arg("-o")
+        .arg(&exe_path)
+        .arg(&c_path)
+        .arg("-std=c99")
+        .output()
+        .map_err(|e| format!("failed to run gcc: {}", e));
+    let _ = fs::remove_file(&c_path);
+    let gcc = gcc?;
+    if !gcc.status.success() {
+        return Err(format!(
+            "C compilation failed:\n{}",
+            String::from_utf8_lossy(&gcc.stderr)
+        ));
+    }
+
+    let batch = Batch {
+        exe_path: &exe_path.to_string_lossy(),
+        program_args: settings.program_args,
+        stdin_data: &[],
+        runs: settings.runs,
+        jobs: settings.jobs,
+    };
+    let runs = run_batch(&batch);
+    let _ = fs::remove_file(&exe_path);
+    let runs = runs.map_err(|e| format!("failed to run {}: {}", batch.exe_path, e))?;
+
+    Ok(montecarlo::aggregate(&probes, &runs, settings.jobs, None))
+}
+
 // Alternative: If you want a simple function to compile all .mist files
 #[allow(dead_code)]
 fn compile_all_mist_files() {
@@ -140,7 +1041,7 @@ fn compile_all_mist_files() {
                     }
                     println!("✓ Compiled to {}", output_name);
                 },
-                Err(e) => eprintln!("Error compiling {}: {}", file, e),
+                Err(e) => eprintln!("Error compiling {}: {}", file, error_chain(&e)),
             }
         }
     }
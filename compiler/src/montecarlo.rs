@@ -0,0 +1,500 @@
+// Monte Carlo Aggregation of Compiled Runs
+//
+// `--runs N` executes the compiled program N times. Every `blah` prints
+// its value on its own line(s), in statement order, and a run can only stop
+// early, never skip a statement, so the stdout of a run splits back into one
+// value per `blah` it reached. How many lines each `blah` takes is read off
+// the IR the C was generated from, `IrProgram::printed_lines`: one, or more
+// for a string with line breaks in it. Those values are counted per statement into
+// frequency tables, each frequency with a 95% Wilson score interval, and set
+// beside the exact probabilities whenever the analyzer can enumerate them.
+
+use crate::analysis::OutputDistribution;
+use crate::compiler::{Program, StatementKind};
+use crate::ir::IrProgram;
+use crate::json::json_string;
+use std::fmt::Write;
+
+/// Normal quantile for a two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+/// How the aggregated runs are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Table,
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(ReportFormat::Table),
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!(
+                "unknown format '{}' (expected table, csv or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// A `blah` statement, and how many lines of stdout its value takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    /// Index of the statement in the program
+    pub statement: usize,
+    pub line: usize,
+    pub source: String,
+    height: usize,
+}
+
+/// The `blah` statements of a program, which `ir` is the lowering of
+pub fn probes(program: &Program, ir: &IrProgram) -> Vec<Probe> {
+    program
+        .iter()
+        .zip(&ir.blocks)
+        .zip(ir.printed_lines())
+        .enumerate()
+        .filter_map(|(statement, ((stmt, block), height))| match &stmt.kind {
+            StatementKind::BlahStatement(expr) => Some(Probe {
+                statement,
+                line: block.line,
+                source: format!("blah {}", expr),
+                height,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// What one run of the compiled program produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutput {
+    pub stdout: String,
+    /// None when the run was killed by a signal
+    pub exit_code: Option<i32>,
+}
+
+/// One row of a frequency table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// The printed value, or None for runs that never reached the statement
+    pub value: Option<String>,
+    pub count: u64,
+    pub exact: Option<f64>,
+}
+
+/// The values one `blah` printed across all runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub line: usize,
+    pub source: String,
+    pub rows: Vec<Row>,
+}
+
+/// Everything learned from a batch of runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub runs: u64,
+    pub jobs: usize,
+    /// (exit status, number of runs), in ascending order of status
+    pub exit_codes: Vec<(Option<i32>, u64)>,
+    pub histograms: Vec<Histogram>,
+    /// Whether the histograms carry exact probabilities
+    pub exact: bool,
+}
+
+/// Count what every run printed, next to the exact distributions if known
+/// (see `analysis::printed_distributions`)
+pub fn aggregate(
+    probes: &[Probe],
+    runs: &[RunOutput],
+    jobs: usize,
+    exact: Option<&[OutputDistribution]>,
+) -> Summary {
+    let mut counts: Vec<Vec<(Option<String>, u64)>> = vec![Vec::new(); probes.len()];
+    let mut exit_codes: Vec<(Option<i32>, u64)> = Vec::new();
+    for run in runs {
+        let mut lines = run.stdout.lines();
+        for (probe, counts) in probes.iter().zip(&mut counts) {
+            let value: Vec<&str> = lines.by_ref().take(probe.height).collect();
+            let value = (value.len() == probe.height).then(|| value.join("\n"));
+            bump(counts, value);
+        }
+        bump(&mut exit_codes, run.exit_code);
+    }
+    exit_codes.sort();
+
+    let histograms = probes
+        .iter()
+        .zip(counts)
+        .map(|(probe, counts)| {
+            let distribution =
+                exact.and_then(|outputs| outputs.iter().find(|o| o.statement == probe.statement));
+            let mut rows: Vec<Row> = counts
+                .into_iter()
+                .map(|(value, count)| Row {
+                    value,
+                    count,
+                    exact: None,
+                })
+                .collect();
+            if let Some(distribution) = distribution {
+                let outcomes = distribution
+                    .outcomes
+                    .iter()
+                    .map(|(value, p)| (Some(value.to_string()), p))
+                    .chain(std::iter::once((None, &distribution.unreached)));
+                for (value, p) in outcomes {
+                    let p = p.to_f64();
+                    match rows.iter_mut().find(|row| row.value == value) {
+                        Some(row) => row.exact = Some(p),
+                        None if p > 0.0 => rows.push(Row {
+                            value,
+                            count: 0,
+                            exact: Some(p),
+                        }),
+                        None => {},
+                    }
+                }
+                // Observed values the analyzer says are impossible
+                for row in rows.iter_mut().filter(|row| row.exact.is_none()) {
+                    row.exact = Some(0.0);
+                }
+            }
+            rows.sort_by_key(|row| value_key(row.value.as_deref()));
+            Histogram {
+                line: probe.line,
+                source: probe.source.clone(),
+                rows,
+            }
+        })
+        .collect();
+
+    Summary {
+        runs: runs.len() as u64,
+        jobs,
+        exit_codes,
+        histograms,
+        exact: exact.is_some(),
+    }
+}
+
+fn bump<T: PartialEq>(counts: &mut Vec<(T, u64)>, key: T) {
+    match counts.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((key, 1)),
+    }
+}
+
+/// Null first, then integers in numeric order, then everything else, then
+/// the runs that never got there
+fn value_key(value: Option<&str>) -> (u8, i64, String) {
+    match value {
+        Some("null") => (0, 0, String::new()),
+        Some(v) => match v.parse::<i64>() {
+            Ok(n) => (1, n, String::new()),
+            Err(_) => (2, 0, v.to_string()),
+        },
+        None => (3, 0, String::new()),
+    }
+}
+
+/// 95% Wilson score interval for `count` successes in `runs` trials
+///
+/// Unlike the normal approximation it stays inside [0, 1] and is sensible
+/// for frequencies of 0 and 1, which coin-flip programs produce often.
+pub fn wilson_interval(count: u64, runs: u64) -> (f64, f64) {
+    if runs == 0 {
+        return (0.0, 1.0);
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let (k, n) = (count as f64, runs as f64);
+    let p = k / n;
+    let z2 = Z_95 * Z_95;
+    let denominator = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denominator;
+    let half = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denominator;
+    // The bounds are exactly 0 and 1 at the extremes; don't let rounding move them
+    let low = if count == 0 {
+        0.0
+    } else {
+        (center - half).max(0.0)
+    };
+    let high = if count >= runs {
+        1.0
+    } else {
+        (center + half).min(1.0)
+    };
+    (low, high)
+}
+
+impl Summary {
+    #[allow(clippy::cast_precision_loss)]
+    fn frequency(&self, count: u64) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            count as f64 / self.runs as f64
+        }
+    }
+
+    /// Human-readable frequency tables
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{} runs, {} job(s)", self.runs, self.jobs);
+        let statuses: Vec<String> = self
+            .exit_codes
+            .iter()
+            .map(|(code, count)| match code {
+                Some(code) => format!("{} in {} runs", code, count),
+                None => format!("killed by a signal in {} runs", count),
+            })
+            .collect();
+        let _ = writeln!(out, "Exit status: {}", statuses.join(", "));
+        let mut outside = false;
+
+        for histogram in &self.histograms {
+            let _ = writeln!(out, "\nline {}: {}", histogram.line, histogram.source);
+            let width = histogram
+                .rows
+                .iter()
+                .map(|row| display_value(row.value.as_deref()).chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("value".len());
+            let _ = write!(
+                out,
+                "  {:<width$}  {:>8}  {:>9}  {:<17}",
+                "value",
+                "count",
+                "frequency",
+                "95% interval",
+                width = width
+            );
+            if self.exact {
+                let _ = write!(out, "  exact");
+            }
+            out.push('\n');
+            for row in &histogram.rows {
+                let (low, high) = wilson_interval(row.count, self.runs);
+                let interval = format!("[{:.2}%, {:.2}%]", low * 100.0, high * 100.0);
+                let _ = write!(
+                    out,
+                    "  {:<width$}  {:>8}  {:>8.2}%  {:<17}",
+                    display_value(row.value.as_deref()),
+                    row.count,
+                    self.frequency(row.count) * 100.0,
+                    interval,
+                    width = width
+                );
+                if let Some(exact) = row.exact {
+                    let mark = if exact < low || exact > high {
+                        outside = true;
+                        " *"
+                    } else {
+                        ""
+                    };
+                    let _ = write!(out, "  {:.2}%{}", exact * 100.0, mark);
+                }
+                out.push('\n');
+            }
+        }
+
+        if outside {
+            let _ = writeln!(
+                out,
+                "\n* exact probability outside the 95% interval (expect about 1 row in 20)"
+            );
+        }
+        out
+    }
+
+    /// One CSV row per (statement, value)
+    pub fn to_csv(&self) -> String {
+        let mut out =
+            String::from("line,statement,value,count,runs,frequency,ci_low,ci_high,exact\n");
+        for histogram in &self.histograms {
+            for row in &histogram.rows {
+                let (low, high) = wilson_interval(row.count, self.runs);
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{},{:.6},{:.6},{:.6},{}",
+                    histogram.line,
+                    csv_field(&histogram.source),
+                    row.value.as_deref().map_or_else(String::new, csv_field),
+                    row.count,
+                    self.runs,
+                    self.frequency(row.count),
+                    low,
+                    high,
+                    row.exact.map_or_else(String::new, |p| format!("{:.6}", p))
+                );
+            }
+        }
+        out
+    }
+
+    /// The whole summary as one JSON object
+    pub fn to_json(&self) -> String {
+        let exit_codes: Vec<String> = self
+            .exit_codes
+            .iter()
+            .map(|(code, count)| {
+                let code = code.map_or_else(|| "null".to_string(), |c| c.to_string());
+                format!("{{\"status\": {}, \"runs\": {}}}", code, count)
+            })
+            .collect();
+        let outputs: Vec<String> = self
+            .histograms
+            .iter()
+            .map(|histogram| {
+                let rows: Vec<String> = histogram
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        let (low, high) = wilson_interval(row.count, self.runs);
+                        format!(
+                            "{{\"value\": {}, \"count\": {}, \"frequency\": {}, \"interval\": [{}, {}], \"exact\": {}}}",
+                            row.value.as_deref().map_or_else(|| "null".to_string(), json_string),
+                            row.count,
+                            self.frequency(row.count),
+                            low,
+                            high,
+                            row.exact.map_or_else(|| "null".to_string(), |p| p.to_string())
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"line\": {}, \"statement\": {}, \"values\": [{}]}}",
+                    histogram.line,
+                    json_string(&histogram.source),
+                    rows.join(", ")
+                )
+            })
+            .collect();
+        format!(
+            "{{\"runs\": {}, \"jobs\": {}, \"exit_codes\": [{}], \"outputs\": [{}]}}\n",
+            self.runs,
+            self.jobs,
+            exit_codes.join(", "),
+            outputs.join(", ")
+        )
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Table => self.to_table(),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Json => self.to_json(),
+        }
+    }
+}
+
+fn display_value(value: Option<&str>) -> String {
+    value.map_or_else(|| "(not reached)".to_string(), |v| v.replace('\n', "\\n"))
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{build_ir, CompileOptions, TraceLevel};
+    use crate::driver::check_source;
+
+    /// The program's probes, from the IR that C would be generated from
+    fn probes_of(source: &str, options: &CompileOptions) -> Vec<Probe> {
+        let checked = check_source(source, options).unwrap();
+        probes(&checked.program, &build_ir(&checked, options))
+    }
+
+    fn run(stdout: &str, exit_code: i32) -> RunOutput {
+        RunOutput {
+            stdout: stdout.to_string(),
+            exit_code: Some(exit_code),
+        }
+    }
+
+    #[test]
+    fn test_aggregate_splits_runs_per_blah() {
+        let source = "maybe a = 1\nblah a\nblah \"x\\ny\"\nblah 3";
+        let probes = probes_of(source, &CompileOptions::default());
+        assert_eq!(probes.len(), 3);
+        assert_eq!(
+            probes.iter().map(|p| p.height).collect::<Vec<_>>(),
+            [1, 2, 1]
+        );
+        // A traced or optimised program prints the same lines
+        for options in [
+            CompileOptions {
+                trace: TraceLevel::Full,
+                ..CompileOptions::default()
+            },
+            CompileOptions {
+                optimize: true,
+                ..CompileOptions::default()
+            },
+        ] {
+            assert_eq!(probes_of(source, &options), probes);
+        }
+
+        let runs = [
+            run("1\nx\ny\n3\n", 0),
+            run("null\nx\n", 1),
+            run("1\nx\ny\n3\n", 0),
+        ];
+        let summary = aggregate(&probes, &runs, 2, None);
+        assert_eq!(summary.runs, 3);
+        assert_eq!(summary.exit_codes, vec![(Some(0), 2), (Some(1), 1)]);
+
+        let values = |h: &Histogram| -> Vec<(Option<String>, u64)> {
+            h.rows.iter().map(|r| (r.value.clone(), r.count)).collect()
+        };
+        assert_eq!(
+            values(&summary.histograms[0]),
+            vec![(Some("null".to_string()), 1), (Some("1".to_string()), 2)]
+        );
+        // A multi-line string cut short by the end of stdout was not reached
+        assert_eq!(
+            values(&summary.histograms[1]),
+            vec![(Some("x\ny".to_string()), 2), (None, 1)]
+        );
+        assert_eq!(summary.histograms[2].line, 4);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // The extremes are pinned to exactly 0 and 1
+    fn test_wilson_interval() {
+        let (low, high) = wilson_interval(50, 100);
+        assert!((low - 0.4038).abs() < 1e-3 && (high - 0.5962).abs() < 1e-3);
+        assert_eq!(wilson_interval(0, 10).0, 0.0);
+        assert_eq!(wilson_interval(10, 10).1, 1.0);
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn test_csv_and_json_escaping() {
+        let probes = probes_of("blah \"a,\\\"b\\\"\"", &CompileOptions::default());
+        let summary = aggregate(&probes, &[run("a,\"b\"\n", 0)], 1, None);
+
+        let csv = summary.render(ReportFormat::Csv);
+        assert!(csv.starts_with("line,statement,value,count,runs,frequency,ci_low,ci_high,exact\n"));
+        assert!(csv.contains(",\"a,\"\"b\"\"\",1,1,"));
+
+        let json = summary.render(ReportFormat::Json);
+        assert!(json.contains("\"value\": \"a,\\\"b\\\"\""));
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+}
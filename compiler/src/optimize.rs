@@ -0,0 +1,1017 @@
+// IR Optimisation Passes
+//
+// `-O` runs three passes over the IR between lowering and the C backend, over
+// and over until none of them changes anything:
+//
+//   elide_null_checks  a maybe known to be a `some` needs no null check, and
+//                      neither does a variable read with null as 0, as its
+//                      value slot is then 0 anyway; a null flag that is no
+//                      longer read is not kept at all
+//   fold_constants     arithmetic, comparisons and builtins of constants,
+//                      following the null rules of the runtime; `surely` and
+//                      `given` of a condition that always holds go away
+//   remove_dead_code   the stores and slots of variables that are never read
+//
+// so that `maybe x = 1 + 2`, `maybe y = 5`, `blah x - 1` compiles to
+//
+//     int mist_v_x = 0;
+//     if (mist_coin()) {
+//         mist_v_x = 3;
+//     }
+//     (void)mist_coin();
+//     printf("%d\n", mist_sub_int(mist_v_x, 1));
+//
+// A run of the optimised program is indistinguishable from one of the
+// original: the same output, trace, exit status and failures. Nothing with an
+// effect (a coin, `input`, a trace, a strict-mode `nonnull`) is dropped or
+// moved, so the random stream is unchanged and an unused declaration still
+// flips its coin; coin traces and `--world` selectors mean the same world with
+// and without `-O`. Integer arithmetic is folded saturating at the limits of
+// an int, as the interpreter and the C runtime compute it.
+//
+// Eliding a variable's null check rests on two facts about lowered programs:
+// every null the runtime makes carries the value 0, and a variable is only
+// nulled by its declaration's coin, before anything is stored in it. A null
+// variable's value slot therefore holds 0, and `null_flag x ? 0 : value x` is
+// just `value x`.
+//
+// The passes work on trees: as every temporary is used exactly once, a block's
+// `let`s are a forest of expressions, rebuilt and renumbered after each pass,
+// with the `bind`s that keep their effects in order placed afresh.
+
+use crate::compiler::{apply_builtin, Value};
+use crate::ir::{self, operands_to_bind, Instruction, IrProgram, IrType, Op, Temp};
+use crate::rational::Rational;
+use std::collections::{HashMap, HashSet};
+
+// ===========================================================================
+// PIPELINE
+// ===========================================================================
+
+/// Run the passes until the program stops changing
+pub fn optimize(program: &mut IrProgram) {
+    loop {
+        let before = program.clone();
+        elide_null_checks(program);
+        fold_constants(program);
+        remove_dead_code(program);
+        if *program == before {
+            break;
+        }
+    }
+}
+
+/// Remove the null checks of values known not to be null, and the null flags
+/// that are no longer read after that
+pub fn elide_null_checks(program: &mut IrProgram) {
+    rewrite(program, |blocks| {
+        let blocks: Vec<Vec<Step>> = blocks
+            .into_iter()
+            .map(|steps| map_nodes(steps, &mut elide))
+            .collect();
+        let reads = Reads::of(&blocks);
+        let unflagged = |name: &str| !reads.flags.contains(name);
+        blocks
+            .into_iter()
+            .map(|steps| {
+                map_steps(steps, &mut |step| match step {
+                    Step::Do(Instruction::Declare(name)) if unflagged(&name) => {
+                        vec![Step::Do(Instruction::DeclareValue(name))]
+                    },
+                    Step::Do(Instruction::SetNull(name)) if unflagged(&name) => Vec::new(),
+                    // A null maybe's value is 0, as is the value slot of a null variable
+                    Step::Use(Instruction::StoreMaybe(name, _), maybe) if unflagged(&name) => {
+                        let value = elide(Node::new(IrType::Int, Op::OrZero(OPERAND), vec![maybe]));
+                        vec![Step::Use(Instruction::Store(name, OPERAND), value)]
+                    },
+                    step => vec![step],
+                })
+            })
+            .collect()
+    });
+}
+
+/// Evaluate what only depends on constants
+pub fn fold_constants(program: &mut IrProgram) {
+    rewrite(program, |blocks| {
+        blocks
+            .into_iter()
+            .map(|steps| {
+                let steps = map_nodes(steps, &mut fold);
+                map_steps(steps, &mut |step| match step {
+                    Step::Use(Instruction::Assert(..) | Instruction::Condition(_), condition)
+                        if condition.as_bool() == Some(true) =>
+                    {
+                        Vec::new()
+                    },
+                    Step::If(condition, then, otherwise) if condition.as_bool().is_some() => {
+                        if condition.as_bool() == Some(true) {
+                            then
+                        } else {
+                            otherwise
+                        }
+                    },
+                    step => vec![step],
+                })
+            })
+            .collect()
+    });
+}
+
+/// Remove variables that are never read, keeping whatever their declarations
+/// do besides storing, such as flipping a coin
+pub fn remove_dead_code(program: &mut IrProgram) {
+    rewrite(program, |blocks| {
+        let reads = Reads::of(&blocks);
+        let unread = |name: &str| !reads.variables.contains(name);
+        blocks
+            .into_iter()
+            .map(|steps| {
+                map_steps(steps, &mut |step| match step {
+                    Step::Do(
+                        Instruction::Declare(name)
+                        | Instruction::DeclareValue(name)
+                        | Instruction::SetNull(name),
+                    ) if unread(&name) => Vec::new(),
+                    Step::Use(
+                        Instruction::Store(name, _) | Instruction::StoreMaybe(name, _),
+                        value,
+                    ) if unread(&name) => discard(value),
+                    Step::Use(Instruction::Discard(_), value) => discard(value),
+                    Step::If(condition, then, otherwise)
+                        if then.is_empty() && otherwise.is_empty() =>
+                    {
+                        discard(condition)
+                    },
+                    step => vec![step],
+                })
+            })
+            .collect()
+    });
+}
+
+// ===========================================================================
+// REWRITES
+// ===========================================================================
+
+/// `elide_null_checks` on one node whose operands are done
+fn elide(node: Node) -> Node {
+    match (&node.op, node.operands.as_slice()) {
+        // A variable read with null as 0
+        (Op::Select(..), [flag, zero, value])
+            if zero.as_int() == Some(0)
+                && matches!((&flag.op, &value.op), (Op::NullFlag(x), Op::Value(y)) if x == y) =>
+        {
+            value.clone()
+        },
+        (Op::OrZero(_) | Op::NonNull(..), [maybe]) if matches!(maybe.op, Op::Some(_)) => {
+            maybe.operands[0].clone()
+        },
+        (Op::IsNull(_), [maybe]) if matches!(maybe.op, Op::Some(_)) && maybe.is_pure() => {
+            Node::boolean(false)
+        },
+        (Op::AddMaybe(..) | Op::SubMaybe(..), [a, b])
+            if matches!(a.op, Op::Some(_)) && matches!(b.op, Op::Some(_)) =>
+        {
+            let op = match node.op {
+                Op::AddMaybe(..) => Op::Add(OPERAND, OPERAND),
+                _ => Op::Sub(OPERAND, OPERAND),
+            };
+            let value = Node::new(
+                IrType::Int,
+                op,
+                vec![a.operands[0].clone(), b.operands[0].clone()],
+            );
+            Node::new(IrType::Maybe, Op::Some(OPERAND), vec![value])
+        },
+        (Op::Pack(..), [value, flag]) if flag.as_bool() == Some(false) => {
+            Node::new(IrType::Maybe, Op::Some(OPERAND), vec![value.clone()])
+        },
+        _ => node,
+    }
+}
+
+/// `fold_constants` on one node whose operands are done
+fn fold(node: Node) -> Node {
+    let folded = match (&node.op, node.operands.as_slice()) {
+        (Op::Add(..), [a, b]) => a
+            .as_int()
+            .zip(b.as_int())
+            .map(|(a, b)| a.saturating_add(b))
+            .map(Node::int),
+        (Op::Sub(..), [a, b]) => a
+            .as_int()
+            .zip(b.as_int())
+            .map(|(a, b)| a.saturating_sub(b))
+            .map(Node::int),
+        (Op::Compare(op, ..), [a, b]) => a
+            .as_number()
+            .zip(b.as_number())
+            .map(|(a, b)| Node::boolean(op.holds(a.cmp(&b)))),
+        (Op::Select(..), [condition, a, b]) => match condition.as_bool() {
+            Some(true) if b.is_pure() => Some(a.clone()),
+            Some(false) if a.is_pure() => Some(b.clone()),
+            _ => None,
+        },
+        // null ⊕ e = e ⊕ null = null, whatever e is
+        (Op::AddMaybe(..) | Op::SubMaybe(..), [a, b]) => match (a.as_maybe(), b.as_maybe()) {
+            (Some(Value::Null), _) if b.is_pure() => Some(Node::maybe(None)),
+            (_, Some(Value::Null)) if a.is_pure() => Some(Node::maybe(None)),
+            (Some(Value::Integer(x)), Some(Value::Integer(y))) => {
+                Some(Node::maybe(Some(match node.op {
+                    Op::AddMaybe(..) => x.saturating_add(y),
+                    _ => x.saturating_sub(y),
+                })))
+            },
+            _ => None,
+        },
+        (Op::OrZero(_), [maybe]) => maybe.as_maybe().map(|value| match value {
+            Value::Integer(n) => Node::int(n),
+            _ => Node::int(0),
+        }),
+        (Op::IsNull(_), [maybe]) => maybe
+            .as_maybe()
+            .map(|value| Node::boolean(value == Value::Null)),
+        // nonnull of a constant null is left to fail at run time
+        (Op::NonNull(..), [maybe]) => match maybe.as_maybe() {
+            Some(Value::Integer(n)) => Some(Node::int(n)),
+            _ => None,
+        },
+        (Op::Call(name, _), args) => args
+            .iter()
+            .map(Node::as_maybe)
+            .collect::<Option<Vec<Value>>>()
+            .and_then(|values| match apply_builtin(name, &values) {
+                Ok(Value::Integer(n)) => Some(Node::maybe(Some(n))),
+                Ok(Value::Null) => Some(Node::maybe(None)),
+                _ => None,
+            }),
+        _ => None,
+    };
+    folded.unwrap_or(node)
+}
+
+/// Evaluate a tree for its effects, if it has any
+fn discard(node: Node) -> Vec<Step> {
+    if node.is_pure() {
+        Vec::new()
+    } else {
+        vec![Step::Use(Instruction::Discard(OPERAND), node)]
+    }
+}
+
+// ===========================================================================
+// TREES
+// ===========================================================================
+
+/// Stands in an operation or instruction for an operand that its tree holds
+const OPERAND: Temp = Temp(usize::MAX);
+
+/// A temporary with the tree of temporaries it is computed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node {
+    ty: IrType,
+    /// Reads OPERAND in place of each of `operands`
+    op: Op,
+    operands: Vec<Node>,
+}
+
+impl Node {
+    fn new(ty: IrType, op: Op, operands: Vec<Node>) -> Self {
+        Node {
+            ty,
+            op: op.map_operands(|_| OPERAND),
+            operands,
+        }
+    }
+
+    fn int(n: i32) -> Self {
+        Node::new(IrType::Int, Op::Int(n), Vec::new())
+    }
+
+    fn boolean(b: bool) -> Self {
+        Node::new(IrType::Bool, Op::Bool(b), Vec::new())
+    }
+
+    /// A constant maybe, null being 0 with its flag set as the runtime makes it
+    fn maybe(value: Option<i32>) -> Self {
+        match value {
+            Some(n) => Node::new(IrType::Maybe, Op::Some(OPERAND), vec![Node::int(n)]),
+            None => Node::new(
+                IrType::Maybe,
+                Op::Pack(OPERAND, OPERAND),
+                vec![Node::int(0), Node::boolean(true)],
+            ),
+        }
+    }
+
+    fn as_int(&self) -> Option<i32> {
+        match self.op {
+            Op::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self.op {
+            Op::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// An integer or query answer constant, as comparisons see it
+    fn as_number(&self) -> Option<Rational> {
+        match self.op {
+            Op::Int(n) => Some(Rational::from(n)),
+            Op::Rational(r) => Some(r),
+            _ => None,
+        }
+    }
+
+    /// A constant maybe, as built by `Node::maybe`, as the builtins take it
+    fn as_maybe(&self) -> Option<Value> {
+        match (&self.op, self.operands.as_slice()) {
+            (Op::Some(_), [value]) => value.as_int().map(Value::Integer),
+            (Op::Pack(..), [value, flag])
+                if value.as_int() == Some(0) && flag.as_bool() == Some(true) =>
+            {
+                Some(Value::Null)
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether evaluating the tree has no effect, so that it may be dropped
+    fn is_pure(&self) -> bool {
+        !self.op.has_effect() && self.operands.iter().all(Node::is_pure)
+    }
+
+    /// Rewrite every node of the tree, operands before the node itself
+    fn map(self, f: &mut impl FnMut(Node) -> Node) -> Node {
+        let operands = self.operands.into_iter().map(|node| node.map(f)).collect();
+        f(Node { operands, ..self })
+    }
+
+    fn each_op<'a>(&'a self, f: &mut impl FnMut(&'a Op)) {
+        f(&self.op);
+        for operand in &self.operands {
+            operand.each_op(f);
+        }
+    }
+}
+
+/// An instruction of a block with the trees of the temporaries it reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// An instruction that reads no temporary
+    Do(Instruction),
+    /// An instruction reading OPERAND in place of the tree
+    Use(Instruction, Node),
+    If(Node, Vec<Step>, Vec<Step>),
+}
+
+/// Run a pass over the steps of every block, then turn them back into
+/// instructions with the temporaries numbered afresh
+fn rewrite(program: &mut IrProgram, pass: impl FnOnce(Vec<Vec<Step>>) -> Vec<Vec<Step>>) {
+    let blocks = program
+        .blocks
+        .iter()
+        .map(|block| steps(&block.instructions))
+        .collect();
+    let mut next = 0;
+    for (block, steps) in program.blocks.iter_mut().zip(pass(blocks)) {
+        block.instructions = instructions(steps, &mut next);
+    }
+}
+
+fn steps(instructions: &[Instruction]) -> Vec<Step> {
+    let mut trees: HashMap<Temp, Node> = HashMap::new();
+    let take = |trees: &mut HashMap<Temp, Node>, temp: Temp| {
+        trees
+            .remove(&temp)
+            .unwrap_or_else(|| unreachable!("{} is not a temporary of the block", temp))
+    };
+    let mut steps = Vec::new();
+    for instruction in instructions {
+        match instruction {
+            Instruction::Let(temp, ty, op) | Instruction::Bind(temp, ty, op) => {
+                let operands = op
+                    .operands()
+                    .into_iter()
+                    .map(|operand| take(&mut trees, operand))
+                    .collect();
+                trees.insert(*temp, Node::new(*ty, op.clone(), operands));
+            },
+            Instruction::If(condition, then, otherwise) => steps.push(Step::If(
+                take(&mut trees, *condition),
+                self::steps(then),
+                self::steps(otherwise),
+            )),
+            _ => steps.push(match read_temp(instruction) {
+                Some(temp) => Step::Use(with_temp(instruction, OPERAND), take(&mut trees, temp)),
+                None => Step::Do(instruction.clone()),
+            }),
+        }
+    }
+    steps
+}
+
+fn instructions(steps: Vec<Step>, next: &mut usize) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    for step in steps {
+        match step {
+            Step::Do(instruction) => instructions.push(instruction),
+            Step::Use(instruction, node) => {
+                let temp = emit(node, next, &mut instructions);
+                instructions.push(with_temp(&instruction, temp));
+            },
+            Step::If(condition, then, otherwise) => {
+                let condition = emit(condition, next, &mut instructions);
+                let then = self::instructions(then, next);
+                let otherwise = self::instructions(otherwise, next);
+                instructions.push(Instruction::If(condition, then, otherwise));
+            },
+        }
+    }
+    instructions
+}
+
+/// The `let`s of a tree, operands first; returns the temporary of its root
+fn emit(node: Node, next: &mut usize, instructions: &mut Vec<Instruction>) -> Temp {
+    tree(node, next, instructions).0
+}
+
+/// `emit`, also returning whether the root has an effect still to happen
+fn tree(node: Node, next: &mut usize, instructions: &mut Vec<Instruction>) -> (Temp, bool) {
+    let (operands, mut effects): (Vec<Temp>, Vec<bool>) = node
+        .operands
+        .into_iter()
+        .map(|operand| tree(operand, next, instructions))
+        .unzip();
+    for (index, bind) in operands_to_bind(&node.op, &effects).into_iter().enumerate() {
+        if bind {
+            ir::bind(instructions, operands[index]);
+            effects[index] = false;
+        }
+    }
+    let effect = node.op.has_effect() || effects.contains(&true);
+    let mut operands = operands.into_iter();
+    let op = node.op.map_operands(|_| operands.next().unwrap_or(OPERAND));
+    let temp = Temp(*next);
+    *next += 1;
+    instructions.push(Instruction::Let(temp, node.ty, op));
+    (temp, effect)
+}
+
+/// The temporary an instruction other than `let` and `if` reads, if any
+fn read_temp(instruction: &Instruction) -> Option<Temp> {
+    match instruction {
+        Instruction::Store(_, temp)
+        | Instruction::StoreMaybe(_, temp)
+        | Instruction::Print(temp)
+        | Instruction::Assert(temp, _)
+        | Instruction::Exit(temp)
+        | Instruction::Condition(temp)
+        | Instruction::Discard(temp) => Some(*temp),
+        _ => None,
+    }
+}
+
+fn with_temp(instruction: &Instruction, temp: Temp) -> Instruction {
+    match instruction {
+        Instruction::Store(name, _) => Instruction::Store(name.clone(), temp),
+        Instruction::StoreMaybe(name, _) => Instruction::StoreMaybe(name.clone(), temp),
+        Instruction::Print(_) => Instruction::Print(temp),
+        Instruction::Assert(_, message) => Instruction::Assert(temp, message.clone()),
+        Instruction::Exit(_) => Instruction::Exit(temp),
+        Instruction::Condition(_) => Instruction::Condition(temp),
+        Instruction::Discard(_) => Instruction::Discard(temp),
+        _ => instruction.clone(),
+    }
+}
+
+fn map_nodes(steps: Vec<Step>, f: &mut impl FnMut(Node) -> Node) -> Vec<Step> {
+    steps
+        .into_iter()
+        .map(|step| match step {
+            Step::Do(instruction) => Step::Do(instruction),
+            Step::Use(instruction, node) => Step::Use(instruction, node.map(f)),
+            Step::If(condition, then, otherwise) => Step::If(
+                condition.map(f),
+                map_nodes(then, f),
+                map_nodes(otherwise, f),
+            ),
+        })
+        .collect()
+}
+
+/// Replace every step by any number of steps; an `if` is replaced after its branches
+fn map_steps(steps: Vec<Step>, f: &mut impl FnMut(Step) -> Vec<Step>) -> Vec<Step> {
+    let mut mapped = Vec::new();
+    for step in steps {
+        let step = match step {
+            Step::If(condition, then, otherwise) => {
+                Step::If(condition, map_steps(then, f), map_steps(otherwise, f))
+            },
+            step => step,
+        };
+        mapped.extend(f(step));
+    }
+    mapped
+}
+
+/// The variables a program reads anywhere
+#[derive(Default)]
+struct Reads {
+    /// Read in any way, including by the trace
+    variables: HashSet<String>,
+    /// Whose null flag is read
+    flags: HashSet<String>,
+}
+
+impl Reads {
+    fn of(blocks: &[Vec<Step>]) -> Self {
+        let mut reads = Reads::default();
+        for steps in blocks {
+            reads.add(steps);
+        }
+        reads
+    }
+
+    fn add(&mut self, steps: &[Step]) {
+        for step in steps {
+            match step {
+                Step::Do(instruction) => self.instruction(instruction),
+                Step::Use(_, node) => self.node(node),
+                Step::If(condition, then, otherwise) => {
+                    self.node(condition);
+                    self.add(then);
+                    self.add(otherwise);
+                },
+            }
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::TraceDeclaration {
+                name,
+                nullable: true,
+                ..
+            } => {
+                self.variables.insert(name.clone());
+                self.flags.insert(name.clone());
+            },
+            Instruction::TraceDeclaration { name, .. } | Instruction::TraceNull { name, .. } => {
+                self.variables.insert(name.clone());
+            },
+            _ => {},
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        node.each_op(&mut |op| match op {
+            Op::Value(name) => {
+                self.variables.insert(name.clone());
+            },
+            Op::NullFlag(name) => {
+                self.variables.insert(name.clone());
+                self.flags.insert(name.clone());
+            },
+            _ => {},
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{CompileOptions, NullMode, TraceLevel};
+    use crate::driver::check_source;
+    use crate::ir::{verify, Block};
+    use crate::test_support::{lower_source, SAMPLES};
+
+    /// Programs run through every pass after the samples
+    const SOURCES: [&str; 5] = [
+        "maybe x = 1 + 2\nmaybe y = x - 1\nmaybe z = max(4, 2) - 1\nblah y + x\nblah x is null\n\
+         blah z > 2\nsurely y >= 0\nsurely 3 - 1 == 2\nbye x",
+        "maybe n = input\nmaybe a = arg(1)\nmaybe (p, q) = (max(n, a) + 1, abs(n))\n\
+         blah min(p, 3) - q\nblah input is null\ngiven p is null\nbye p + sign(q)",
+        "maybe x = 1\nmaybe y = 2\nblah prob(x > 0)\nsurely prob(x == 1) > 0\n\
+         given expect(y) <= x + 1\nblah x + y",
+        "maybe c = 3\nmaybe d = input + c\nsurely d - c != 7\nmaybe (u, v) = (1, 2)\n\
+         blah u + v\nblah arg(0 + 1)",
+        "maybe x = 1\nmaybe x = 2\nmaybe unused = clamp(7, 0, 5)\nblah x",
+    ];
+
+    fn options(null_mode: NullMode, trace: TraceLevel) -> CompileOptions {
+        CompileOptions {
+            null_mode,
+            trace,
+            ..CompileOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_optimized_dump() {
+        let mut program = lower_source(
+            "maybe x = 1 + 2\nmaybe y = 5\nblah x - 1\nsurely 1 < 2",
+            &options(NullMode::Zero, TraceLevel::None),
+        );
+        optimize(&mut program);
+        assert_eq!(
+            program.to_string(),
+            "\
+# line 1: Maybe declaration: x
+declare_value x
+%0 = coin : bool
+if %0 {
+    %1 = int 3 : int
+    store x, %1
+}
+# line 2: Maybe declaration: y
+%2 = coin : bool
+discard %2
+# line 3: Blah statement
+%3 = value x : int
+%4 = int 1 : int
+%5 = sub %3, %4 : int
+print %5
+# line 4: Surely statement (line 4)
+"
+        );
+        assert_eq!(program.coin_count(), 2);
+    }
+
+    // An interpreter of the IR with the C backend's semantics, for checking
+    // that the passes change nothing a run can observe
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Val {
+        Int(i32),
+        Bool(bool),
+        Str(String),
+        Rational(Rational),
+        Maybe(i32, bool),
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum End {
+        Finished,
+        Exit(i32),
+        Failed(String),
+        Rejected,
+    }
+
+    /// A run in the world whose coin i is bit i of `world`, with the input
+    /// `4` and then end of file, and arg(n) = 10n
+    struct Run {
+        world: u64,
+        coins: usize,
+        inputs: Vec<i32>,
+        variables: HashMap<String, (i32, bool)>,
+        events: Vec<String>,
+    }
+
+    impl Run {
+        fn new(world: u64) -> Self {
+            Run {
+                world,
+                coins: 0,
+                inputs: vec![4],
+                variables: HashMap::new(),
+                events: Vec::new(),
+            }
+        }
+
+        fn steps(&mut self, steps: &[Step]) -> Result<(), End> {
+            for step in steps {
+                match step {
+                    Step::If(condition, then, otherwise) => {
+                        if self.boolean(condition)? {
+                            self.steps(then)?;
+                        } else {
+                            self.steps(otherwise)?;
+                        }
+                    },
+                    Step::Do(instruction) => self.instruction(instruction, None)?,
+                    Step::Use(instruction, node) => {
+                        let value = self.eval(node)?;
+                        self.instruction(instruction, Some(value))?;
+                    },
+                }
+            }
+            Ok(())
+        }
+
+        fn instruction(
+            &mut self,
+            instruction: &Instruction,
+            value: Option<Val>,
+        ) -> Result<(), End> {
+            match (instruction, value) {
+                (Instruction::Declare(name) | Instruction::DeclareValue(name), None) => {
+                    self.variables.insert(name.clone(), (0, false));
+                },
+                (Instruction::SetNull(name), None) => {
+                    self.variables.get_mut(name).unwrap().1 = true;
+                },
+                (Instruction::Store(name, _), Some(Val::Int(n))) => {
+                    self.variables.insert(name.clone(), (n, false));
+                },
+                (Instruction::StoreMaybe(name, _), Some(Val::Maybe(n, null))) => {
+                    self.variables.insert(name.clone(), (n, null));
+                },
+                (Instruction::Print(_), Some(value)) => self.events.push(format!("{:?}", value)),
+                (Instruction::Discard(_), Some(_)) => {},
+                (Instruction::Assert(_, message), Some(Val::Bool(holds))) => {
+                    if !holds {
+                        return Err(End::Failed(message.clone()));
+                    }
+                },
+                (Instruction::Condition(_), Some(Val::Bool(holds))) => {
+                    if !holds {
+                        return Err(End::Rejected);
+                    }
+                },
+                (Instruction::Exit(_), Some(Val::Int(status))) => return Err(End::Exit(status)),
+                (
+                    Instruction::TraceDeclaration {
+                        name,
+                        line,
+                        nullable,
+                    },
+                    None,
+                ) => {
+                    let (value, null) = self.variables[name];
+                    let shown = if *nullable && null {
+                        "null".to_string()
+                    } else {
+                        value.to_string()
+                    };
+                    self.events.push(format!("{:?} {} = {}", line, name, shown));
+                },
+                (Instruction::TraceNull { name, line }, None) => {
+                    self.events.push(format!("{:?} {} = null", line, name));
+                },
+                (instruction, value) => unreachable!("{:?} of {:?}", instruction, value),
+            }
+            Ok(())
+        }
+
+        fn boolean(&mut self, node: &Node) -> Result<bool, End> {
+            match self.eval(node)? {
+                Val::Bool(b) => Ok(b),
+                value => unreachable!("{:?} is not a bool", value),
+            }
+        }
+
+        fn int(&mut self, node: &Node) -> Result<i32, End> {
+            match self.eval(node)? {
+                Val::Int(n) => Ok(n),
+                value => unreachable!("{:?} is not an int", value),
+            }
+        }
+
+        fn maybe(&mut self, node: &Node) -> Result<(i32, bool), End> {
+            match self.eval(node)? {
+                Val::Maybe(n, null) => Ok((n, null)),
+                value => unreachable!("{:?} is not a maybe", value),
+            }
+        }
+
+        fn eval(&mut self, node: &Node) -> Result<Val, End> {
+            let operands = &node.operands;
+            Ok(match &node.op {
+                Op::Int(n) => Val::Int(*n),
+                Op::Bool(b) => Val::Bool(*b),
+                Op::Str(s) => Val::Str(s.clone()),
+                Op::Rational(r) => Val::Rational(*r),
+                Op::Value(name) => Val::Int(self.variables[name].0),
+                Op::NullFlag(name) => Val::Bool(self.variables[name].1),
+                // Only the chosen side is evaluated, as with C's `?:`
+                Op::Select(..) => {
+                    let side = if self.boolean(&operands[0])? { 1 } else { 2 };
+                    self.eval(&operands[side])?
+                },
+                Op::Pack(..) => Val::Maybe(self.int(&operands[0])?, self.boolean(&operands[1])?),
+                Op::Some(_) => Val::Maybe(self.int(&operands[0])?, false),
+                Op::OrZero(_) => {
+                    let (n, null) = self.maybe(&operands[0])?;
+                    Val::Int(if null { 0 } else { n })
+                },
+                Op::IsNull(_) => Val::Bool(self.maybe(&operands[0])?.1),
+                Op::NonNull(_, message) => match self.maybe(&operands[0])? {
+                    (_, true) => return Err(End::Failed(message.clone())),
+                    (n, false) => Val::Int(n),
+                },
+                Op::Add(..) => Val::Int(
+                    self.int(&operands[0])?
+                        .saturating_add(self.int(&operands[1])?),
+                ),
+                Op::Sub(..) => Val::Int(
+                    self.int(&operands[0])?
+                        .saturating_sub(self.int(&operands[1])?),
+                ),
+                Op::AddMaybe(..) | Op::SubMaybe(..) => {
+                    let (a, b) = (self.maybe(&operands[0])?, self.maybe(&operands[1])?);
+                    match (a, b) {
+                        ((_, true), _) | (_, (_, true)) => Val::Maybe(0, true),
+                        ((a, false), (b, false)) if matches!(node.op, Op::AddMaybe(..)) => {
+                            Val::Maybe(a.saturating_add(b), false)
+                        },
+                        ((a, false), (b, false)) => Val::Maybe(a.saturating_sub(b), false),
+                    }
+                },
+                Op::Compare(op, ..) => {
+                    let mut number = |node| match self.eval(node)? {
+                        Val::Int(n) => Ok(Rational::from(n)),
+                        Val::Rational(r) => Ok(r),
+                        value => unreachable!("{:?} is not a number", value),
+                    };
+                    let a = number(&operands[0])?;
+                    let b = number(&operands[1])?;
+                    Val::Bool(op.holds(a.cmp(&b)))
+                },
+                Op::Call(name, _) => {
+                    let mut args = Vec::new();
+                    for operand in operands {
+                        args.push(match self.maybe(operand)? {
+                            (_, true) => Value::Null,
+                            (n, false) => Value::Integer(n),
+                        });
+                    }
+                    match apply_builtin(name, &args) {
+                        Ok(Value::Integer(n)) => Val::Maybe(n, false),
+                        Ok(Value::Null) => Val::Maybe(0, true),
+                        result => unreachable!("{} gave {:?}", name, result),
+                    }
+                },
+                Op::Input => match self.inputs.pop() {
+                    Some(n) => Val::Maybe(n, false),
+                    None => Val::Maybe(0, true),
+                },
+                Op::Arg(_) => Val::Maybe(self.int(&operands[0])?.wrapping_mul(10), false),
+                Op::Coin => {
+                    let coin = self.world >> self.coins & 1 == 1;
+                    self.coins += 1;
+                    Val::Bool(coin)
+                },
+                Op::Trace {
+                    line, statement, ..
+                } => {
+                    let value = self.eval(&operands[0])?;
+                    self.events
+                        .push(format!("[line {}] {} => {:?}", line, statement, value));
+                    value
+                },
+            })
+        }
+    }
+
+    /// What a run of the program shows in each of its worlds
+    fn runs(program: &IrProgram) -> Vec<(Vec<String>, End)> {
+        let worlds = 1u64 << program.coin_count().min(8);
+        (0..worlds)
+            .map(|world| {
+                let mut run = Run::new(world);
+                let mut end = End::Finished;
+                for block in &program.blocks {
+                    if let Err(stop) = run.steps(&steps(&block.instructions)) {
+                        end = stop;
+                        break;
+                    }
+                }
+                (run.events, end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_passes_preserve_every_run() {
+        type Pass = fn(&mut IrProgram);
+        let passes: [(&str, Pass); 4] = [
+            ("elide_null_checks", elide_null_checks),
+            ("fold_constants", fold_constants),
+            ("remove_dead_code", remove_dead_code),
+            ("optimize", optimize),
+        ];
+        for source in SAMPLES.into_iter().chain(SOURCES) {
+            for null_mode in [NullMode::Zero, NullMode::Propagate, NullMode::Strict] {
+                for trace in [TraceLevel::None, TraceLevel::Declarations, TraceLevel::Full] {
+                    let original = lower_source(source, &options(null_mode, trace));
+                    let expected = runs(&original);
+                    for (name, pass) in passes {
+                        let mut program = original.clone();
+                        pass(&mut program);
+                        assert_eq!(verify(&program), Ok(()), "{}:\n{}", name, program);
+                        assert_eq!(program.coin_count(), original.coin_count(), "{}", name);
+                        assert_eq!(runs(&program), expected, "{}:\n{}", name, program);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_passes_shrink_the_corpus() {
+        for source in SAMPLES.into_iter().chain(SOURCES) {
+            let original = lower_source(source, &options(NullMode::Zero, TraceLevel::None));
+            let mut program = original.clone();
+            optimize(&mut program);
+            assert!(
+                program.to_string().len() < original.to_string().len(),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn test_folding_follows_the_null_rules() {
+        let folded = |ty: IrType, op: Op, operands: Vec<Node>| {
+            let step = Step::Use(Instruction::Discard(OPERAND), Node::new(ty, op, operands));
+            let mut program = IrProgram {
+                blocks: vec![Block {
+                    line: 1,
+                    comment: "Test".to_string(),
+                    instructions: instructions(vec![step], &mut 0),
+                }],
+                checksum: String::new(),
+            };
+            fold_constants(&mut program);
+            steps(&program.blocks[0].instructions)
+        };
+        let discard = |node: Node| vec![Step::Use(Instruction::Discard(OPERAND), node)];
+        let pair = |a: i32, b: Option<i32>| vec![Node::maybe(Some(a)), Node::maybe(b)];
+
+        // null ⊕ e is null, and so is a builtin of a null argument except max and min
+        assert_eq!(
+            folded(IrType::Maybe, Op::AddMaybe(OPERAND, OPERAND), pair(1, None)),
+            discard(Node::maybe(None))
+        );
+        assert_eq!(
+            folded(
+                IrType::Maybe,
+                Op::SubMaybe(OPERAND, OPERAND),
+                pair(1, Some(3))
+            ),
+            discard(Node::maybe(Some(-2)))
+        );
+        assert_eq!(
+            folded(
+                IrType::Maybe,
+                Op::Call("max".to_string(), vec![OPERAND; 2]),
+                pair(3, None)
+            ),
+            discard(Node::maybe(Some(3)))
+        );
+        assert_eq!(
+            folded(
+                IrType::Maybe,
+                Op::Call("abs".to_string(), vec![OPERAND]),
+                vec![Node::maybe(None)]
+            ),
+            discard(Node::maybe(None))
+        );
+        assert_eq!(
+            folded(IrType::Int, Op::OrZero(OPERAND), vec![Node::maybe(None)]),
+            discard(Node::int(0))
+        );
+        // Pure constants are dropped altogether by dead code removal, not here
+        // Overflow saturates, as in the runtime
+        let overflow = vec![Node::int(i32::MAX), Node::int(1)];
+        assert_eq!(
+            folded(IrType::Int, Op::Add(OPERAND, OPERAND), overflow),
+            discard(Node::int(i32::MAX))
+        );
+        // A null passed to nonnull still fails when the program runs
+        let check = Node::new(
+            IrType::Int,
+            Op::NonNull(OPERAND, "null".to_string()),
+            vec![Node::maybe(None)],
+        );
+        assert_eq!(
+            folded(
+                IrType::Int,
+                Op::NonNull(OPERAND, "null".to_string()),
+                vec![Node::maybe(None)]
+            ),
+            discard(check)
+        );
+    }
+
+    #[test]
+    fn test_unused_declaration_keeps_its_coin_in_c() {
+        let source = "maybe x = 1\nmaybe y = 2\nblah x + 1";
+        let options = CompileOptions {
+            optimize: true,
+            ..options(NullMode::Zero, TraceLevel::None)
+        };
+        let c = crate::compiler::generate_c(check_source(source, &options).unwrap(), &options);
+        assert!(c.contains("(void)mist_coin();"), "{}", c);
+        assert!(!c.contains("mist_v_y") && !c.contains("mist_n_y"), "{}", c);
+        // Null reads as 0 in zero mode, which x's value slot already holds
+        assert!(!c.contains("mist_n_x"), "{}", c);
+    }
+}
@@ -0,0 +1,300 @@
+// Compile-Time Answers to expect(e) and prob(c)
+//
+// The last phase of the front end replaces every `expect`/`prob` query with
+// its exact answer, a Rational that the backends treat as a constant. A query
+// is answered by enumerating the worlds of the statements before it with the
+// analyzer, so this phase sits above both the interpreter and the analyzer,
+// and the driver runs it after type checking.
+
+use crate::analysis::{coin_count, each_world, EXACT_COIN_LIMIT};
+use crate::compiler::{
+    Expression, ExpressionKind, NoInput, NullMode, Program, RuntimeError, Span, Statement,
+    StatementKind, Value,
+};
+use crate::rational::Rational;
+use std::fmt;
+
+/// Reasons a query cannot be answered statically
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    /// The query, or a statement before it, reads stdin or argv
+    DependsOnInput(String, Span),
+    /// More coin flips precede the query than can be enumerated
+    TooManyCoins {
+        query: String,
+        coins: usize,
+        span: Span,
+    },
+    /// Every world stops (bye, given or surely) before reaching the query
+    Unreachable(String, Span),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::DependsOnInput(query, _) => {
+                write!(
+                    f,
+                    "{} depends on program input and cannot be answered exactly",
+                    query
+                )
+            },
+            QueryError::TooManyCoins { query, coins, .. } => write!(
+                f,
+                "{} depends on {} coin flips; at most {} can be enumerated",
+                query, coins, EXACT_COIN_LIMIT
+            ),
+            QueryError::Unreachable(query, _) => write!(f, "no world reaches {}", query),
+            // The RuntimeError is the source
+            QueryError::Runtime(_) => write!(f, "the query failed while running"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueryError::Runtime(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Replace every `expect`/`prob` query with its exact answer
+///
+/// A query is answered over the worlds of the statements before it, so it sees
+/// every earlier `given`; null counts as 0 in `expect`, exactly as in ⊕.
+pub fn answer_queries(program: Program, mode: NullMode) -> Result<Program, QueryError> {
+    let mut answered = Program::new();
+    for stmt in program {
+        let prefix = &answered;
+        let answer = |e: Expression| answer_expression(e, prefix, mode);
+        let kind = match stmt.kind {
+            StatementKind::MaybeDeclaration(x, e) => StatementKind::MaybeDeclaration(x, answer(e)?),
+            StatementKind::EntangledDeclaration(bindings) => StatementKind::EntangledDeclaration(
+                bindings
+                    .into_iter()
+                    .map(|(x, e)| Ok((x, answer(e)?)))
+                    .collect::<Result<_, QueryError>>()?,
+            ),
+            StatementKind::BlahStatement(e) => StatementKind::BlahStatement(answer(e)?),
+            StatementKind::SurelyStatement(e) => StatementKind::SurelyStatement(answer(e)?),
+            StatementKind::ByeStatement(e) => StatementKind::ByeStatement(answer(e)?),
+            StatementKind::GivenStatement(e) => StatementKind::GivenStatement(answer(e)?),
+            StatementKind::Error => StatementKind::Error,
+        };
+        answered.push(Statement::new(kind, stmt.span));
+    }
+    Ok(answered)
+}
+
+/// Answer the queries in `expr`, innermost first, after the statements in `prefix`
+fn answer_expression(
+    expr: Expression,
+    prefix: &Program,
+    mode: NullMode,
+) -> Result<Expression, QueryError> {
+    let answer = |e: Box<Expression>| answer_expression(*e, prefix, mode).map(Box::new);
+    let span = expr.span;
+    let kind = match expr.kind {
+        ExpressionKind::Expect(e) => {
+            let query = Expression::new(ExpressionKind::Expect(answer(e)?), span);
+            ExpressionKind::Exact(evaluate_query(&query, prefix, mode)?, Box::new(query))
+        },
+        ExpressionKind::Prob(e) => {
+            let query = Expression::new(ExpressionKind::Prob(answer(e)?), span);
+            ExpressionKind::Exact(evaluate_query(&query, prefix, mode)?, Box::new(query))
+        },
+        ExpressionKind::Argument(e) => ExpressionKind::Argument(answer(e)?),
+        ExpressionKind::Call(name, args) => ExpressionKind::Call(
+            name,
+            args.into_iter()
+                .map(|arg| answer_expression(arg, prefix, mode))
+                .collect::<Result<_, _>>()?,
+        ),
+        ExpressionKind::Add(e1, e2) => ExpressionKind::Add(answer(e1)?, answer(e2)?),
+        ExpressionKind::Subtract(e1, e2) => ExpressionKind::Subtract(answer(e1)?, answer(e2)?),
+        ExpressionKind::Comparison(op, e1, e2) => {
+            ExpressionKind::Comparison(op, answer(e1)?, answer(e2)?)
+        },
+        ExpressionKind::IsNull(e) => ExpressionKind::IsNull(answer(e)?),
+        e @ (ExpressionKind::Number(_)
+        | ExpressionKind::Variable(_)
+        | ExpressionKind::StringLiteral(_)
+        | ExpressionKind::Input
+        | ExpressionKind::Exact(_, _)
+        | ExpressionKind::Error) => e,
+    };
+    Ok(Expression::new(kind, span))
+}
+
+/// Enumerate the worlds of `prefix` and average the query's subject over those
+/// that reach it
+fn evaluate_query(
+    query: &Expression,
+    prefix: &Program,
+    mode: NullMode,
+) -> Result<Rational, QueryError> {
+    let (subject, is_expect) = match &query.kind {
+        ExpressionKind::Expect(e) => (e, true),
+        ExpressionKind::Prob(e) => (e, false),
+        _ => unreachable!("only expect and prob are queries"),
+    };
+
+    if reads_input(subject)
+        || prefix
+            .iter()
+            .any(|stmt| statement_expressions(stmt).any(reads_input))
+    {
+        return Err(QueryError::DependsOnInput(query.to_string(), query.span));
+    }
+    let coins = coin_count(prefix);
+    if coins > EXACT_COIN_LIMIT {
+        return Err(QueryError::TooManyCoins {
+            query: query.to_string(),
+            coins,
+            span: query.span,
+        });
+    }
+
+    // The subject is printed by a probe statement appended to the prefix, so a
+    // world reaches the query exactly when the probe shows up in its output
+    let mut probe = prefix.clone();
+    probe.push(Statement::new(
+        StatementKind::BlahStatement((**subject).clone()),
+        query.span,
+    ));
+    let probe_index = prefix.len();
+
+    let mut total = 0i128;
+    let mut reached = 0i128;
+    each_world(
+        &probe,
+        Box::new(NoInput),
+        mode,
+        coins,
+        |attempt, printed| {
+            match attempt {
+                // A world that stops on a failed check never reaches the query
+                Ok(_)
+                | Err(RuntimeError::AssertionFailed { .. } | RuntimeError::NullOperand(..)) => {},
+                Err(e) => return Err(QueryError::Runtime(e)),
+            }
+            if let Some((_, value)) = printed.iter().find(|(index, _)| *index == probe_index) {
+                reached += 1;
+                total += match value {
+                    Value::Integer(n) if is_expect => i128::from(*n),
+                    Value::Boolean(true) if !is_expect => 1,
+                    _ => 0,
+                };
+            }
+            Ok(())
+        },
+    )?;
+
+    if reached == 0 {
+        return Err(QueryError::Unreachable(query.to_string(), query.span));
+    }
+    Ok(Rational::new(total, reached))
+}
+
+fn statement_expressions(stmt: &Statement) -> Box<dyn Iterator<Item = &Expression> + '_> {
+    match &stmt.kind {
+        StatementKind::EntangledDeclaration(bindings) => Box::new(bindings.iter().map(|(_, e)| e)),
+        StatementKind::MaybeDeclaration(_, e)
+        | StatementKind::BlahStatement(e)
+        | StatementKind::SurelyStatement(e)
+        | StatementKind::ByeStatement(e)
+        | StatementKind::GivenStatement(e) => Box::new(std::iter::once(e)),
+        StatementKind::Error => Box::new(std::iter::empty()),
+    }
+}
+
+/// Whether evaluating `expr` observes stdin or argv
+fn reads_input(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Input | ExpressionKind::Argument(_) => true,
+        ExpressionKind::Call(_, args) => args.iter().any(reads_input),
+        ExpressionKind::Add(e1, e2)
+        | ExpressionKind::Subtract(e1, e2)
+        | ExpressionKind::Comparison(_, e1, e2) => reads_input(e1) || reads_input(e2),
+        ExpressionKind::IsNull(e) | ExpressionKind::Expect(e) | ExpressionKind::Prob(e) => {
+            reads_input(e)
+        },
+        ExpressionKind::Number(_)
+        | ExpressionKind::Variable(_)
+        | ExpressionKind::StringLiteral(_)
+        | ExpressionKind::Exact(_, _)
+        | ExpressionKind::Error => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::{front_end, CompilerError};
+
+    fn answer(source: &str) -> Vec<Rational> {
+        fn collect(expr: &Expression, answers: &mut Vec<Rational>) {
+            match &expr.kind {
+                ExpressionKind::Exact(r, _) => answers.push(*r),
+                ExpressionKind::Comparison(_, e1, e2) => {
+                    collect(e1, answers);
+                    collect(e2, answers);
+                },
+                _ => {},
+            }
+        }
+        let (program, _) = front_end(source).unwrap();
+        let mut answers = Vec::new();
+        for stmt in &program {
+            statement_expressions(stmt).for_each(|e| collect(e, &mut answers));
+        }
+        answers
+    }
+
+    #[test]
+    fn test_queries_are_exact() {
+        let source = "maybe die1 = 3\nmaybe die2 = 4\nblah expect(die1 + die2)\n\
+                      blah prob(die1 is null)\nblah prob(die1 + die2 >= 4)";
+        assert_eq!(
+            answer(source),
+            vec![
+                Rational::new(7, 2),
+                Rational::new(1, 2),
+                Rational::new(1, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_queries_see_earlier_givens() {
+        let source = "maybe a = 1\nmaybe b = 2\ngiven a + b > 0\nblah prob(a is null)\n\
+                      maybe c = 5\nblah expect(c)";
+        assert_eq!(
+            answer(source),
+            vec![Rational::new(1, 3), Rational::new(5, 2)]
+        );
+    }
+
+    #[test]
+    fn test_query_errors() {
+        assert!(matches!(
+            front_end("maybe n = input\nblah expect(n)"),
+            Err(CompilerError::QueryError(QueryError::DependsOnInput(..)))
+        ));
+        assert!(matches!(
+            front_end("maybe a = 1\ngiven a > 5\nblah prob(a is null)"),
+            Err(CompilerError::QueryError(QueryError::Unreachable(..)))
+        ));
+        let many = "maybe a = 1\n".repeat(EXACT_COIN_LIMIT + 1) + "blah expect(a)";
+        assert!(matches!(
+            front_end(&many),
+            Err(CompilerError::QueryError(QueryError::TooManyCoins {
+                coins: 21,
+                ..
+            }))
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+// Exact Rational Arithmetic
+//
+// Probabilities and expectations are kept as exact fractions, so that a query
+// such as `prob(a is null)` is answered as 1/2 rather than 0.5000000001. The
+// AST, the analyzer, the IR and the optimiser all share this one
+// numeric type, which depends on nothing else in the compiler.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul};
+
+/// Exact rational number in lowest terms with a positive denominator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: i128,
+    den: i128,
+}
+
+impl Rational {
+    /// num/den reduced to lowest terms; `den` must be non-zero
+    pub fn new(num: i128, den: i128) -> Self {
+        debug_assert!(den != 0, "zero denominator");
+        let sign = if den < 0 { -1 } else { 1 };
+        let divisor = gcd(num.abs(), den.abs()).max(1);
+        Rational {
+            num: sign * num / divisor,
+            den: sign * den / divisor,
+        }
+    }
+
+    pub fn numerator(self) -> i128 {
+        self.num
+    }
+
+    pub fn denominator(self) -> i128 {
+        self.den
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl From<i32> for Rational {
+    fn from(n: i32) -> Self {
+        Rational {
+            num: i128::from(n),
+            den: 1,
+        }
+    }
+}
+
+impl Add for Rational {
+    type Output = Rational;
+
+    fn add(self, other: Rational) -> Rational {
+        Rational::new(
+            self.num * other.den + other.num * self.den,
+            self.den * other.den,
+        )
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, other: Rational) -> Rational {
+        Rational::new(self.num * other.num, self.den * other.den)
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+
+    fn div(self, other: Rational) -> Rational {
+        Rational::new(self.num * other.den, self.den * other.num)
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        (self.num * other.den).cmp(&(other.num * self.den))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rational_arithmetic() {
+        let half = Rational::new(2, 4);
+        assert_eq!(half, Rational::new(1, 2));
+        assert_eq!(half + Rational::new(1, 3), Rational::new(5, 6));
+        assert_eq!(half * Rational::new(-2, 3), Rational::new(-1, 3));
+        assert_eq!(half / Rational::new(1, 4), Rational::new(2, 1));
+        assert_eq!(Rational::new(3, -6).to_string(), "-1/2");
+        assert!(Rational::new(1, 3) < half);
+    }
+}
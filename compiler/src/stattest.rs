@@ -0,0 +1,701 @@
+// Statistical Tests Written in Mist
+//
+// `mist-compiler test <file.mist>...` checks expectations written in the
+// program's own comments, one per `//!` line:
+//
+//     maybe a = 10
+//     maybe b = 2
+//     blah a + b
+//     //! line 3 prints 12 with p = 0.25 ± 0.02
+//     //! line 3 distribution 0: 0.25, 2: 0.25, 10: 0.25, 12: 0.25
+//
+// When the analyzer can enumerate every world, the exact probabilities are
+// compared with the expectation directly. Otherwise the compiled program is
+// run many times and every expectation becomes a hypothesis test at a fixed
+// significance level: an exact binomial test for a single value, Pearson's
+// chi-square test for a whole distribution.
+
+use crate::analysis::OutputDistribution;
+use crate::montecarlo::Summary;
+use std::fmt;
+
+/// Significance level of every hypothesis test; low, so that a correct
+/// program fails about once in a thousand checks
+pub const SIGNIFICANCE: f64 = 0.001;
+
+/// Runs per test file when its probabilities cannot be computed exactly
+pub const DEFAULT_RUNS: usize = 10_000;
+
+/// Slack for comparing probabilities that are exact up to rounding
+const EPSILON: f64 = 1e-12;
+
+// ===========================================================================
+// EXPECTATIONS - The `//!` Comments of a Test File
+// ===========================================================================
+
+/// An expected probability, as written in a test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Expected {
+    pub p: f64,
+    /// The `± t` part, if any; without it an exact probability must equal `p`,
+    /// so a repeating decimal is best written as a fraction such as `1/3`
+    pub tolerance: Option<f64>,
+}
+
+impl Expected {
+    /// How far an exact probability may be from `p`
+    fn allowance(self) -> f64 {
+        self.tolerance.unwrap_or(0.0) + EPSILON
+    }
+}
+
+/// What an expectation says about one `blah` statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Claim {
+    /// The statement prints the value (None: is never reached) this often
+    Prints(Option<String>, Expected),
+    /// The statement prints exactly these values, this often
+    Distribution(Vec<(Option<String>, Expected)>),
+}
+
+/// One `//!` line of a test file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expectation {
+    /// Line of the `blah` statement the expectation is about
+    pub line: usize,
+    pub claim: Claim,
+    /// The expectation as written, without the `//!`
+    pub text: String,
+}
+
+/// Every `//!` expectation in a test file, in source order
+pub fn parse_expectations(source: &str) -> Result<Vec<Expectation>, String> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let text = line.trim_start().strip_prefix("//!")?.trim();
+            Some(parse_expectation(text).map_err(|e| format!("line {}: {}", index + 1, e)))
+        })
+        .collect()
+}
+
+fn parse_expectation(text: &str) -> Result<Expectation, String> {
+    let invalid = || {
+        format!(
+            "invalid expectation '{}' (expected `line <n> prints <value> with p = <p>` \
+             or `line <n> distribution <value>: <p>, ...`)",
+            text
+        )
+    };
+    let mut cursor = Cursor { rest: text };
+    if !cursor.keyword("line") {
+        return Err(invalid());
+    }
+    let line = cursor
+        .word(&[])
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(invalid)?;
+
+    let claim = if cursor.keyword("prints") {
+        let value = cursor.value(&[]).ok_or_else(invalid)?;
+        if !(cursor.keyword("with") && cursor.symbol("p") && cursor.symbol("=")) {
+            return Err(invalid());
+        }
+        let mut expected = cursor.probability().ok_or_else(invalid)??;
+        if cursor.symbol("±") || cursor.symbol("+-") {
+            expected.tolerance = Some(cursor.probability().ok_or_else(invalid)??.p);
+        }
+        Claim::Prints(value, expected)
+    } else if cursor.keyword("distribution") {
+        let mut outcomes = Vec::new();
+        loop {
+            let value = cursor.value(&[':', ',']).ok_or_else(invalid)?;
+            if !cursor.symbol(":") {
+                return Err(invalid());
+            }
+            outcomes.push((value, cursor.probability().ok_or_else(invalid)??));
+            if !cursor.symbol(",") {
+                break;
+            }
+        }
+        // Checked as written, never scaled to add up to 1
+        let total: f64 = outcomes.iter().map(|(_, expected)| expected.p).sum();
+        if (total - 1.0).abs() > EPSILON {
+            return Err(format!(
+                "the probabilities of a distribution add up to {}, not 1",
+                // Without the noise of adding up decimals
+                (total * 1e9).round() / 1e9
+            ));
+        }
+        Claim::Distribution(outcomes)
+    } else {
+        return Err(invalid());
+    };
+
+    if !cursor.rest.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(Expectation {
+        line,
+        claim,
+        text: text.to_string(),
+    })
+}
+
+/// Reads an expectation a word at a time
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn symbol(&mut self, symbol: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(symbol) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let before = self.rest;
+        if self.word(&[]) == Some(keyword) {
+            return true;
+        }
+        self.rest = before;
+        false
+    }
+
+    /// The next run of non-space characters, stopping before any of `stops`
+    fn word(&mut self, stops: &[char]) -> Option<&'a str> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || stops.contains(&c))
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        (!word.is_empty()).then_some(word)
+    }
+
+    /// A printed value: a quoted string, a bare word, or `unreached` (None)
+    #[allow(clippy::option_option)]
+    fn value(&mut self, stops: &[char]) -> Option<Option<String>> {
+        self.rest = self.rest.trim_start();
+        let Some(quoted) = self.rest.strip_prefix('"') else {
+            let word = self.word(stops)?;
+            return Some((word != "unreached").then(|| word.to_string()));
+        };
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &quoted[i + 1..];
+                    return Some(Some(value));
+                },
+                '\\' => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    other => value.push(other),
+                },
+                _ => value.push(c),
+            }
+        }
+        None
+    }
+
+    /// A probability written as a decimal or a fraction; None when there is no
+    /// number, an error when it is not between 0 and 1
+    fn probability(&mut self) -> Option<Result<Expected, String>> {
+        let word = self.word(&[',', '±', '+'])?;
+        let p = match word.split_once('/') {
+            Some((num, den)) => {
+                let (num, den) = (num.parse::<u32>().ok()?, den.parse::<u32>().ok()?);
+                if den == 0 {
+                    return None;
+                }
+                f64::from(num) / f64::from(den)
+            },
+            None => word.parse().ok()?,
+        };
+        if !(0.0..=1.0).contains(&p) {
+            return Some(Err(format!(
+                "probability '{}' is not between 0 and 1",
+                word
+            )));
+        }
+        Some(Ok(Expected { p, tolerance: None }))
+    }
+}
+
+// ===========================================================================
+// OBSERVATIONS - What the Program Printed, Exactly or Over Many Runs
+// ===========================================================================
+
+/// Per `blah` statement: (source line, [(value or None if not reached, weight)])
+type Lines<T> = Vec<(usize, Vec<(Option<String>, T)>)>;
+
+/// The outputs an expectation is checked against
+#[derive(Debug, Clone, PartialEq)]
+pub enum Observations {
+    /// Exact probabilities, from enumerating every world
+    Exact(Lines<f64>),
+    /// Counts over `runs` runs of the compiled program
+    Sampled { runs: u64, lines: Lines<u64> },
+}
+
+impl Observations {
+    /// Exact distributions of a program whose statements start on `lines`
+    pub fn exact(outputs: &[OutputDistribution], lines: &[usize]) -> Self {
+        Observations::Exact(
+            outputs
+                .iter()
+                .map(|output| {
+                    let mut values: Vec<_> = output
+                        .outcomes
+                        .iter()
+                        .map(|(value, p)| (Some(value.to_string()), p.to_f64()))
+                        .collect();
+                    values.push((None, output.unreached.to_f64()));
+                    (lines[output.statement], values)
+                })
+                .collect(),
+        )
+    }
+
+    /// The counts of a Monte Carlo batch
+    pub fn sampled(summary: &Summary) -> Self {
+        Observations::Sampled {
+            runs: summary.runs,
+            lines: summary
+                .histograms
+                .iter()
+                .map(|h| {
+                    let rows = h.rows.iter().map(|r| (r.value.clone(), r.count)).collect();
+                    (h.line, rows)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whether an expectation held, and the numbers behind the answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed { "ok  " } else { "FAIL" };
+        write!(f, "{}  {}", status, self.detail)
+    }
+}
+
+/// Check one expectation against what the program printed
+pub fn check(expectation: &Expectation, observations: &Observations) -> Verdict {
+    let verdict = match observations {
+        Observations::Exact(lines) => {
+            find(lines, expectation.line).map(|values| check_exact(&expectation.claim, values))
+        },
+        Observations::Sampled { runs, lines } => find(lines, expectation.line)
+            .map(|values| check_sampled(&expectation.claim, values, *runs)),
+    };
+    let (passed, detail) =
+        verdict.unwrap_or_else(|| (false, format!("line {} has no blah", expectation.line)));
+    Verdict {
+        passed,
+        detail: format!("{}: {}", expectation.text, detail),
+    }
+}
+
+fn find<T>(lines: &Lines<T>, line: usize) -> Option<&[(Option<String>, T)]> {
+    lines
+        .iter()
+        .find(|(l, _)| *l == line)
+        .map(|(_, values)| values.as_slice())
+}
+
+fn weight<T: Copy + Default>(values: &[(Option<String>, T)], value: Option<&str>) -> T {
+    values
+        .iter()
+        .find(|(v, _)| v.as_deref() == value)
+        .map_or_else(T::default, |(_, w)| *w)
+}
+
+fn display_value(value: Option<&str>) -> String {
+    value.map_or_else(|| "unreached".to_string(), |v| format!("{:?}", v))
+}
+
+fn check_exact(claim: &Claim, values: &[(Option<String>, f64)]) -> (bool, String) {
+    match claim {
+        Claim::Prints(value, expected) => {
+            let p = weight(values, value.as_deref());
+            let passed = (p - expected.p).abs() <= expected.allowance();
+            (passed, format!("exact probability {:.6}", p))
+        },
+        Claim::Distribution(outcomes) => {
+            let mut problems = Vec::new();
+            for (value, expected) in outcomes {
+                let p = weight(values, value.as_deref());
+                if (p - expected.p).abs() > expected.allowance() {
+                    problems.push(format!(
+                        "{} has exact probability {:.6}, expected {}",
+                        display_value(value.as_deref()),
+                        p,
+                        expected.p
+                    ));
+                }
+            }
+            for (value, p) in values {
+                if *p > EPSILON && !outcomes.iter().any(|(v, _)| v == value) {
+                    problems.push(format!(
+                        "{} is missing, with exact probability {:.6}",
+                        display_value(value.as_deref()),
+                        p
+                    ));
+                }
+            }
+            if problems.is_empty() {
+                (true, "exact distribution matches".to_string())
+            } else {
+                (false, problems.join("; "))
+            }
+        },
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn check_sampled(claim: &Claim, values: &[(Option<String>, u64)], runs: u64) -> (bool, String) {
+    let frequency = |count: u64| count as f64 / runs.max(1) as f64;
+    match claim {
+        Claim::Prints(value, expected) => {
+            let count = weight(values, value.as_deref());
+            let observed = frequency(count);
+            let tolerance = expected.tolerance.unwrap_or(0.0);
+            let low = (expected.p - tolerance).max(0.0);
+            let high = (expected.p + tolerance).min(1.0);
+            // Test against the end of the expected range nearest the observation
+            let p_value = if (low..=high).contains(&observed) {
+                1.0
+            } else {
+                let nearest = if observed < low { low } else { high };
+                binomial_p_value(count, runs, nearest)
+            };
+            let passed = p_value >= SIGNIFICANCE;
+            (
+                passed,
+                format!(
+                    "observed {:.4} ({} of {} runs), expected {}, binomial p-value {:.4}{}",
+                    observed,
+                    count,
+                    runs,
+                    expected.p,
+                    p_value,
+                    if passed {
+                        String::new()
+                    } else {
+                        format!(" < {}", SIGNIFICANCE)
+                    }
+                ),
+            )
+        },
+        Claim::Distribution(outcomes) => {
+            let mut unexpected: Vec<String> = values
+                .iter()
+                .filter(|(value, count)| *count > 0 && !outcomes.iter().any(|(v, _)| v == value))
+                .map(|(value, count)| {
+                    format!("{} in {} runs", display_value(value.as_deref()), count)
+                })
+                .collect();
+
+            let mut statistic = 0.0;
+            let mut categories = 0;
+            let mut table = Vec::new();
+            for (value, expected) in outcomes {
+                let count = weight(values, value.as_deref());
+                let p = expected.p;
+                table.push(format!(
+                    "{} {:.4} vs {:.4}",
+                    display_value(value.as_deref()),
+                    frequency(count),
+                    p
+                ));
+                if p == 0.0 {
+                    if count > 0 {
+                        unexpected.push(format!(
+                            "{} in {} runs",
+                            display_value(value.as_deref()),
+                            count
+                        ));
+                    }
+                    continue;
+                }
+                let mean = p * runs as f64;
+                statistic += (count as f64 - mean).powi(2) / mean;
+                categories += 1;
+            }
+
+            if !unexpected.is_empty() {
+                return (
+                    false,
+                    format!("printed unexpected values: {}", unexpected.join(", ")),
+                );
+            }
+            let freedom = categories - 1;
+            let p_value = if freedom == 0 {
+                1.0
+            } else {
+                chi_square_p_value(statistic, freedom)
+            };
+            let passed = p_value >= SIGNIFICANCE;
+            (
+                passed,
+                format!(
+                    "observed vs expected frequency over {} runs: {}; chi-square {:.2} with {} \
+                     degree(s) of freedom, p-value {:.4}{}",
+                    runs,
+                    table.join(", "),
+                    statistic,
+                    freedom,
+                    p_value,
+                    if passed {
+                        String::new()
+                    } else {
+                        format!(" < {}", SIGNIFICANCE)
+                    }
+                ),
+            )
+        },
+    }
+}
+
+// ===========================================================================
+// STATISTICS - Binomial and Chi-Square Tests
+// ===========================================================================
+
+/// ln Γ(x) for x ≥ 1/2, by the Lanczos approximation (g = 7, n = 9)
+#[allow(clippy::suboptimal_flops)]
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        #[allow(clippy::cast_precision_loss)]
+        let i = i as f64;
+        sum += c / (x + i);
+    }
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Two-sided exact binomial test of `count` successes in `runs` trials
+/// against success probability `p`: the total probability of every count no
+/// more likely than the one observed
+#[allow(clippy::cast_precision_loss, clippy::suboptimal_flops)]
+pub fn binomial_p_value(count: u64, runs: u64, p: f64) -> f64 {
+    if p <= 0.0 {
+        return if count == 0 { 1.0 } else { 0.0 };
+    }
+    if p >= 1.0 {
+        return if count == runs { 1.0 } else { 0.0 };
+    }
+    let n = runs as f64;
+    let ln_pmf = |k: u64| {
+        let k = k as f64;
+        ln_gamma(n + 1.0) - ln_gamma(k + 1.0) - ln_gamma(n - k + 1.0)
+            + k * p.ln()
+            + (n - k) * (1.0 - p).ln()
+    };
+    // Relative slack so that counts exactly as likely as the observed one count
+    let observed = ln_pmf(count) + 1e-7;
+    (0..=runs)
+        .map(ln_pmf)
+        .filter(|&l| l <= observed)
+        .map(f64::exp)
+        .sum::<f64>()
+        .min(1.0)
+}
+
+/// Probability that a chi-square variable with `freedom` degrees of freedom
+/// is at least `statistic`
+pub fn chi_square_p_value(statistic: f64, freedom: u32) -> f64 {
+    upper_gamma(f64::from(freedom) / 2.0, statistic / 2.0)
+}
+
+/// Regularized upper incomplete gamma function Q(a, x), by its series below
+/// a + 1 and its continued fraction above
+#[allow(clippy::many_single_char_names, clippy::suboptimal_flops)]
+fn upper_gamma(a: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut ap) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        (1.0 - sum * scale).max(0.0)
+    } else {
+        // Modified Lentz's method
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let i = f64::from(i);
+            let an = -i * (i - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (scale * h).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prints(value: &str, p: f64, tolerance: Option<f64>) -> Expectation {
+        Expectation {
+            line: 3,
+            claim: Claim::Prints(Some(value.to_string()), Expected { p, tolerance }),
+            text: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_expectations() {
+        let source = "blah a + b\n//! line 1 prints 12 with p = 0.25 ± 0.02\n\
+                      // an ordinary comment\n//! line 1 prints \"x y\" with p=1/3\n\
+                      //! line 1 distribution null: 0.5, 12: 0.5, unreached: 0";
+        let expectations = parse_expectations(source).unwrap();
+        assert_eq!(expectations.len(), 3);
+        assert_eq!(
+            expectations[0].claim,
+            Claim::Prints(
+                Some("12".to_string()),
+                Expected {
+                    p: 0.25,
+                    tolerance: Some(0.02),
+                }
+            )
+        );
+        match &expectations[1].claim {
+            Claim::Prints(Some(value), expected) => {
+                assert_eq!(value, "x y");
+                assert!((expected.p - 1.0 / 3.0).abs() < EPSILON);
+            },
+            other => unreachable!("{:?}", other),
+        }
+        assert!(matches!(
+            &expectations[2].claim,
+            Claim::Distribution(outcomes) if outcomes.len() == 3 && outcomes[2].0.is_none()
+        ));
+
+        let error = parse_expectations("blah 1\n//! line 1 prints 1").unwrap_err();
+        assert!(error.starts_with("line 2: invalid expectation"));
+        let error = parse_expectations("//! line 1 prints 1 with p = 1.5").unwrap_err();
+        assert_eq!(error, "line 1: probability '1.5' is not between 0 and 1");
+        let error = parse_expectations("//! line 1 distribution 1: 0.4, 2: 0.2").unwrap_err();
+        assert_eq!(
+            error,
+            "line 1: the probabilities of a distribution add up to 0.6, not 1"
+        );
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // The degenerate cases are exactly 0 and 1
+    fn test_statistics() {
+        // Known values: 60 heads in 100 fair flips, and the 95% chi-square quantiles
+        assert!((binomial_p_value(60, 100, 0.5) - 0.0569).abs() < 1e-4);
+        assert!((binomial_p_value(50, 100, 0.5) - 1.0).abs() < 1e-9);
+        assert_eq!(binomial_p_value(1, 10, 0.0), 0.0);
+        assert_eq!(binomial_p_value(10, 10, 1.0), 1.0);
+        assert!((chi_square_p_value(3.841, 1) - 0.05).abs() < 1e-4);
+        assert!((chi_square_p_value(5.991, 2) - 0.05).abs() < 1e-4);
+        assert!((chi_square_p_value(0.5, 3) - 0.9189).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_exact_checks() {
+        let third = 1.0 / 3.0;
+        let observations = Observations::Exact(vec![(
+            3,
+            vec![
+                (Some("1".to_string()), third),
+                (Some("2".to_string()), 1.0 - third),
+            ],
+        )]);
+        let written = parse_expectations("//! line 3 prints 1 with p = 1/3").unwrap();
+        assert!(check(&written[0], &observations).passed);
+        assert!(!check(&prints("1", 0.333, None), &observations).passed);
+        assert!(check(&prints("1", 0.333, Some(0.001)), &observations).passed);
+        assert!(!check(&prints("1", 0.3, Some(0.03)), &observations).passed);
+
+        let written = parse_expectations("//! line 3 distribution 1: 1/3, 3: 2/3").unwrap();
+        let verdict = check(&written[0], &observations);
+        assert!(!verdict.passed);
+        assert!(verdict.detail.contains("\"2\" is missing"));
+        let verdict = check(&prints("1", 0.5, None), &Observations::Exact(Vec::new()));
+        assert!(verdict.detail.ends_with("line 3 has no blah"));
+    }
+
+    #[test]
+    fn test_sampled_checks() {
+        let observations = Observations::Sampled {
+            runs: 1000,
+            lines: vec![(
+                3,
+                vec![(Some("1".to_string()), 520), (Some("2".to_string()), 480)],
+            )],
+        };
+        assert!(check(&prints("1", 0.5, None), &observations).passed);
+        assert!(!check(&prints("1", 0.4, None), &observations).passed);
+        assert!(check(&prints("1", 0.4, Some(0.1)), &observations).passed);
+
+        let fair = parse_expectations("//! line 3 distribution 1: 0.5, 2: 0.5").unwrap();
+        assert!(check(&fair[0], &observations).passed);
+        let skewed = parse_expectations("//! line 3 distribution 1: 0.7, 2: 0.3").unwrap();
+        let verdict = check(&skewed[0], &observations);
+        assert!(!verdict.passed);
+        assert!(verdict.detail.contains("\"1\" 0.5200 vs 0.7000"));
+        let partial = parse_expectations("//! line 3 distribution 1: 1").unwrap();
+        assert!(!check(&partial[0], &observations).passed);
+    }
+}
@@ -0,0 +1,20 @@
+// Shared Test Fixtures
+//
+// The sample programs and the check-then-lower step that the IR, optimiser
+// and formatter tests all build on, kept here so that each has one copy.
+
+use crate::compiler::CompileOptions;
+use crate::driver::check_source;
+use crate::ir::{lower, IrProgram};
+
+/// The sample programs in src/, which are kept in canonical layout
+pub const SAMPLES: [&str; 3] = [
+    include_str!("dice.mist"),
+    include_str!("restaurant.mist"),
+    include_str!("weather.mist"),
+];
+
+/// The IR of a program that is expected to compile
+pub fn lower_source(source: &str, options: &CompileOptions) -> IrProgram {
+    lower(&check_source(source, options).unwrap(), options)
+}